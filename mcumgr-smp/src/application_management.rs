@@ -1,35 +1,42 @@
 // Author: Sascha Zenglein <zenglein@gessler.de>
 // Copyright (c) 2023 Gessler GmbH.
 
-use crate::{Group, OpCode, SmpFrame};
+use crate::smp::max_chunk_len;
+use crate::{Group, OpCode, SmpFrame, SmpRequest, SmpResponse};
 
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf; // CBOR byte string
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 pub enum ApplicationManagementCommand {
     State,
     Upload,
     Erase,
+    SlotInfo,
     Unknown(u8),
 }
 
-impl From<ApplicationManagementCommand> for u8 {
-    fn from(cmd: ApplicationManagementCommand) -> Self {
-        match cmd {
+impl ApplicationManagementCommand {
+    /// command id in the SMP header
+    pub const fn id(&self) -> u8 {
+        match self {
             ApplicationManagementCommand::State => 0,
             ApplicationManagementCommand::Upload => 1,
             ApplicationManagementCommand::Erase => 5,
-            ApplicationManagementCommand::Unknown(n) => n,
+            ApplicationManagementCommand::SlotInfo => 6,
+            ApplicationManagementCommand::Unknown(n) => *n,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(untagged)]
-pub enum GetImageStateResult {
-    Ok(GetImageStatePayload),
-    Err(GetImageStateError),
+impl From<ApplicationManagementCommand> for u8 {
+    fn from(cmd: ApplicationManagementCommand) -> Self {
+        cmd.id()
+    }
 }
 
+pub type GetImageStateResult = SmpResponse<GetImageStatePayload>;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GetImageStatePayload {
     pub images: Vec<ImageState>,
@@ -37,13 +44,6 @@ pub struct GetImageStatePayload {
     pub split_status: Option<i32>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct GetImageStateError {
-    pub rc: i32,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub rsn: Option<String>,
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ImageState {
     pub image: Option<i32>,
@@ -63,18 +63,51 @@ pub struct ImageState {
     pub permanent: bool,
 }
 
+impl GetImageStatePayload {
+    /// Slot holding the image with `hash`, on multi-image devices in any image
+    pub fn find_hash(&self, hash: &[u8]) -> Option<&ImageState> {
+        self.images
+            .iter()
+            .find(|state| state.hash.as_ref().is_some_and(|h| h.as_slice() == hash))
+    }
+
+    /// Slot `slot` of image `image`
+    pub fn find_slot(&self, image: i32, slot: i32) -> Option<&ImageState> {
+        self.images
+            .iter()
+            .find(|state| state.image_number() == image && state.slot == slot)
+    }
+
+    /// Slots grouped by image number
+    pub fn by_image(&self) -> BTreeMap<i32, Vec<&ImageState>> {
+        let mut images: BTreeMap<i32, Vec<&ImageState>> = BTreeMap::new();
+        for state in &self.images {
+            images.entry(state.image_number()).or_default().push(state);
+        }
+        images
+    }
+}
+
+impl ImageState {
+    /// Image number, e.g. the core on multi-core devices.
+    /// Devices with a single image may leave it out, it is 0 then.
+    pub fn image_number(&self) -> i32 {
+        self.image.unwrap_or(0)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GetStatePayload {}
 
+impl SmpRequest for GetStatePayload {
+    const GROUP: Group = Group::ApplicationManagement;
+    const COMMAND: u8 = ApplicationManagementCommand::State.id();
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = GetImageStatePayload;
+}
+
 pub fn get_state(sequence: u8) -> SmpFrame<GetStatePayload> {
-    SmpFrame {
-        operation: OpCode::ReadRequest,
-        flags: 0,
-        group: Group::ApplicationManagement,
-        sequence,
-        command: ApplicationManagementCommand::State.into(),
-        data: GetStatePayload {},
-    }
+    GetStatePayload {}.into_frame(sequence)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -84,6 +117,13 @@ pub struct SetConfirmState {
     pub confirm: bool,
 }
 
+impl SmpRequest for SetConfirmState {
+    const GROUP: Group = Group::ApplicationManagement;
+    const COMMAND: u8 = ApplicationManagementCommand::State.id();
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = GetImageStatePayload;
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SetPendingState {
     #[serde(with = "serde_bytes")]
@@ -91,30 +131,19 @@ pub struct SetPendingState {
     pub pending: bool,
 }
 
-pub fn set_confirm(hash: Vec<u8>, confirm: bool, sequence: u8) -> SmpFrame<SetConfirmState> {
-    let data = SetConfirmState { hash, confirm };
+impl SmpRequest for SetPendingState {
+    const GROUP: Group = Group::ApplicationManagement;
+    const COMMAND: u8 = ApplicationManagementCommand::State.id();
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = GetImageStatePayload;
+}
 
-    SmpFrame {
-        operation: OpCode::WriteRequest,
-        flags: 0,
-        group: Group::ApplicationManagement,
-        sequence,
-        command: ApplicationManagementCommand::State.into(),
-        data,
-    }
+pub fn set_confirm(hash: Vec<u8>, confirm: bool, sequence: u8) -> SmpFrame<SetConfirmState> {
+    SetConfirmState { hash, confirm }.into_frame(sequence)
 }
 
 pub fn set_pending(hash: Vec<u8>, pending: bool, sequence: u8) -> SmpFrame<SetPendingState> {
-    let data = SetPendingState { hash, pending };
-
-    SmpFrame {
-        operation: OpCode::WriteRequest,
-        flags: 0,
-        group: Group::ApplicationManagement,
-        sequence,
-        command: ApplicationManagementCommand::State.into(),
-        data,
-    }
+    SetPendingState { hash, pending }.into_frame(sequence)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -133,6 +162,21 @@ pub struct ImageChunk<'d, 's> {
     pub upgrade: Option<bool>,
 }
 
+impl SmpRequest for ImageChunk<'_, '_> {
+    const GROUP: Group = Group::ApplicationManagement;
+    const COMMAND: u8 = ApplicationManagementCommand::Upload.id();
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = WriteImageChunkPayload;
+}
+
+/// Upload hash of an image file, the SHA-256 of the whole file.
+///
+/// This is not the image hash TLV of an MCUboot image, which devices report for the slot.
+pub fn upload_sha(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+#[derive(Clone)]
 pub struct ImageWriter<'s> {
     pub image: Option<u8>,
     pub hash: Option<&'s [u8]>,
@@ -143,7 +187,12 @@ pub struct ImageWriter<'s> {
 }
 
 impl ImageWriter<'_> {
-    pub fn new(image: Option<u8>, len: usize, hash: Option<&'_ [u8]>, upgrade: bool) -> ImageWriter<'_> {
+    pub fn new(
+        image: Option<u8>,
+        len: usize,
+        hash: Option<&'_ [u8]>,
+        upgrade: bool,
+    ) -> ImageWriter<'_> {
         ImageWriter {
             image,
             hash,
@@ -186,23 +235,104 @@ impl ImageWriter<'_> {
 
         (self.sequence, _) = self.sequence.overflowing_add(1);
 
-        SmpFrame::new(
-            OpCode::WriteRequest,
-            self.sequence,
-            Group::ApplicationManagement,
-            ApplicationManagementCommand::Upload.into(),
-            chunk_data,
-        )
+        chunk_data.into_frame(self.sequence)
+    }
+
+    /// Empty first chunk carrying length and hash.
+    ///
+    /// A device that already holds part of an upload with the same hash answers with the
+    /// offset it has reached, so the upload can continue from there.
+    pub fn probe(&mut self) -> SmpFrame<ImageChunk<'static, '_>> {
+        self.offset = 0;
+        self.write_chunk(&[])
+    }
+
+    /// Largest amount of data a chunk at `offset` can carry without its encoded frame
+    /// exceeding `frame_size` bytes. The first chunk also carries length, hash and flags.
+    pub fn max_chunk_len(&self, offset: usize, frame_size: usize) -> usize {
+        let mut writer = self.clone();
+        writer.offset = offset;
+        max_chunk_len(frame_size, || writer.write_chunk(&[]).encode_with_cbor())
+    }
+}
+
+/// Erase a slot, the secondary slot if `slot` is not set
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct EraseRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u32>,
+}
+
+impl SmpRequest for EraseRequest {
+    const GROUP: Group = Group::ApplicationManagement;
+    const COMMAND: u8 = ApplicationManagementCommand::Erase.id();
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = EraseResponse;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EraseResponse {}
+
+pub fn erase(slot: Option<u32>, sequence: u8) -> SmpFrame<EraseRequest> {
+    EraseRequest { slot }.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SlotInfoRequest {}
+
+impl SmpRequest for SlotInfoRequest {
+    const GROUP: Group = Group::ApplicationManagement;
+    const COMMAND: u8 = ApplicationManagementCommand::SlotInfo.id();
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = SlotInfoResponse;
+}
+
+pub fn slot_info(sequence: u8) -> SmpFrame<SlotInfoRequest> {
+    SlotInfoRequest {}.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SlotInfoResponse {
+    pub images: Vec<ImageSlots>,
+}
+
+impl SlotInfoResponse {
+    /// Largest image that can be uploaded to image `image`.
+    /// Without `max_image_size` this is the size of the secondary slot.
+    pub fn max_upload_size(&self, image: u32) -> Option<u64> {
+        let slots = self.images.iter().find(|slots| slots.image == image)?;
+        slots.max_image_size.map(u64::from).or_else(|| {
+            slots
+                .slots
+                .iter()
+                .find(|slot| slot.slot == 1)
+                .map(|slot| slot.size)
+        })
     }
 }
 
+/// Slots of one image
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImageSlots {
+    pub image: u32,
+    pub slots: Vec<SlotInfo>,
+    /// largest image that fits, slot size minus the MCUboot trailer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_image_size: Option<u32>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(untagged)]
-pub enum WriteImageChunkResult {
-    Ok(WriteImageChunkPayload),
-    Err(WriteImageChunkError),
+pub struct SlotInfo {
+    pub slot: u32,
+    /// size of the slot in bytes
+    pub size: u64,
+    /// image number to upload to for writing this slot, if it differs from the image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_image_id: Option<u32>,
 }
 
+pub type WriteImageChunkResult = SmpResponse<WriteImageChunkPayload>;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WriteImageChunkPayload {
     pub off: u32,
@@ -211,36 +341,172 @@ pub struct WriteImageChunkPayload {
     pub match_: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct WriteImageChunkError {
-    pub rc: i32,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub rsn: Option<String>,
+pub fn get_state_response(sequence: u8, hash: String) -> SmpFrame<GetImageStatePayload> {
+    let hash_bytes = hex::decode(&hash).expect("hash string is not valid hex");
+
+    let bytebuf = ByteBuf::from(hash_bytes);
+
+    SmpFrame::new(
+        OpCode::ReadResponse,
+        sequence,
+        Group::ApplicationManagement,
+        0,
+        GetImageStatePayload {
+            images: vec![ImageState {
+                image: Some(0),
+                slot: 0,
+                version: "0".to_string(),
+                hash: Some(bytebuf),
+                bootable: true,
+                pending: false,
+                confirmed: true,
+                active: true,
+                permanent: true,
+            }],
+            split_status: None,
+        },
+    )
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcuboot_image::tests::build_image;
+    use crate::mcuboot_image::McubootImage;
 
-pub fn get_state_response(sequence: u8, hash: String ) -> SmpFrame<GetImageStatePayload> {
-    let hash_bytes = hex::decode(&hash)
-        .expect("hash string is not valid hex");
+    #[test]
+    fn test_upload_sha_of_signed_image() {
+        let data = build_image(&[0xaa; 100], &[], &[(0x24, &[2; 64])]);
+        let image_hash = McubootImage::parse(&data).unwrap().hash().unwrap();
 
-    let bytebuf = ByteBuf::from(hash_bytes);
-    
-    SmpFrame::new(OpCode::ReadResponse, sequence, Group::ApplicationManagement, 0, 
-    GetImageStatePayload { 
-                images: vec![
-                    ImageState { 
-                            image: Some(0), 
-                            slot: 0, 
-                            version: "0".to_string(), 
-                            hash: Some(bytebuf), 
-                            bootable: true, 
-                            pending: false, 
-                            confirmed: true, 
-                            active: true, 
-                            permanent: true
-                        }
-                    ],
-                split_status: None 
+        let sha = upload_sha(&data);
+        assert_eq!(sha.as_slice(), Sha256::digest(&data).as_slice());
+        assert_ne!(sha.as_slice(), image_hash);
+
+        let mut writer = ImageWriter::new(None, data.len(), Some(&sha), false);
+        let chunk = writer.write_chunk(&data[..32]);
+        assert_eq!(chunk.data.sha, Some(sha.as_slice()));
+    }
+
+    fn slot(image: Option<i32>, slot: i32, hash: u8) -> ImageState {
+        ImageState {
+            image,
+            slot,
+            version: "1.0.0".to_string(),
+            hash: Some(ByteBuf::from(vec![hash; 32])),
+            bootable: true,
+            pending: false,
+            confirmed: slot == 0,
+            active: slot == 0,
+            permanent: false,
+        }
+    }
+
+    #[test]
+    fn test_multi_image_state() {
+        let state = GetImageStatePayload {
+            images: vec![
+                slot(Some(0), 0, 0xa0),
+                slot(Some(0), 1, 0xa1),
+                slot(Some(1), 0, 0xb0),
+                slot(Some(1), 1, 0xb1),
+            ],
+            split_status: None,
+        };
+
+        let found = state.find_hash(&[0xb1; 32]).unwrap();
+        assert_eq!((found.image_number(), found.slot), (1, 1));
+        assert!(state.find_hash(&[0xcc; 32]).is_none());
+        assert_eq!(
+            state.find_slot(1, 0).unwrap().hash.as_deref().unwrap()[0],
+            0xb0
+        );
+
+        let images = state.by_image();
+        assert_eq!(images.keys().copied().collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(images[&1].len(), 2);
+
+        // single image devices leave out the image number
+        let single = GetImageStatePayload {
+            images: vec![slot(None, 0, 0xa0), slot(None, 1, 0xa1)],
+            split_status: None,
+        };
+        assert_eq!(single.find_slot(0, 1).unwrap().slot, 1);
+        assert_eq!(single.by_image().len(), 1);
+    }
+
+    #[test]
+    fn test_slot_info() {
+        use ciborium::Value;
+        fn map(entries: Vec<(&str, Value)>) -> Value {
+            Value::Map(entries.into_iter().map(|(k, v)| (k.into(), v)).collect())
+        }
+        fn slot(slot: u32, size: u64, upload_image_id: Option<u32>) -> Value {
+            let mut entries = vec![("slot", slot.into()), ("size", size.into())];
+            if let Some(id) = upload_image_id {
+                entries.push(("upload_image_id", id.into()));
             }
-    )
-}
\ No newline at end of file
+            map(entries)
+        }
+
+        // the network core of an nRF5340 reports no max_image_size
+        let value = map(vec![(
+            "images",
+            Value::Array(vec![
+                map(vec![
+                    ("image", 0.into()),
+                    (
+                        "slots",
+                        Value::Array(vec![slot(0, 0x60000, None), slot(1, 0x60000, Some(0))]),
+                    ),
+                    ("max_image_size", 0x5f000.into()),
+                ]),
+                map(vec![
+                    ("image", 1.into()),
+                    (
+                        "slots",
+                        Value::Array(vec![slot(0, 0x3a000, None), slot(1, 0x3a000, None)]),
+                    ),
+                ]),
+            ]),
+        )]);
+        let mut payload = Vec::new();
+        ciborium::into_writer(&value, &mut payload).unwrap();
+
+        let info: SlotInfoResponse = ciborium::from_reader(payload.as_slice()).unwrap();
+        assert_eq!(info.images[0].slots[1].upload_image_id, Some(0));
+        assert_eq!(info.max_upload_size(0), Some(0x5f000));
+        assert_eq!(info.max_upload_size(1), Some(0x3a000));
+        assert_eq!(info.max_upload_size(2), None);
+
+        let frame = erase(Some(1), 3);
+        assert_eq!(frame.command, 5);
+        assert_eq!(frame.operation, OpCode::WriteRequest);
+        assert_eq!(slot_info(4).command, 6);
+    }
+
+    #[test]
+    fn test_max_chunk_len() {
+        let hash = [0xab; 32];
+        let data = [0x55; 2048];
+        let writer = ImageWriter::new(Some(1), 100_000, Some(&hash), true);
+
+        for frame_size in [100, 128, 256, 300, 1024, 1232] {
+            for offset in [0, 10, 1000, 70_000] {
+                let len = writer.max_chunk_len(offset, frame_size);
+
+                let mut chunk = writer.clone();
+                chunk.offset = offset;
+                let fits = chunk.clone().write_chunk(&data[..len]).encode_with_cbor();
+                let too_big = chunk.write_chunk(&data[..len + 1]).encode_with_cbor();
+                assert!(fits.len() <= frame_size);
+                assert!(too_big.len() > frame_size);
+            }
+        }
+
+        // the first chunk carries the metadata
+        assert!(writer.max_chunk_len(0, 256) < writer.max_chunk_len(256, 256));
+        // no room for data
+        assert_eq!(writer.max_chunk_len(0, 40), 0);
+    }
+}
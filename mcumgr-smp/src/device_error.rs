@@ -0,0 +1,346 @@
+// Author: Sascha Zenglein <zenglein@gessler.de>
+// Copyright (c) 2023 Gessler GmbH.
+
+//! Error codes returned by devices.
+//!
+//! SMP version 1 devices return a generic [ReturnCode] in the `rc` field.
+//! Version 2 devices return group specific codes in `{"err": {"group": g, "rc": rc}}`.
+//! Both are mapped to a [DeviceError], which keeps the group and an optional `rsn` string.
+
+use crate::Group;
+use std::fmt;
+
+macro_rules! error_codes {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $($code:literal => $variant:ident: $msg:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $(
+                #[error($msg)]
+                $variant,
+            )*
+            #[error("unknown error code {0}")]
+            Other(i32),
+        }
+
+        impl From<i32> for $name {
+            fn from(code: i32) -> Self {
+                match code {
+                    $($code => Self::$variant,)*
+                    code => Self::Other(code),
+                }
+            }
+        }
+
+        impl From<$name> for i32 {
+            fn from(code: $name) -> Self {
+                match code {
+                    $($name::$variant => $code,)*
+                    $name::Other(code) => code,
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
+    /// Generic `MGMT_ERR_*` codes, used by all groups in SMP version 1
+    pub enum ReturnCode {
+        0 => Ok: "no error",
+        1 => Unknown: "unknown error",
+        2 => OutOfMemory: "insufficient memory",
+        3 => InvalidValue: "invalid value in request",
+        4 => Timeout: "operation timed out",
+        5 => NoEntry: "no such file or entry",
+        6 => BadState: "current state disallows command",
+        7 => MessageTooLarge: "response too large",
+        8 => NotSupported: "command not supported",
+        9 => Corrupt: "corrupt data",
+        10 => Busy: "device is busy",
+        11 => AccessDenied: "access denied",
+        12 => ProtocolTooOld: "requested SMP version is too old",
+        13 => ProtocolTooNew: "requested SMP version is too new",
+        256 => UserDefined: "user defined error",
+    }
+}
+
+error_codes! {
+    /// `OS_MGMT_ERR_*` codes of the os group (0)
+    pub enum OsMgmtError {
+        0 => Ok: "no error",
+        1 => Unknown: "unknown error",
+        2 => InvalidFormat: "invalid format",
+        3 => QueryYieldsNoAnswer: "query not recognized",
+        4 => RtcNotSet: "RTC not set",
+        5 => RtcCommandFailed: "RTC command failed",
+        6 => QueryResponseValueNotValid: "query response value not valid",
+    }
+}
+
+error_codes! {
+    /// `IMG_MGMT_ERR_*` codes of the image group (1)
+    pub enum ImgMgmtError {
+        0 => Ok: "no error",
+        1 => Unknown: "unknown error",
+        2 => FlashConfigQueryFail: "failed to query flash area configuration",
+        3 => NoImage: "no image in slot",
+        4 => NoTlvs: "image has no TLVs",
+        5 => InvalidTlv: "invalid TLV in image",
+        6 => TlvMultipleHashesFound: "multiple hash TLVs found",
+        7 => TlvInvalidSize: "invalid TLV size",
+        8 => HashNotFound: "image hash not found",
+        9 => NoFreeSlot: "no free slot",
+        10 => FlashOpenFailed: "flash area open failed",
+        11 => FlashReadFailed: "flash read failed",
+        12 => FlashWriteFailed: "flash write failed",
+        13 => FlashEraseFailed: "flash erase failed",
+        14 => InvalidSlot: "invalid slot",
+        15 => NoFreeMemory: "insufficient heap memory",
+        16 => FlashContextAlreadySet: "flash context already set",
+        17 => FlashContextNotSet: "flash context not set",
+        18 => FlashAreaDeviceNull: "flash area device is NULL",
+        19 => InvalidPageOffset: "invalid page offset",
+        20 => InvalidOffset: "invalid offset",
+        21 => InvalidLength: "invalid length",
+        22 => InvalidImageHeader: "invalid image header",
+        23 => InvalidImageHeaderMagic: "invalid image header magic",
+        24 => InvalidHash: "invalid hash",
+        25 => InvalidFlashAddress: "invalid flash address",
+        26 => VersionGetFailed: "failed to get version",
+        27 => CurrentVersionIsNewer: "current image version is newer",
+        28 => ImageAlreadyPending: "an image is already pending",
+        29 => InvalidImageVectorTable: "invalid image vector table",
+        30 => InvalidImageTooLarge: "image too large for slot",
+        31 => InvalidImageDataOverrun: "image data overrun",
+        32 => ImageConfirmationDenied: "image confirmation denied",
+        33 => ImageSettingTestToActiveDenied: "setting test on the active image denied",
+        34 => ActiveSlotNotKnown: "active slot not known",
+    }
+}
+
+error_codes! {
+    /// `STAT_MGMT_ERR_*` codes of the statistics group (2)
+    pub enum StatMgmtError {
+        0 => Ok: "no error",
+        1 => Unknown: "unknown error",
+        2 => InvalidGroup: "statistic group not found",
+        3 => InvalidStatName: "statistic name not found",
+        4 => InvalidStatSize: "invalid statistic size",
+        5 => WalkAborted: "walking statistics aborted",
+    }
+}
+
+error_codes! {
+    /// `SETTINGS_MGMT_ERR_*` codes of the settings group (3)
+    pub enum SettingsMgmtError {
+        0 => Ok: "no error",
+        1 => Unknown: "unknown error",
+        2 => KeyTooLong: "key too long",
+        3 => KeyNotFound: "key not found",
+        4 => ReadNotSupported: "read not supported",
+        5 => RootKeyNotFound: "root key not found",
+        6 => WriteNotSupported: "write not supported",
+        7 => DeleteNotSupported: "delete not supported",
+        8 => SaveFailed: "save failed",
+    }
+}
+
+error_codes! {
+    /// `FS_MGMT_ERR_*` codes of the file system group (8)
+    pub enum FsMgmtError {
+        0 => Ok: "no error",
+        1 => Unknown: "unknown error",
+        2 => FileInvalidName: "invalid file name",
+        3 => FileNotFound: "file not found",
+        4 => FileIsDirectory: "path is a directory",
+        5 => FileOpenFailed: "file open failed",
+        6 => FileSeekFailed: "file seek failed",
+        7 => FileReadFailed: "file read failed",
+        8 => FileTruncateFailed: "file truncate failed",
+        9 => FileDeleteFailed: "file delete failed",
+        10 => FileWriteFailed: "file write failed",
+        11 => FileOffsetNotValid: "invalid file offset",
+        12 => FileOffsetLargerThanFile: "offset larger than file",
+        13 => ChecksumHashNotFound: "checksum or hash type not found",
+        14 => MountPointNotFound: "mount point not found",
+        15 => ReadOnlyFilesystem: "read only file system",
+        16 => FileEmpty: "file is empty",
+    }
+}
+
+error_codes! {
+    /// `SHELL_MGMT_ERR_*` codes of the shell group (9)
+    pub enum ShellMgmtError {
+        0 => Ok: "no error",
+        1 => Unknown: "unknown error",
+        2 => CommandTooLong: "command too long",
+        3 => EmptyCommand: "empty command",
+    }
+}
+
+error_codes! {
+    /// `ZEPHYRBASIC_MGMT_ERR_*` codes of the Zephyr basic group (63)
+    pub enum ZephyrBasicMgmtError {
+        0 => Ok: "no error",
+        1 => Unknown: "unknown error",
+        2 => FlashOpenFailed: "flash area open failed",
+        3 => FlashConfigQueryFail: "failed to query flash area configuration",
+        4 => FlashEraseFailed: "flash erase failed",
+    }
+}
+
+/// An error code returned by a device, typed by the group that returned it
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceErrorCode {
+    /// SMP version 1 `rc`
+    #[error(transparent)]
+    Mgmt(#[from] ReturnCode),
+    #[error(transparent)]
+    Os(#[from] OsMgmtError),
+    #[error(transparent)]
+    Image(#[from] ImgMgmtError),
+    #[error(transparent)]
+    Stat(#[from] StatMgmtError),
+    #[error(transparent)]
+    Settings(#[from] SettingsMgmtError),
+    #[error(transparent)]
+    Fs(#[from] FsMgmtError),
+    #[error(transparent)]
+    Shell(#[from] ShellMgmtError),
+    #[error(transparent)]
+    ZephyrBasic(#[from] ZephyrBasicMgmtError),
+    /// group specific code of a group without known error codes
+    #[error("error code {0}")]
+    Other(i32),
+}
+
+impl DeviceErrorCode {
+    /// Type a group specific (SMP version 2) error code
+    pub fn from_group_rc(group: Group, rc: i32) -> Self {
+        match u16::from(group) {
+            0 => OsMgmtError::from(rc).into(),
+            1 => ImgMgmtError::from(rc).into(),
+            2 => StatMgmtError::from(rc).into(),
+            3 => SettingsMgmtError::from(rc).into(),
+            8 => FsMgmtError::from(rc).into(),
+            9 => ShellMgmtError::from(rc).into(),
+            63 => ZephyrBasicMgmtError::from(rc).into(),
+            _ => Self::Other(rc),
+        }
+    }
+
+    /// The raw error code
+    pub fn rc(&self) -> i32 {
+        match *self {
+            Self::Mgmt(code) => code.into(),
+            Self::Os(code) => code.into(),
+            Self::Image(code) => code.into(),
+            Self::Stat(code) => code.into(),
+            Self::Settings(code) => code.into(),
+            Self::Fs(code) => code.into(),
+            Self::Shell(code) => code.into(),
+            Self::ZephyrBasic(code) => code.into(),
+            Self::Other(code) => code,
+        }
+    }
+}
+
+/// Error response of a device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceError {
+    /// group that handled the request
+    pub group: Group,
+    pub code: DeviceErrorCode,
+    /// optional human readable reason
+    pub rsn: Option<String>,
+}
+
+impl DeviceError {
+    /// SMP version 1 error: `{"rc": rc, "rsn": ...}`
+    pub fn from_rc(group: Group, rc: i32, rsn: Option<String>) -> Self {
+        Self {
+            group,
+            code: ReturnCode::from(rc).into(),
+            rsn,
+        }
+    }
+
+    /// SMP version 2 error: `{"err": {"group": group, "rc": rc}}`
+    pub fn from_group_rc(group: Group, rc: i32, rsn: Option<String>) -> Self {
+        Self {
+            group,
+            code: DeviceErrorCode::from_group_rc(group, rc),
+            rsn,
+        }
+    }
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "device returned {:?} error {}: {}",
+            self.group,
+            self.code.rc(),
+            self.code
+        )?;
+        if let Some(rsn) = &self.rsn {
+            write!(f, " ({rsn})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+#[cfg(feature = "payload-cbor")]
+impl From<crate::SmpGroupError> for DeviceError {
+    fn from(err: crate::SmpGroupError) -> Self {
+        Self::from_group_rc(err.group.into(), err.rc, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_roundtrip() {
+        assert_eq!(ReturnCode::from(8), ReturnCode::NotSupported);
+        assert_eq!(i32::from(ReturnCode::UserDefined), 256);
+        assert_eq!(ImgMgmtError::from(30), ImgMgmtError::InvalidImageTooLarge);
+        assert_eq!(FsMgmtError::from(99), FsMgmtError::Other(99));
+        assert_eq!(i32::from(FsMgmtError::Other(99)), 99);
+    }
+
+    #[test]
+    fn test_group_specific_codes() {
+        let err = DeviceError::from_group_rc(Group::FileManagement, 3, None);
+        assert_eq!(err.code, DeviceErrorCode::Fs(FsMgmtError::FileNotFound));
+        assert_eq!(err.code.rc(), 3);
+
+        let err = DeviceError::from_group_rc(Group::Custom(64), 3, None);
+        assert_eq!(err.code, DeviceErrorCode::Other(3));
+    }
+
+    #[test]
+    fn test_display() {
+        let err =
+            DeviceError::from_group_rc(Group::ApplicationManagement, 23, Some("bad header".into()));
+        assert_eq!(
+            err.to_string(),
+            "device returned ApplicationManagement error 23: invalid image header magic (bad header)"
+        );
+
+        let err = DeviceError::from_rc(Group::Default, 8, None);
+        assert_eq!(
+            err.to_string(),
+            "device returned Default error 8: command not supported"
+        );
+    }
+}
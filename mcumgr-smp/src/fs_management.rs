@@ -0,0 +1,329 @@
+// Author: Sascha Zenglein <zenglein@gessler.de>
+// Copyright (c) 2023 Gessler GmbH.
+
+use crate::smp::max_chunk_len;
+use crate::{Group, OpCode, SmpFrame, SmpRequest, SmpResponse};
+
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf; // CBOR byte string
+use std::collections::BTreeMap;
+
+/// Commands of the file management group
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsManagementCommand {
+    /// file download (read) or upload (write)
+    File = 0,
+    Status = 1,
+    HashChecksum = 2,
+    SupportedHashChecksum = 3,
+    Close = 4,
+}
+
+impl From<FsManagementCommand> for u8 {
+    fn from(cmd: FsManagementCommand) -> Self {
+        cmd as u8
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileUploadChunk<'n, 'd> {
+    pub name: &'n str,
+    #[serde(with = "serde_bytes")]
+    pub data: &'d [u8],
+    pub off: usize,
+    /// total length, only sent with the first chunk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub len: Option<usize>,
+}
+
+impl SmpRequest for FileUploadChunk<'_, '_> {
+    const GROUP: Group = Group::FileManagement;
+    const COMMAND: u8 = FsManagementCommand::File as u8;
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = FileUploadPayload;
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FileUploadPayload {
+    pub off: u32,
+}
+
+pub type FileUploadResult = SmpResponse<FileUploadPayload>;
+
+/// Splits a file into upload requests, see [ImageWriter](crate::application_management::ImageWriter).
+#[derive(Debug, Clone)]
+pub struct FileWriter<'n> {
+    pub name: &'n str,
+    pub offset: usize,
+    pub len: usize,
+    pub sequence: u8,
+}
+
+impl<'n> FileWriter<'n> {
+    pub fn new(name: &'n str, len: usize) -> FileWriter<'n> {
+        FileWriter {
+            name,
+            offset: 0,
+            len,
+            sequence: 0,
+        }
+    }
+
+    pub fn write_chunk<'d>(&mut self, data: &'d [u8]) -> SmpFrame<FileUploadChunk<'n, 'd>> {
+        let chunk = FileUploadChunk {
+            name: self.name,
+            data,
+            off: self.offset,
+            len: (self.offset == 0).then_some(self.len),
+        };
+
+        self.offset += data.len();
+        (self.sequence, _) = self.sequence.overflowing_add(1);
+
+        chunk.into_frame(self.sequence)
+    }
+
+    /// Largest amount of data a chunk at `offset` can carry without its encoded frame
+    /// exceeding `frame_size` bytes. The first chunk also carries the file length.
+    pub fn max_chunk_len(&self, offset: usize, frame_size: usize) -> usize {
+        let mut writer = self.clone();
+        writer.offset = offset;
+        max_chunk_len(frame_size, || writer.write_chunk(&[]).encode_with_cbor())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileDownloadRequest {
+    pub name: String,
+    pub off: usize,
+}
+
+impl SmpRequest for FileDownloadRequest {
+    const GROUP: Group = Group::FileManagement;
+    const COMMAND: u8 = FsManagementCommand::File as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = FileDownloadPayload;
+}
+
+/// Read a chunk of a file starting at `offset`.
+/// The device decides how many bytes are returned.
+pub fn download(sequence: u8, name: String, offset: usize) -> SmpFrame<FileDownloadRequest> {
+    FileDownloadRequest { name, off: offset }.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FileDownloadPayload {
+    pub off: u32,
+    pub data: ByteBuf,
+    /// total length of the file, only sent in response to offset 0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub len: Option<u32>,
+}
+
+pub type FileDownloadResult = SmpResponse<FileDownloadPayload>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileStatusRequest {
+    pub name: String,
+}
+
+impl SmpRequest for FileStatusRequest {
+    const GROUP: Group = Group::FileManagement;
+    const COMMAND: u8 = FsManagementCommand::Status as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = FileStatusPayload;
+}
+
+pub fn status(sequence: u8, name: String) -> SmpFrame<FileStatusRequest> {
+    FileStatusRequest { name }.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FileStatusPayload {
+    pub len: u32,
+}
+
+pub type FileStatusResult = SmpResponse<FileStatusPayload>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileHashRequest {
+    pub name: String,
+    /// hash or checksum type, e.g. "sha256" or "crc32". The device default is used if not set.
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub off: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub len: Option<u32>,
+}
+
+impl SmpRequest for FileHashRequest {
+    const GROUP: Group = Group::FileManagement;
+    const COMMAND: u8 = FsManagementCommand::HashChecksum as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = FileHashPayload;
+}
+
+pub fn hash(sequence: u8, name: String, type_: Option<String>) -> SmpFrame<FileHashRequest> {
+    FileHashRequest {
+        name,
+        type_,
+        off: None,
+        len: None,
+    }
+    .into_frame(sequence)
+}
+
+/// Hashes are returned as byte strings, checksums as integers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum HashOutput {
+    Checksum(u64),
+    Hash(ByteBuf),
+}
+
+impl std::fmt::Display for HashOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HashOutput::Checksum(sum) => write!(f, "{:08x}", sum),
+            HashOutput::Hash(hash) => hash.iter().try_for_each(|b| write!(f, "{:02x}", b)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FileHashPayload {
+    #[serde(rename = "type")]
+    pub type_: String,
+    #[serde(default)]
+    pub off: u32,
+    pub len: u32,
+    pub output: HashOutput,
+}
+
+pub type FileHashResult = SmpResponse<FileHashPayload>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SupportedHashRequest {}
+
+impl SmpRequest for SupportedHashRequest {
+    const GROUP: Group = Group::FileManagement;
+    const COMMAND: u8 = FsManagementCommand::SupportedHashChecksum as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = SupportedHashPayload;
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SupportedHashType {
+    /// 0 for numerical checksums, 1 for byte array hashes
+    pub format: u8,
+    /// output size in bytes
+    pub size: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SupportedHashPayload {
+    pub types: BTreeMap<String, SupportedHashType>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileCloseRequest {}
+
+impl SmpRequest for FileCloseRequest {
+    const GROUP: Group = Group::FileManagement;
+    const COMMAND: u8 = FsManagementCommand::Close as u8;
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = FileClosePayload;
+}
+
+/// Close all files that were left open by an upload or download
+pub fn close(sequence: u8) -> SmpFrame<FileCloseRequest> {
+    FileCloseRequest {}.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FileClosePayload {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writer_sends_len_with_first_chunk() {
+        let mut writer = FileWriter::new("/lfs/cal.bin", 6);
+
+        let first = writer.write_chunk(&[1, 2, 3]);
+        assert_eq!((first.data.off, first.data.len), (0, Some(6)));
+        assert_eq!(first.command, 0);
+
+        let second = writer.write_chunk(&[4, 5, 6]);
+        assert_eq!((second.data.off, second.data.len), (3, None));
+        assert_eq!(second.sequence, first.sequence + 1);
+    }
+
+    #[test]
+    fn test_max_chunk_len() {
+        let data = [0x55; 2048];
+        let writer = FileWriter::new("/lfs/calibration.bin", 100_000);
+
+        for frame_size in [128, 256, 1024] {
+            for offset in [0, 10, 1000, 70_000] {
+                let len = writer.max_chunk_len(offset, frame_size);
+
+                let mut chunk = writer.clone();
+                chunk.offset = offset;
+                let fits = chunk.clone().write_chunk(&data[..len]).encode_with_cbor();
+                let too_big = chunk.write_chunk(&data[..len + 1]).encode_with_cbor();
+                assert!(fits.len() <= frame_size);
+                assert!(too_big.len() > frame_size);
+            }
+        }
+
+        // the name alone doesn't fit
+        assert_eq!(writer.max_chunk_len(0, 30), 0);
+    }
+
+    #[test]
+    fn test_hash_output() {
+        #[derive(Serialize)]
+        struct Resp<O> {
+            #[serde(rename = "type")]
+            type_: &'static str,
+            len: u32,
+            output: O,
+        }
+
+        let frame = SmpFrame::new(
+            OpCode::ReadResponse,
+            0,
+            Group::FileManagement,
+            2,
+            Resp {
+                type_: "crc32",
+                len: 4,
+                output: 0x1234abcdu32,
+            },
+        );
+        let decoded =
+            SmpFrame::<FileHashResult>::decode_with_cbor(&frame.encode_with_cbor()).unwrap();
+        let payload = decoded.into_result().unwrap();
+        assert_eq!(payload.output, HashOutput::Checksum(0x1234abcd));
+        assert_eq!(payload.output.to_string(), "1234abcd");
+
+        let frame = SmpFrame::new(
+            OpCode::ReadResponse,
+            0,
+            Group::FileManagement,
+            2,
+            Resp {
+                type_: "sha256",
+                len: 4,
+                output: serde_bytes::Bytes::new(&[0xde, 0xad]),
+            },
+        );
+        let decoded =
+            SmpFrame::<FileHashResult>::decode_with_cbor(&frame.encode_with_cbor()).unwrap();
+        assert_eq!(decoded.into_result().unwrap().output.to_string(), "dead");
+    }
+}
@@ -0,0 +1,328 @@
+// Author: Sascha Zenglein <zenglein@gessler.de>
+// Copyright (c) 2023 Gessler GmbH.
+
+use crate::mcuboot_image::{ImageError, McubootImage, TlvKind};
+
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePublicKey};
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey, EncodeRsaPublicKey};
+use rsa::traits::PublicKeyParts;
+use sha2::{Digest, Sha256};
+
+#[derive(thiserror::Error, Debug)]
+pub enum SignatureError {
+    #[error(transparent)]
+    Image(#[from] ImageError),
+    #[error("cannot read key: {0}")]
+    Key(String),
+    #[error("image is not signed")]
+    Unsigned,
+    #[error("{0:?} signatures are not supported")]
+    UnsupportedSignature(TlvKind),
+    #[error("image is signed with {image:?}, but the key is {key}")]
+    AlgorithmMismatch { image: TlvKind, key: &'static str },
+    #[error(
+        "image was signed with a different key: image key hash {image_key}, given key hash {key}"
+    )]
+    KeyMismatch { image_key: String, key: String },
+    #[error("image hash does not match the image content")]
+    HashMismatch,
+    #[error("{algorithm} signature is not valid for key {key}")]
+    Invalid {
+        algorithm: &'static str,
+        key: String,
+    },
+}
+
+/// Public key of an image signing key
+#[derive(Debug, Clone)]
+pub enum PublicKey {
+    Ed25519(ed25519_dalek::VerifyingKey),
+    EcdsaP256(p256::ecdsa::VerifyingKey),
+    Rsa(rsa::RsaPublicKey),
+}
+
+impl PublicKey {
+    /// Read a PEM encoded public key, or the public part of a private key as generated by `imgtool keygen`
+    pub fn from_pem(pem: &str) -> Result<PublicKey, SignatureError> {
+        let pem = pem::parse(pem).map_err(|e| SignatureError::Key(e.to_string()))?;
+        let der = pem.contents();
+
+        let key = match pem.tag() {
+            "PUBLIC KEY" => ed25519_dalek::VerifyingKey::from_public_key_der(der)
+                .map(PublicKey::Ed25519)
+                .ok()
+                .or_else(|| {
+                    p256::ecdsa::VerifyingKey::from_public_key_der(der)
+                        .map(PublicKey::EcdsaP256)
+                        .ok()
+                })
+                .or_else(|| {
+                    rsa::RsaPublicKey::from_public_key_der(der)
+                        .map(PublicKey::Rsa)
+                        .ok()
+                }),
+            "RSA PUBLIC KEY" => rsa::RsaPublicKey::from_pkcs1_der(der)
+                .map(PublicKey::Rsa)
+                .ok(),
+            "PRIVATE KEY" => ed25519_dalek::SigningKey::from_pkcs8_der(der)
+                .map(|key| PublicKey::Ed25519(key.verifying_key()))
+                .ok()
+                .or_else(|| {
+                    p256::SecretKey::from_pkcs8_der(der)
+                        .map(|key| PublicKey::EcdsaP256(key.public_key().into()))
+                        .ok()
+                })
+                .or_else(|| {
+                    rsa::RsaPrivateKey::from_pkcs8_der(der)
+                        .map(|key| PublicKey::Rsa(key.to_public_key()))
+                        .ok()
+                }),
+            "EC PRIVATE KEY" => p256::SecretKey::from_sec1_der(der)
+                .map(|key| PublicKey::EcdsaP256(key.public_key().into()))
+                .ok(),
+            "RSA PRIVATE KEY" => rsa::RsaPrivateKey::from_pkcs1_der(der)
+                .map(|key| PublicKey::Rsa(key.to_public_key()))
+                .ok(),
+            tag => return Err(SignatureError::Key(format!("unsupported PEM type {}", tag))),
+        };
+
+        key.ok_or_else(|| {
+            SignatureError::Key("not an ED25519, ECDSA-P256, RSA-2048 or RSA-3072 key".to_string())
+        })
+    }
+
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            PublicKey::Ed25519(_) => "ED25519",
+            PublicKey::EcdsaP256(_) => "ECDSA-P256",
+            PublicKey::Rsa(key) if key.size() == 384 => "RSA-3072",
+            PublicKey::Rsa(_) => "RSA-2048",
+        }
+    }
+
+    /// The key hash as stored in the key hash TLV by imgtool
+    pub fn key_hash(&self) -> [u8; 32] {
+        const ENCODE: &str = "public keys can be encoded";
+        match self {
+            PublicKey::Ed25519(key) => Sha256::digest(key.to_public_key_der().expect(ENCODE)),
+            PublicKey::EcdsaP256(key) => Sha256::digest(key.to_public_key_der().expect(ENCODE)),
+            // imgtool hashes RSA keys in PKCS#1 format
+            PublicKey::Rsa(key) => Sha256::digest(key.to_pkcs1_der().expect(ENCODE)),
+        }
+        .into()
+    }
+
+    fn verify(&self, kind: TlvKind, hash: &[u8], sig: &[u8]) -> Result<(), SignatureError> {
+        let invalid = || SignatureError::Invalid {
+            algorithm: self.algorithm(),
+            key: hex::encode(self.key_hash()),
+        };
+        let mismatch = || SignatureError::AlgorithmMismatch {
+            image: kind,
+            key: self.algorithm(),
+        };
+
+        match (self, kind) {
+            // MCUboot signs the image hash, not the image
+            (PublicKey::Ed25519(key), TlvKind::Ed25519) => {
+                let sig = ed25519_dalek::Signature::from_slice(sig).map_err(|_| invalid())?;
+                key.verify_strict(hash, &sig).map_err(|_| invalid())
+            }
+            (PublicKey::EcdsaP256(key), TlvKind::EcdsaSig) => {
+                let sig = p256::ecdsa::Signature::from_der(trim_der(sig)).map_err(|_| invalid())?;
+                key.verify_prehash(hash, &sig).map_err(|_| invalid())
+            }
+            (PublicKey::Rsa(key), TlvKind::Rsa2048Pss | TlvKind::Rsa3072Pss) => {
+                let expected_size = if kind == TlvKind::Rsa3072Pss {
+                    384
+                } else {
+                    256
+                };
+                if key.size() != expected_size {
+                    return Err(mismatch());
+                }
+                key.verify(rsa::Pss::new::<Sha256>(), hash, sig)
+                    .map_err(|_| invalid())
+            }
+            (
+                _,
+                TlvKind::Ed25519 | TlvKind::EcdsaSig | TlvKind::Rsa2048Pss | TlvKind::Rsa3072Pss,
+            ) => Err(mismatch()),
+            (_, kind) => Err(SignatureError::UnsupportedSignature(kind)),
+        }
+    }
+}
+
+/// Older imgtool versions pad ECDSA signatures with zeros, keep only the DER sequence
+fn trim_der(sig: &[u8]) -> &[u8] {
+    match sig {
+        [0x30, len, ..] if (*len as usize) + 2 <= sig.len() => &sig[..*len as usize + 2],
+        _ => sig,
+    }
+}
+
+impl McubootImage<'_> {
+    /// Check the image hash and the signature against `key`.
+    ///
+    /// If the image contains a key hash, it is compared first,
+    /// so a wrong key is reported as [SignatureError::KeyMismatch].
+    pub fn verify_signature(&self, key: &PublicKey) -> Result<(), SignatureError> {
+        let signature = self.signature().ok_or(SignatureError::Unsigned)?;
+
+        if !self.verify_hash()? {
+            return Err(SignatureError::HashMismatch);
+        }
+
+        if let Some(image_key) = self.key_hash() {
+            let key_hash = key.key_hash();
+            if image_key != key_hash {
+                return Err(SignatureError::KeyMismatch {
+                    image_key: hex::encode(image_key),
+                    key: hex::encode(key_hash),
+                });
+            }
+        }
+
+        key.verify(signature.kind, self.hash()?, signature.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcuboot_image::tests::build_image;
+    use ed25519_dalek::Signer;
+    use p256::ecdsa::signature::hazmat::PrehashSigner;
+    use rsa::pkcs8::EncodePrivateKey;
+
+    /// generated with `openssl genpkey -algorithm RSA -pkeyopt rsa_keygen_bits:2048`
+    const RSA_2048_KEY: &str = include_str!("../testdata/rsa-2048.pem");
+
+    /// deterministic RNG for the PSS salt
+    struct XorShift(u64);
+
+    impl rsa::rand_core::RngCore for XorShift {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.iter_mut().for_each(|b| *b = self.next_u64() as u8);
+        }
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rsa::rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl rsa::rand_core::CryptoRng for XorShift {}
+
+    /// build an image signed by `sign`, like `imgtool sign` does
+    fn signed_image(key: &PublicKey, kind: u16, sign: impl Fn(&[u8]) -> Vec<u8>) -> Vec<u8> {
+        let unsigned = build_image(&[0x55; 64], &[], &[]);
+        let hash = McubootImage::parse(&unsigned)
+            .unwrap()
+            .hash()
+            .unwrap()
+            .to_vec();
+        let key_hash = key.key_hash();
+        build_image(&[0x55; 64], &[], &[(0x01, &key_hash), (kind, &sign(&hash))])
+    }
+
+    fn ed25519_key(seed: u8) -> (ed25519_dalek::SigningKey, PublicKey) {
+        let signing = ed25519_dalek::SigningKey::from_bytes(&[seed; 32]);
+        let pem = signing
+            .verifying_key()
+            .to_public_key_pem(Default::default());
+        (signing, PublicKey::from_pem(&pem.unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_ed25519() {
+        let (signing, key) = ed25519_key(1);
+        assert_eq!(key.algorithm(), "ED25519");
+        let image = signed_image(&key, 0x24, |hash| signing.sign(hash).to_vec());
+        let image = McubootImage::parse(&image).unwrap();
+        image.verify_signature(&key).unwrap();
+
+        let (_, other) = ed25519_key(2);
+        assert!(matches!(
+            image.verify_signature(&other),
+            Err(SignatureError::KeyMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_ecdsa_p256() {
+        let signing = p256::ecdsa::SigningKey::from_slice(&[7; 32]).unwrap();
+        let pem = signing
+            .to_pkcs8_pem(Default::default())
+            .unwrap()
+            .to_string();
+        let key = PublicKey::from_pem(&pem).unwrap();
+        assert_eq!(key.algorithm(), "ECDSA-P256");
+
+        let image = signed_image(&key, 0x22, |hash| {
+            let sig: p256::ecdsa::Signature = signing.sign_prehash(hash).unwrap();
+            // padded like older imgtool versions do
+            let mut der = sig.to_der().as_bytes().to_vec();
+            der.resize(72, 0);
+            der
+        });
+        McubootImage::parse(&image)
+            .unwrap()
+            .verify_signature(&key)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_rsa_2048() {
+        let key = PublicKey::from_pem(RSA_2048_KEY).unwrap();
+        assert_eq!(key.algorithm(), "RSA-2048");
+
+        let private = rsa::RsaPrivateKey::from_pkcs8_pem(RSA_2048_KEY).unwrap();
+        let mut image = signed_image(&key, 0x20, |hash| {
+            private
+                .sign_with_rng(&mut XorShift(42), rsa::Pss::new::<Sha256>(), hash)
+                .unwrap()
+        });
+        McubootImage::parse(&image)
+            .unwrap()
+            .verify_signature(&key)
+            .unwrap();
+
+        // the signature is the last TLV
+        let len = image.len();
+        image[len - 1] ^= 1;
+        assert!(matches!(
+            McubootImage::parse(&image).unwrap().verify_signature(&key),
+            Err(SignatureError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn test_algorithm_mismatch() {
+        let (signing, key) = ed25519_key(1);
+        let image = signed_image(&key, 0x22, |hash| signing.sign(hash).to_vec());
+        assert!(matches!(
+            McubootImage::parse(&image).unwrap().verify_signature(&key),
+            Err(SignatureError::AlgorithmMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_unsigned() {
+        let (_, key) = ed25519_key(1);
+        let image = build_image(&[0; 16], &[], &[]);
+        assert!(matches!(
+            McubootImage::parse(&image).unwrap().verify_signature(&key),
+            Err(SignatureError::Unsigned)
+        ));
+    }
+}
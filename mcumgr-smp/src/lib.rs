@@ -19,14 +19,38 @@
 /// Implementation of a general [SmpFrame] that can have any payload.
 pub mod smp;
 
+pub mod device_error;
+
 #[cfg(feature = "payload-cbor")]
 pub mod application_management;
 #[cfg(feature = "payload-cbor")]
+pub mod fs_management;
+/// Local verification of MCUboot image signatures
+#[cfg(feature = "image-verify")]
+pub mod image_signature;
+/// Parser for MCUboot image headers and TLVs
+pub mod mcuboot_image;
+#[cfg(feature = "payload-cbor")]
 pub mod os_management;
+/// Trait pairing requests with their header fields and response type
+#[cfg(feature = "payload-cbor")]
+pub mod request;
+/// Generic response payload with error handling
+#[cfg(feature = "payload-cbor")]
+pub mod response;
+#[cfg(feature = "payload-cbor")]
+pub mod settings_management;
 #[cfg(feature = "payload-cbor")]
 pub mod shell_management;
+#[cfg(feature = "payload-cbor")]
+pub mod stat_management;
 
 /// Implementations over UDP transports
 pub mod transport;
 
+pub use device_error::{DeviceError, DeviceErrorCode};
+#[cfg(feature = "payload-cbor")]
+pub use request::SmpRequest;
+#[cfg(feature = "payload-cbor")]
+pub use response::SmpResponse;
 pub use smp::*;
@@ -0,0 +1,535 @@
+// Author: Sascha Zenglein <zenglein@gessler.de>
+// Copyright (c) 2023 Gessler GmbH.
+
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+
+pub const IMAGE_MAGIC: u32 = 0x96f3_b83d;
+/// magic of images built for MCUboot before v1.0
+pub const IMAGE_MAGIC_V1: u32 = 0x96f3_b83c;
+pub const HEADER_SIZE: usize = 32;
+
+const TLV_INFO_MAGIC: u16 = 0x6907;
+const TLV_PROT_INFO_MAGIC: u16 = 0x6908;
+const TLV_INFO_SIZE: usize = 4;
+const TLV_HEADER_SIZE: usize = 4;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum ImageError {
+    #[error("file is too short for an MCUboot image ({0} bytes)")]
+    TooShort(usize),
+    #[error("not an MCUboot image, bad magic {0:#010x}")]
+    BadMagic(u32),
+    #[error("image header size {0} is smaller than the header")]
+    BadHeaderSize(u16),
+    #[error("image is truncated, expected at least {expected} bytes, got {got}")]
+    Truncated { expected: usize, got: usize },
+    #[error("bad TLV info magic {found:#06x} at offset {offset:#x}")]
+    BadTlvMagic { found: u16, offset: usize },
+    #[error("TLV at offset {0:#x} exceeds the TLV area")]
+    BadTlv(usize),
+    #[error("protected TLV size {header} in the header does not match the protected TLV area ({area} bytes)")]
+    BadProtectedTlvSize { header: u16, area: usize },
+    #[error("image has no SHA256 hash TLV")]
+    MissingHash,
+}
+
+/// Flags of the image header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageFlags(pub u32);
+
+impl ImageFlags {
+    pub const PIC: u32 = 0x01;
+    pub const ENCRYPTED_AES128: u32 = 0x04;
+    pub const ENCRYPTED_AES256: u32 = 0x08;
+    pub const NON_BOOTABLE: u32 = 0x10;
+    pub const RAM_LOAD: u32 = 0x20;
+    pub const ROM_FIXED: u32 = 0x100;
+
+    const NAMES: [(u32, &'static str); 6] = [
+        (Self::PIC, "PIC"),
+        (Self::ENCRYPTED_AES128, "ENCRYPTED_AES128"),
+        (Self::ENCRYPTED_AES256, "ENCRYPTED_AES256"),
+        (Self::NON_BOOTABLE, "NON_BOOTABLE"),
+        (Self::RAM_LOAD, "RAM_LOAD"),
+        (Self::ROM_FIXED, "ROM_FIXED"),
+    ];
+
+    pub fn contains(&self, flag: u32) -> bool {
+        self.0 & flag == flag
+    }
+
+    /// names of the set flags, unknown flags are shown as hex
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| name.to_string())
+            .collect();
+        let known = Self::NAMES.iter().fold(0, |acc, (flag, _)| acc | flag);
+        if self.0 & !known != 0 {
+            names.push(format!("{:#x}", self.0 & !known));
+        }
+        names
+    }
+}
+
+/// Image version as `major.minor.revision+build`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct ImageVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub revision: u16,
+    pub build_num: u32,
+}
+
+impl Display for ImageVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}+{}",
+            self.major, self.minor, self.revision, self.build_num
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub magic: u32,
+    pub load_addr: u32,
+    pub hdr_size: u16,
+    pub protect_tlv_size: u16,
+    pub img_size: u32,
+    pub flags: ImageFlags,
+    pub version: ImageVersion,
+}
+
+impl ImageHeader {
+    pub fn parse(data: &[u8]) -> Result<ImageHeader, ImageError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ImageError::TooShort(data.len()));
+        }
+
+        let u16_at = |off: usize| u16::from_le_bytes([data[off], data[off + 1]]);
+        let u32_at = |off: usize| u32::from_le_bytes(data[off..off + 4].try_into().unwrap());
+
+        let magic = u32_at(0);
+        if magic != IMAGE_MAGIC && magic != IMAGE_MAGIC_V1 {
+            return Err(ImageError::BadMagic(magic));
+        }
+
+        let hdr_size = u16_at(8);
+        if (hdr_size as usize) < HEADER_SIZE {
+            return Err(ImageError::BadHeaderSize(hdr_size));
+        }
+
+        Ok(ImageHeader {
+            magic,
+            load_addr: u32_at(4),
+            hdr_size,
+            protect_tlv_size: u16_at(10),
+            img_size: u32_at(12),
+            flags: ImageFlags(u32_at(16)),
+            version: ImageVersion {
+                major: data[20],
+                minor: data[21],
+                revision: u16_at(22),
+                build_num: u32_at(24),
+            },
+        })
+    }
+}
+
+/// Known TLV types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlvKind {
+    KeyHash,
+    PublicKey,
+    Sha256,
+    Sha384,
+    Sha512,
+    Rsa2048Pss,
+    Ecdsa224,
+    EcdsaSig,
+    Rsa3072Pss,
+    Ed25519,
+    SigPure,
+    EncRsa2048,
+    EncKw,
+    EncEc256,
+    EncX25519,
+    Dependency,
+    SecurityCounter,
+    BootRecord,
+    Unknown(u16),
+}
+
+impl From<u16> for TlvKind {
+    fn from(kind: u16) -> Self {
+        match kind {
+            0x01 => TlvKind::KeyHash,
+            0x02 => TlvKind::PublicKey,
+            0x10 => TlvKind::Sha256,
+            0x11 => TlvKind::Sha384,
+            0x12 => TlvKind::Sha512,
+            0x20 => TlvKind::Rsa2048Pss,
+            0x21 => TlvKind::Ecdsa224,
+            0x22 => TlvKind::EcdsaSig,
+            0x23 => TlvKind::Rsa3072Pss,
+            0x24 => TlvKind::Ed25519,
+            0x25 => TlvKind::SigPure,
+            0x30 => TlvKind::EncRsa2048,
+            0x31 => TlvKind::EncKw,
+            0x32 => TlvKind::EncEc256,
+            0x33 => TlvKind::EncX25519,
+            0x40 => TlvKind::Dependency,
+            0x50 => TlvKind::SecurityCounter,
+            0x60 => TlvKind::BootRecord,
+            n => TlvKind::Unknown(n),
+        }
+    }
+}
+
+impl From<TlvKind> for u16 {
+    fn from(kind: TlvKind) -> Self {
+        match kind {
+            TlvKind::KeyHash => 0x01,
+            TlvKind::PublicKey => 0x02,
+            TlvKind::Sha256 => 0x10,
+            TlvKind::Sha384 => 0x11,
+            TlvKind::Sha512 => 0x12,
+            TlvKind::Rsa2048Pss => 0x20,
+            TlvKind::Ecdsa224 => 0x21,
+            TlvKind::EcdsaSig => 0x22,
+            TlvKind::Rsa3072Pss => 0x23,
+            TlvKind::Ed25519 => 0x24,
+            TlvKind::SigPure => 0x25,
+            TlvKind::EncRsa2048 => 0x30,
+            TlvKind::EncKw => 0x31,
+            TlvKind::EncEc256 => 0x32,
+            TlvKind::EncX25519 => 0x33,
+            TlvKind::Dependency => 0x40,
+            TlvKind::SecurityCounter => 0x50,
+            TlvKind::BootRecord => 0x60,
+            TlvKind::Unknown(n) => n,
+        }
+    }
+}
+
+impl TlvKind {
+    pub fn is_signature(&self) -> bool {
+        matches!(
+            self,
+            TlvKind::Rsa2048Pss
+                | TlvKind::Ecdsa224
+                | TlvKind::EcdsaSig
+                | TlvKind::Rsa3072Pss
+                | TlvKind::Ed25519
+        )
+    }
+}
+
+/// Minimum version of another image that this image requires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageDependency {
+    pub image_id: u8,
+    pub version: ImageVersion,
+}
+
+impl ImageDependency {
+    fn parse(data: &[u8]) -> Option<ImageDependency> {
+        if data.len() != 12 {
+            return None;
+        }
+        Some(ImageDependency {
+            image_id: data[0],
+            version: ImageVersion {
+                major: data[4],
+                minor: data[5],
+                revision: u16::from_le_bytes([data[6], data[7]]),
+                build_num: u32::from_le_bytes(data[8..12].try_into().unwrap()),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageTlv<'a> {
+    pub kind: TlvKind,
+    /// protected TLVs are covered by the image hash and signature
+    pub protected: bool,
+    pub data: &'a [u8],
+}
+
+/// A parsed MCUboot image, borrowing the TLV data from the image file
+#[derive(Debug, Clone)]
+pub struct McubootImage<'a> {
+    pub header: ImageHeader,
+    pub tlvs: Vec<ImageTlv<'a>>,
+    data: &'a [u8],
+}
+
+impl<'a> McubootImage<'a> {
+    /// Parse the header and all TLVs of an image file
+    pub fn parse(data: &'a [u8]) -> Result<McubootImage<'a>, ImageError> {
+        let header = ImageHeader::parse(data)?;
+
+        let mut offset = header.hdr_size as usize + header.img_size as usize;
+        let mut tlvs = Vec::new();
+
+        if header.protect_tlv_size > 0 {
+            let end = Self::parse_tlv_area(data, offset, TLV_PROT_INFO_MAGIC, true, &mut tlvs)?;
+            // the hashed region ends after the protected TLVs the header claims
+            if end - offset != header.protect_tlv_size as usize {
+                return Err(ImageError::BadProtectedTlvSize {
+                    header: header.protect_tlv_size,
+                    area: end - offset,
+                });
+            }
+            offset = end;
+        }
+        Self::parse_tlv_area(data, offset, TLV_INFO_MAGIC, false, &mut tlvs)?;
+
+        Ok(McubootImage { header, tlvs, data })
+    }
+
+    /// parse a TLV info header and its TLVs, returns the end of the area
+    fn parse_tlv_area(
+        data: &'a [u8],
+        offset: usize,
+        magic: u16,
+        protected: bool,
+        tlvs: &mut Vec<ImageTlv<'a>>,
+    ) -> Result<usize, ImageError> {
+        let truncated = |expected: usize| ImageError::Truncated {
+            expected,
+            got: data.len(),
+        };
+
+        let info = data
+            .get(offset..offset + TLV_INFO_SIZE)
+            .ok_or(truncated(offset + TLV_INFO_SIZE))?;
+        let found = u16::from_le_bytes([info[0], info[1]]);
+        if found != magic {
+            return Err(ImageError::BadTlvMagic { found, offset });
+        }
+
+        // the total size includes the info header
+        let end = offset + u16::from_le_bytes([info[2], info[3]]) as usize;
+        if end > data.len() {
+            return Err(truncated(end));
+        }
+
+        let mut pos = offset + TLV_INFO_SIZE;
+        while pos < end {
+            if pos + TLV_HEADER_SIZE > end {
+                return Err(ImageError::BadTlv(pos));
+            }
+            let kind = u16::from_le_bytes([data[pos], data[pos + 1]]);
+            let len = u16::from_le_bytes([data[pos + 2], data[pos + 3]]) as usize;
+            let start = pos + TLV_HEADER_SIZE;
+            if start + len > end {
+                return Err(ImageError::BadTlv(pos));
+            }
+            tlvs.push(ImageTlv {
+                kind: kind.into(),
+                protected,
+                data: &data[start..start + len],
+            });
+            pos = start + len;
+        }
+
+        Ok(end)
+    }
+
+    /// first TLV of the given kind
+    pub fn tlv(&self, kind: TlvKind) -> Option<&ImageTlv<'a>> {
+        self.tlvs.iter().find(|tlv| tlv.kind == kind)
+    }
+
+    /// The SHA256 image hash, this is the hash reported by the device in the image state
+    pub fn hash(&self) -> Result<&'a [u8], ImageError> {
+        self.tlv(TlvKind::Sha256)
+            .map(|tlv| tlv.data)
+            .ok_or(ImageError::MissingHash)
+    }
+
+    /// Hash of the public key the image was signed with
+    pub fn key_hash(&self) -> Option<&'a [u8]> {
+        self.tlv(TlvKind::KeyHash).map(|tlv| tlv.data)
+    }
+
+    /// The signature TLV, if the image is signed
+    pub fn signature(&self) -> Option<&ImageTlv<'a>> {
+        self.tlvs.iter().find(|tlv| tlv.kind.is_signature())
+    }
+
+    /// Dependencies on other images, malformed dependency TLVs are skipped
+    pub fn dependencies(&self) -> Vec<ImageDependency> {
+        self.tlvs
+            .iter()
+            .filter(|tlv| tlv.kind == TlvKind::Dependency)
+            .filter_map(|tlv| ImageDependency::parse(tlv.data))
+            .collect()
+    }
+
+    /// Security counter used for rollback protection
+    pub fn security_counter(&self) -> Option<u32> {
+        self.tlv(TlvKind::SecurityCounter)
+            .and_then(|tlv| tlv.data.try_into().ok())
+            .map(u32::from_le_bytes)
+    }
+
+    /// Header, image and protected TLVs, the data covered by the hash
+    pub fn hashed_region(&self) -> Result<&'a [u8], ImageError> {
+        let mut end = self.header.hdr_size as usize + self.header.img_size as usize;
+        end += self.header.protect_tlv_size as usize;
+        self.data.get(..end).ok_or(ImageError::Truncated {
+            expected: end,
+            got: self.data.len(),
+        })
+    }
+
+    /// Compute the SHA256 hash of the image and compare it with the hash TLV
+    pub fn verify_hash(&self) -> Result<bool, ImageError> {
+        let computed = Sha256::digest(self.hashed_region()?);
+        Ok(computed.as_slice() == self.hash()?)
+    }
+
+    /// The image file this was parsed from
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn tlv_area(magic: u16, tlvs: &[(u16, &[u8])]) -> Vec<u8> {
+        let body: Vec<u8> = tlvs
+            .iter()
+            .flat_map(|(kind, data)| {
+                let mut tlv = kind.to_le_bytes().to_vec();
+                tlv.extend((data.len() as u16).to_le_bytes());
+                tlv.extend_from_slice(data);
+                tlv
+            })
+            .collect();
+        let mut area = magic.to_le_bytes().to_vec();
+        area.extend(((body.len() + TLV_INFO_SIZE) as u16).to_le_bytes());
+        area.extend(body);
+        area
+    }
+
+    /// Build an image with a correct SHA256 TLV and the given additional TLVs
+    pub(crate) fn build_image(
+        payload: &[u8],
+        protected: &[(u16, &[u8])],
+        unprotected: &[(u16, &[u8])],
+    ) -> Vec<u8> {
+        let prot = if protected.is_empty() {
+            Vec::new()
+        } else {
+            tlv_area(TLV_PROT_INFO_MAGIC, protected)
+        };
+
+        let mut image = IMAGE_MAGIC.to_le_bytes().to_vec();
+        image.extend(0x1000u32.to_le_bytes()); // load address
+        image.extend(0x200u16.to_le_bytes()); // header size
+        image.extend((prot.len() as u16).to_le_bytes());
+        image.extend((payload.len() as u32).to_le_bytes());
+        image.extend(ImageFlags::RAM_LOAD.to_le_bytes());
+        image.extend([1, 2]);
+        image.extend(3u16.to_le_bytes());
+        image.extend(4u32.to_le_bytes());
+        image.resize(0x200, 0xff);
+        image.extend_from_slice(payload);
+        image.extend(prot);
+
+        let hash = Sha256::digest(&image);
+        let mut tlvs = vec![(0x10, hash.as_slice())];
+        tlvs.extend_from_slice(unprotected);
+        image.extend(tlv_area(TLV_INFO_MAGIC, &tlvs));
+        image
+    }
+
+    #[test]
+    fn test_parse() {
+        let image = build_image(&[0xaa; 100], &[(0x50, &[7, 0, 0, 0])], &[(0x01, &[1; 32])]);
+        let parsed = McubootImage::parse(&image).unwrap();
+
+        assert_eq!(parsed.header.version.to_string(), "1.2.3+4");
+        assert_eq!(parsed.header.load_addr, 0x1000);
+        assert_eq!(parsed.header.img_size, 100);
+        assert_eq!(parsed.header.flags.names(), vec!["RAM_LOAD"]);
+
+        let counter = parsed.tlv(TlvKind::SecurityCounter).unwrap();
+        assert!(counter.protected);
+        assert_eq!(counter.data, &[7, 0, 0, 0]);
+        assert!(!parsed.tlv(TlvKind::KeyHash).unwrap().protected);
+
+        assert_eq!(parsed.hash().unwrap().len(), 32);
+        assert!(parsed.verify_hash().unwrap());
+    }
+
+    #[test]
+    fn test_metadata() {
+        let dependency = [1, 0, 0, 0, 2, 1, 5, 0, 9, 0, 0, 0];
+        let image = build_image(
+            &[0xaa; 16],
+            &[(0x50, &[7, 0, 0, 0]), (0x40, &dependency)],
+            &[(0x01, &[1; 32]), (0x24, &[2; 64])],
+        );
+        let parsed = McubootImage::parse(&image).unwrap();
+
+        assert_eq!(parsed.security_counter(), Some(7));
+        assert_eq!(parsed.key_hash(), Some(&[1u8; 32][..]));
+        assert_eq!(parsed.signature().unwrap().kind, TlvKind::Ed25519);
+        assert_eq!(
+            parsed.dependencies(),
+            vec![ImageDependency {
+                image_id: 1,
+                version: ImageVersion {
+                    major: 2,
+                    minor: 1,
+                    revision: 5,
+                    build_num: 9
+                }
+            }]
+        );
+    }
+
+    #[test]
+    fn test_corrupted_image() {
+        let mut image = build_image(&[0xaa; 100], &[], &[]);
+        image[0x210] ^= 1;
+        assert!(!McubootImage::parse(&image).unwrap().verify_hash().unwrap());
+
+        assert_eq!(
+            McubootImage::parse(&image[..0x200 + 50]).unwrap_err(),
+            ImageError::Truncated {
+                expected: 0x200 + 100 + TLV_INFO_SIZE,
+                got: 0x200 + 50
+            }
+        );
+        assert_eq!(
+            McubootImage::parse(&[0u8; 64]).unwrap_err(),
+            ImageError::BadMagic(0)
+        );
+    }
+
+    #[test]
+    fn test_protected_tlv_size_mismatch() {
+        let mut image = build_image(&[0xaa; 16], &[(0x50, &[7, 0, 0, 0])], &[]);
+        image[10..12].copy_from_slice(&0xffffu16.to_le_bytes());
+
+        assert_eq!(
+            McubootImage::parse(&image).unwrap_err(),
+            ImageError::BadProtectedTlvSize {
+                header: 0xffff,
+                area: TLV_INFO_SIZE + TLV_HEADER_SIZE + 4
+            }
+        );
+    }
+}
@@ -1,52 +1,421 @@
 // Author: Sascha Zenglein <zenglein@gessler.de>
 // Copyright (c) 2023 Gessler GmbH.
-use crate::{Group, SmpFrame};
+use crate::{Group, OpCode, SmpFrame, SmpRequest, SmpResponse};
 
-use crate::OpCode::{ReadRequest, WriteRequest};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Commands of the os group
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OsManagementCommand {
+    Echo = 0,
+    ConsoleEcho = 1,
+    TaskStatistics = 2,
+    MemoryPoolStatistics = 3,
+    DateTime = 4,
+    Reset = 5,
+    McumgrParameters = 6,
+    Info = 7,
+    BootloaderInfo = 8,
+}
+
+impl From<OsManagementCommand> for u8 {
+    fn from(cmd: OsManagementCommand) -> Self {
+        cmd as u8
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct EchoRequest {
     pub d: String,
 }
 
+impl SmpRequest for EchoRequest {
+    const GROUP: Group = Group::Default;
+    const COMMAND: u8 = OsManagementCommand::Echo as u8;
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = EchoResponse;
+}
+
 pub fn echo(sequence: u8, msg: String) -> SmpFrame<EchoRequest> {
-    let payload = EchoRequest { d: msg };
-    SmpFrame::new(WriteRequest, sequence, Group::Default, 0, payload)
+    EchoRequest { d: msg }.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct EchoResponse {
+    pub r: String,
+}
+
+pub type EchoResult = SmpResponse<EchoResponse>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TaskStatRequest {}
+
+impl SmpRequest for TaskStatRequest {
+    const GROUP: Group = Group::Default;
+    const COMMAND: u8 = OsManagementCommand::TaskStatistics as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = TaskStatResponse;
+}
+
+pub fn task_stat(sequence: u8) -> SmpFrame<TaskStatRequest> {
+    TaskStatRequest {}.into_frame(sequence)
+}
+
+/// Statistics of a single thread, fields depend on the device configuration
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskStat {
+    pub prio: i32,
+    pub tid: u32,
+    pub state: u32,
+    /// used stack size in 4 byte words
+    #[serde(default)]
+    pub stkuse: Option<u32>,
+    /// stack size in 4 byte words
+    #[serde(default)]
+    pub stksiz: Option<u32>,
+    #[serde(default)]
+    pub cswcnt: Option<u32>,
+    #[serde(default)]
+    pub runtime: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TaskStatResponse {
+    pub tasks: BTreeMap<String, TaskStat>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MemPoolStatRequest {}
+
+impl SmpRequest for MemPoolStatRequest {
+    const GROUP: Group = Group::Default;
+    const COMMAND: u8 = OsManagementCommand::MemoryPoolStatistics as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = MemPoolStatResponse;
+}
+
+pub fn mem_pool_stat(sequence: u8) -> SmpFrame<MemPoolStatRequest> {
+    MemPoolStatRequest {}.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MemPoolStat {
+    /// block size
+    pub blksiz: u32,
+    /// number of blocks
+    pub nblks: u32,
+    /// number of free blocks
+    pub nfree: u32,
+    /// lowest number of free blocks
+    #[serde(default)]
+    pub min: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct MemPoolStatResponse {
+    pub mpools: BTreeMap<String, MemPoolStat>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GetDateTimeRequest {}
+
+impl SmpRequest for GetDateTimeRequest {
+    const GROUP: Group = Group::Default;
+    const COMMAND: u8 = OsManagementCommand::DateTime as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = DateTimeResponse;
+}
+
+pub fn get_datetime(sequence: u8) -> SmpFrame<GetDateTimeRequest> {
+    GetDateTimeRequest {}.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DateTimeResponse {
+    /// `yyyy-MM-ddTHH:mm:ss`, optionally with milliseconds
+    pub datetime: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SetDateTimeRequest {
+    /// `yyyy-MM-ddTHH:mm:ss`, optionally with milliseconds and time zone
+    pub datetime: String,
+}
+
+impl SmpRequest for SetDateTimeRequest {
+    const GROUP: Group = Group::Default;
+    const COMMAND: u8 = OsManagementCommand::DateTime as u8;
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = SetDateTimeResponse;
 }
 
+pub fn set_datetime(sequence: u8, datetime: String) -> SmpFrame<SetDateTimeRequest> {
+    SetDateTimeRequest { datetime }.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SetDateTimeResponse {}
+
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(untagged)]
-pub enum EchoResult {
-    Ok { r: String },
-    Err { rc: i32 },
+pub struct McumgrParamsRequest {}
+
+impl SmpRequest for McumgrParamsRequest {
+    const GROUP: Group = Group::Default;
+    const COMMAND: u8 = OsManagementCommand::McumgrParameters as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = McumgrParamsResponse;
+}
+
+pub fn mcumgr_params(sequence: u8) -> SmpFrame<McumgrParamsRequest> {
+    McumgrParamsRequest {}.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct McumgrParamsResponse {
+    /// size of a single SMP buffer, the maximum size of a request
+    pub buf_size: u32,
+    /// number of SMP buffers
+    pub buf_count: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GetInfoRequest {
-    pub format: String,
+    /// Characters of the fields to return, see [OsInfoField]. The device returns the kernel name if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+impl SmpRequest for GetInfoRequest {
+    const GROUP: Group = Group::Default;
+    const COMMAND: u8 = OsManagementCommand::Info as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = GetInfoResponse;
 }
 
 pub fn get_info(sequence: u8, format: String) -> SmpFrame<GetInfoRequest> {
-    let request = GetInfoRequest { format };
+    GetInfoRequest {
+        format: Some(format),
+    }
+    .into_frame(sequence)
+}
 
-    SmpFrame::new(ReadRequest, sequence, Group::Default, 7, request)
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct GetInfoResponse {
+    pub output: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(untagged)]
-pub enum ResetResult {
-    Ok {},
-    Err { rc: i32 },
+/// Fields of the OS/application info, in the order the device prints them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OsInfoField {
+    KernelName,
+    NodeName,
+    KernelRelease,
+    KernelVersion,
+    BuildDateTime,
+    Machine,
+    Processor,
+    HardwarePlatform,
+    OperatingSystem,
+}
+
+impl OsInfoField {
+    pub const ALL: [OsInfoField; 9] = [
+        OsInfoField::KernelName,
+        OsInfoField::NodeName,
+        OsInfoField::KernelRelease,
+        OsInfoField::KernelVersion,
+        OsInfoField::BuildDateTime,
+        OsInfoField::Machine,
+        OsInfoField::Processor,
+        OsInfoField::HardwarePlatform,
+        OsInfoField::OperatingSystem,
+    ];
+
+    /// character in the format string
+    pub fn format_char(&self) -> char {
+        match self {
+            OsInfoField::KernelName => 's',
+            OsInfoField::NodeName => 'n',
+            OsInfoField::KernelRelease => 'r',
+            OsInfoField::KernelVersion => 'v',
+            OsInfoField::BuildDateTime => 'b',
+            OsInfoField::Machine => 'm',
+            OsInfoField::Processor => 'p',
+            OsInfoField::HardwarePlatform => 'i',
+            OsInfoField::OperatingSystem => 'o',
+        }
+    }
+
+    pub fn from_format_char(c: char) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.format_char() == c)
+    }
+
+    /// number of space separated words of the field
+    fn words(&self) -> usize {
+        match self {
+            // e.g. "Tue Oct 14 09:21:43 2025"
+            OsInfoField::BuildDateTime => 5,
+            _ => 1,
+        }
+    }
+}
+
+/// Parsed output of an info request
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OsInfo {
+    pub fields: BTreeMap<OsInfoField, String>,
+}
+
+impl OsInfo {
+    /// Request all fields
+    pub const FORMAT_ALL: &'static str = "a";
+
+    /// Split the output of an info request with the given format into its fields.
+    ///
+    /// The device prints the fields in a fixed order, separated by spaces, regardless of the
+    /// order in `format`. Unknown format characters are ignored. If the output has more words than
+    /// expected, the remaining words are added to the last field.
+    pub fn parse(format: &str, output: &str) -> OsInfo {
+        let mut requested: Vec<OsInfoField> = if format.contains('a') {
+            OsInfoField::ALL.to_vec()
+        } else {
+            format
+                .chars()
+                .filter_map(OsInfoField::from_format_char)
+                .collect()
+        };
+        requested.sort();
+        requested.dedup();
+
+        let mut words = output.split_whitespace().peekable();
+        let mut fields = BTreeMap::new();
+        for (i, field) in requested.iter().enumerate() {
+            let count = if i + 1 == requested.len() {
+                usize::MAX
+            } else {
+                field.words()
+            };
+            let value: Vec<&str> = words.by_ref().take(count).collect();
+            if value.is_empty() {
+                break;
+            }
+            fields.insert(*field, value.join(" "));
+        }
+
+        OsInfo { fields }
+    }
+
+    pub fn get(&self, field: OsInfoField) -> Option<&str> {
+        self.fields.get(&field).map(String::as_str)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+pub struct BootloaderInfoRequest {
+    /// e.g. "mode" for MCUboot, the bootloader name is returned if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+}
+
+impl SmpRequest for BootloaderInfoRequest {
+    const GROUP: Group = Group::Default;
+    const COMMAND: u8 = OsManagementCommand::BootloaderInfo as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = BootloaderInfoResponse;
+}
+
+pub fn bootloader_info(sequence: u8, query: Option<String>) -> SmpFrame<BootloaderInfoRequest> {
+    BootloaderInfoRequest { query }.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct BootloaderInfoResponse {
+    #[serde(default)]
+    pub bootloader: Option<String>,
+    /// MCUboot mode, e.g. 0 for single slot, 1 for swap using scratch
+    #[serde(default)]
+    pub mode: Option<i32>,
+    #[serde(rename = "no-downgrade")]
+    #[serde(default)]
+    pub no_downgrade: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ResetResponse {}
+
+pub type ResetResult = SmpResponse<ResetResponse>;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct ResetRequest {
-    pub force: u8,
+    /// reset even if the application vetoes it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub force: Option<bool>,
+    /// boot mode after the reset, e.g. 1 to stay in the bootloader
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boot_mode: Option<u8>,
+}
+
+impl SmpRequest for ResetRequest {
+    const GROUP: Group = Group::Default;
+    const COMMAND: u8 = OsManagementCommand::Reset as u8;
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = ResetResponse;
 }
 
 pub fn reset(sequence: u8, force: bool) -> SmpFrame<ResetRequest> {
-    let payload = ResetRequest { force: force as u8 };
+    ResetRequest {
+        force: force.then_some(true),
+        boot_mode: None,
+    }
+    .into_frame(sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_info() {
+        let info = OsInfo::parse(
+            "a",
+            "Zephyr dev v3.7.0 v3.7.0-2-gabc Tue Oct 14 09:21:43 2025 arm cortex-m33 nrf5340dk Zephyr",
+        );
+        assert_eq!(info.get(OsInfoField::KernelName), Some("Zephyr"));
+        assert_eq!(
+            info.get(OsInfoField::BuildDateTime),
+            Some("Tue Oct 14 09:21:43 2025")
+        );
+        assert_eq!(info.get(OsInfoField::HardwarePlatform), Some("nrf5340dk"));
+        assert_eq!(info.get(OsInfoField::OperatingSystem), Some("Zephyr"));
+    }
+
+    #[test]
+    fn test_parse_info_device_order() {
+        // the device prints fields in its own order, not in the order of the format string
+        let info = OsInfo::parse("is", "Zephyr nrf5340dk nrf5340 cpuapp");
+        assert_eq!(info.get(OsInfoField::KernelName), Some("Zephyr"));
+        assert_eq!(
+            info.get(OsInfoField::HardwarePlatform),
+            Some("nrf5340dk nrf5340 cpuapp")
+        );
+    }
+
+    #[test]
+    fn test_reset_payload() {
+        let bytes = reset(0, false).encode_with_cbor();
+        let frame = SmpFrame::<ciborium::Value>::decode_with_cbor(&bytes).unwrap();
+        assert_eq!(frame.data, ciborium::Value::Map(vec![]));
 
-    SmpFrame::new(WriteRequest, sequence, Group::Default, 5, payload)
+        let bytes = ResetRequest {
+            force: Some(true),
+            boot_mode: Some(1),
+        }
+        .into_frame(0)
+        .encode_with_cbor();
+        let frame =
+            SmpFrame::<BTreeMap<String, ciborium::Value>>::decode_with_cbor(&bytes).unwrap();
+        assert_eq!(frame.data["force"], ciborium::Value::Bool(true));
+        assert_eq!(frame.data["boot_mode"], ciborium::Value::Integer(1.into()));
+    }
 }
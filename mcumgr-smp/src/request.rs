@@ -0,0 +1,133 @@
+// Author: Sascha Zenglein <zenglein@gessler.de>
+// Copyright (c) 2023 Gessler GmbH.
+
+use crate::{Group, OpCode, SmpFrame};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// A request payload that knows its header fields and the payload of its response.
+///
+/// The response is decoded as [SmpResponse<Self::Response>](crate::SmpResponse), so error
+/// responses are detected for every request.
+///
+/// Custom groups can be used the same way as the built-in ones:
+/// ```
+/// use mcumgr_smp::{Group, OpCode, SmpRequest};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Serialize)]
+/// struct ReadSensor {
+///     channel: u8,
+/// }
+///
+/// #[derive(Deserialize)]
+/// struct SensorValue {
+///     value: f32,
+/// }
+///
+/// impl SmpRequest for ReadSensor {
+///     const GROUP: Group = Group::Custom(64);
+///     const COMMAND: u8 = 0;
+///     const OPERATION: OpCode = OpCode::ReadRequest;
+///     type Response = SensorValue;
+/// }
+///
+/// let frame = ReadSensor { channel: 2 }.into_frame(42);
+/// assert_eq!(frame.group, Group::Custom(64));
+/// ```
+pub trait SmpRequest: Serialize {
+    const GROUP: Group;
+    const COMMAND: u8;
+    const OPERATION: OpCode;
+
+    /// payload of a successful response
+    type Response: DeserializeOwned;
+
+    /// Wrap the request in a frame with the given sequence number
+    fn into_frame(self, sequence: u8) -> SmpFrame<Self>
+    where
+        Self: Sized,
+    {
+        SmpFrame::new(Self::OPERATION, sequence, Self::GROUP, Self::COMMAND, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::os_management::{EchoRequest, EchoResponse, GetInfoRequest, ResetRequest};
+    use crate::transport::error::Error;
+    use crate::transport::smp::{CborSmpTransport, SmpTransport};
+    use crate::{SmpGroupError, SmpResponse};
+
+    /// answers every request with the same payload
+    struct Responder<T> {
+        reply: SmpResponse<T>,
+        pending: Option<Vec<u8>>,
+    }
+
+    impl<T: Serialize + Send + Sync> SmpTransport for Responder<T> {
+        fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            let request = SmpFrame::<ciborium::Value>::decode_with_cbor(&frame)?;
+            let response = SmpFrame::new(
+                OpCode::WriteResponse,
+                request.sequence,
+                request.group,
+                request.command,
+                &self.reply,
+            );
+            self.pending = Some(response.encode_with_cbor());
+            Ok(())
+        }
+
+        fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.send(frame)
+        }
+
+        fn receive(&mut self) -> Result<Vec<u8>, Error> {
+            self.pending
+                .take()
+                .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())
+        }
+    }
+
+    fn transport<T: Serialize + Send + Sync + 'static>(reply: SmpResponse<T>) -> CborSmpTransport {
+        CborSmpTransport {
+            transport: Box::new(Responder {
+                reply,
+                pending: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_header_fields() {
+        let frame = ResetRequest::default().into_frame(3);
+        assert_eq!(frame.operation, OpCode::WriteRequest);
+        assert_eq!(frame.group, Group::Default);
+        assert_eq!(frame.command, 5);
+        assert_eq!(frame.sequence, 3);
+
+        let frame = GetInfoRequest { format: None }.into_frame(0);
+        assert_eq!(frame.operation, OpCode::ReadRequest);
+        assert_eq!(frame.command, 7);
+    }
+
+    #[test]
+    fn test_call() {
+        let mut transport = transport(SmpResponse::Ok(EchoResponse { r: "hi".into() }));
+        let resp = transport.call(1, EchoRequest { d: "hi".into() }).unwrap();
+        assert_eq!(resp, EchoResponse { r: "hi".into() });
+    }
+
+    #[test]
+    fn test_call_device_error() {
+        let mut transport = transport(SmpResponse::<()>::GroupErr(SmpGroupError {
+            group: 0,
+            rc: 2,
+        }));
+        let err = transport.call(1, ResetRequest::default()).unwrap_err();
+        assert!(matches!(err, Error::Device(e) if e.code.rc() == 2));
+    }
+}
@@ -0,0 +1,166 @@
+// Author: Sascha Zenglein <zenglein@gessler.de>
+// Copyright (c) 2023 Gessler GmbH.
+
+use crate::{DeviceError, Group, SmpFrame, SmpGroupError};
+
+use ciborium::Value;
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Response payload of any command.
+///
+/// Decoding looks at the error fields first: a non-zero `rc` (SMP version 1)
+/// or an `err` map (SMP version 2) is an error, regardless of what else the map contains.
+/// Only then is the payload decoded as `T`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SmpResponse<T> {
+    Ok(T),
+    /// SMP version 1 error
+    Err {
+        rc: i32,
+        rsn: Option<String>,
+    },
+    /// SMP version 2 group error
+    GroupErr(SmpGroupError),
+}
+
+impl<T> SmpResponse<T> {
+    /// Convert into a [Result], `group` is the group of the response header
+    pub fn into_result(self, group: Group) -> Result<T, DeviceError> {
+        match self {
+            SmpResponse::Ok(payload) => Ok(payload),
+            SmpResponse::Err { rc, rsn } => Err(DeviceError::from_rc(group, rc, rsn)),
+            SmpResponse::GroupErr(err) => Err(err.into()),
+        }
+    }
+}
+
+impl<T> SmpFrame<SmpResponse<T>> {
+    /// Take the payload of the response or the error returned by the device
+    pub fn into_result(self) -> Result<T, DeviceError> {
+        self.data.into_result(self.group)
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorFields {
+    rc: Option<i32>,
+    rsn: Option<String>,
+    err: Option<SmpGroupError>,
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for SmpResponse<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+
+        if value.is_map() {
+            let fields: ErrorFields = value.deserialized().map_err(D::Error::custom)?;
+            if let Some(err) = fields.err {
+                return Ok(SmpResponse::GroupErr(err));
+            }
+            if let Some(rc) = fields.rc.filter(|rc| *rc != 0) {
+                return Ok(SmpResponse::Err {
+                    rc,
+                    rsn: fields.rsn,
+                });
+            }
+        }
+
+        value
+            .deserialized()
+            .map(SmpResponse::Ok)
+            .map_err(D::Error::custom)
+    }
+}
+
+impl<T: Serialize> Serialize for SmpResponse<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            SmpResponse::Ok(payload) => payload.serialize(serializer),
+            SmpResponse::Err { rc, rsn } => {
+                let mut map = serializer.serialize_map(None)?;
+                map.serialize_entry("rc", rc)?;
+                if let Some(rsn) = rsn {
+                    map.serialize_entry("rsn", rsn)?;
+                }
+                map.end()
+            }
+            SmpResponse::GroupErr(err) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("err", err)?;
+                map.end()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application_management::WriteImageChunkPayload;
+    use crate::os_management::{EchoResponse, ResetResponse};
+    use crate::OpCode;
+
+    fn roundtrip<Req: Serialize, Resp: DeserializeOwned>(payload: Req) -> SmpResponse<Resp> {
+        let bytes =
+            SmpFrame::new(OpCode::WriteResponse, 0, Group::Default, 0, payload).encode_with_cbor();
+        SmpFrame::<SmpResponse<Resp>>::decode_with_cbor(&bytes)
+            .unwrap()
+            .data
+    }
+
+    #[derive(Serialize)]
+    struct Rc {
+        rc: i32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rsn: Option<String>,
+    }
+
+    #[test]
+    fn test_empty_payload_does_not_hide_errors() {
+        let resp: SmpResponse<ResetResponse> = roundtrip(Rc { rc: 6, rsn: None });
+        assert_eq!(resp, SmpResponse::Err { rc: 6, rsn: None });
+
+        let resp: SmpResponse<ResetResponse> = roundtrip(ResetResponse {});
+        assert_eq!(resp, SmpResponse::Ok(ResetResponse {}));
+    }
+
+    #[test]
+    fn test_v2_group_error() {
+        let err = SmpGroupError { group: 1, rc: 30 };
+        let resp: SmpResponse<WriteImageChunkPayload> = roundtrip(SmpResponse::<()>::GroupErr(err));
+        assert!(matches!(resp, SmpResponse::GroupErr(e) if e == err));
+        assert_eq!(
+            resp.into_result(Group::ApplicationManagement)
+                .unwrap_err()
+                .to_string(),
+            "device returned ApplicationManagement error 30: image too large for slot"
+        );
+    }
+
+    #[test]
+    fn test_zero_rc_is_success() {
+        #[derive(Serialize)]
+        struct Upload {
+            rc: i32,
+            off: u32,
+        }
+
+        let resp: SmpResponse<WriteImageChunkPayload> = roundtrip(Upload { rc: 0, off: 512 });
+        assert!(matches!(
+            resp,
+            SmpResponse::Ok(WriteImageChunkPayload { off: 512, .. })
+        ));
+    }
+
+    #[test]
+    fn test_rsn_is_kept() {
+        let resp: SmpResponse<EchoResponse> = roundtrip(Rc {
+            rc: 3,
+            rsn: Some("bad input".into()),
+        });
+        let err = resp.into_result(Group::Default).unwrap_err();
+        assert_eq!(err.rsn.as_deref(), Some("bad input"));
+    }
+}
@@ -0,0 +1,179 @@
+// Author: Sascha Zenglein <zenglein@gessler.de>
+// Copyright (c) 2023 Gessler GmbH.
+
+use crate::{Group, OpCode, SmpFrame, SmpRequest, SmpResponse};
+
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf; // CBOR byte string
+
+/// Commands of the settings group
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SettingsManagementCommand {
+    /// read (read) or write (write) a setting
+    ReadWrite = 0,
+    Delete = 1,
+    Commit = 2,
+    /// load (read) or save (write) all settings
+    LoadSave = 3,
+}
+
+impl From<SettingsManagementCommand> for u8 {
+    fn from(cmd: SettingsManagementCommand) -> Self {
+        cmd as u8
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReadSettingRequest {
+    pub name: String,
+    /// limit the size of the returned value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u32>,
+}
+
+impl SmpRequest for ReadSettingRequest {
+    const GROUP: Group = Group::Settings;
+    const COMMAND: u8 = SettingsManagementCommand::ReadWrite as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = ReadSettingPayload;
+}
+
+pub fn read(sequence: u8, name: String) -> SmpFrame<ReadSettingRequest> {
+    ReadSettingRequest {
+        name,
+        max_size: None,
+    }
+    .into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ReadSettingPayload {
+    pub val: ByteBuf,
+    /// set if the value was truncated to the maximum size supported by the device
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u32>,
+}
+
+pub type ReadSettingResult = SmpResponse<ReadSettingPayload>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WriteSettingRequest {
+    pub name: String,
+    pub val: ByteBuf,
+}
+
+impl SmpRequest for WriteSettingRequest {
+    const GROUP: Group = Group::Settings;
+    const COMMAND: u8 = SettingsManagementCommand::ReadWrite as u8;
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = SettingsPayload;
+}
+
+pub fn write(sequence: u8, name: String, val: Vec<u8>) -> SmpFrame<WriteSettingRequest> {
+    WriteSettingRequest {
+        name,
+        val: ByteBuf::from(val),
+    }
+    .into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeleteSettingRequest {
+    pub name: String,
+}
+
+impl SmpRequest for DeleteSettingRequest {
+    const GROUP: Group = Group::Settings;
+    const COMMAND: u8 = SettingsManagementCommand::Delete as u8;
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = SettingsPayload;
+}
+
+pub fn delete(sequence: u8, name: String) -> SmpFrame<DeleteSettingRequest> {
+    DeleteSettingRequest { name }.into_frame(sequence)
+}
+
+/// Apply written settings in the running application
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CommitSettingsRequest {}
+
+impl SmpRequest for CommitSettingsRequest {
+    const GROUP: Group = Group::Settings;
+    const COMMAND: u8 = SettingsManagementCommand::Commit as u8;
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = SettingsPayload;
+}
+
+pub fn commit(sequence: u8) -> SmpFrame<CommitSettingsRequest> {
+    CommitSettingsRequest {}.into_frame(sequence)
+}
+
+/// Load all settings from persistent storage
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LoadSettingsRequest {}
+
+impl SmpRequest for LoadSettingsRequest {
+    const GROUP: Group = Group::Settings;
+    const COMMAND: u8 = SettingsManagementCommand::LoadSave as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = SettingsPayload;
+}
+
+pub fn load(sequence: u8) -> SmpFrame<LoadSettingsRequest> {
+    LoadSettingsRequest {}.into_frame(sequence)
+}
+
+/// Save all settings to persistent storage
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SaveSettingsRequest {}
+
+impl SmpRequest for SaveSettingsRequest {
+    const GROUP: Group = Group::Settings;
+    const COMMAND: u8 = SettingsManagementCommand::LoadSave as u8;
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = SettingsPayload;
+}
+
+pub fn save(sequence: u8) -> SmpFrame<SaveSettingsRequest> {
+    SaveSettingsRequest {}.into_frame(sequence)
+}
+
+/// Empty response of write, delete, commit, load and save
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SettingsPayload {}
+
+pub type SettingsResult = SmpResponse<SettingsPayload>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_save_share_command() {
+        let load = load(0);
+        let save = save(1);
+        assert_eq!(load.command, save.command);
+        assert_eq!(load.operation, OpCode::ReadRequest);
+        assert_eq!(save.operation, OpCode::WriteRequest);
+        assert_eq!(u16::from(save.group), 3);
+    }
+
+    #[test]
+    fn test_settings_error() {
+        let frame = SmpFrame::new(
+            OpCode::WriteResponse,
+            0,
+            Group::Settings,
+            0,
+            SettingsResult::GroupErr(crate::SmpGroupError { group: 3, rc: 3 }),
+        );
+        let decoded =
+            SmpFrame::<SettingsResult>::decode_with_cbor(&frame.encode_with_cbor()).unwrap();
+        let err = decoded.into_result().unwrap_err();
+        assert_eq!(err.group, Group::Settings);
+        assert_eq!(
+            err.code,
+            crate::DeviceErrorCode::Settings(crate::device_error::SettingsMgmtError::KeyNotFound)
+        );
+    }
+}
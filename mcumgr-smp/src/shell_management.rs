@@ -1,8 +1,8 @@
 // Author: Sascha Zenglein <zenglein@gessler.de>
 // Copyright (c) 2023 Gessler GmbH.
-use crate::{Group, SmpFrame};
+use crate::{Group, OpCode, SmpFrame, SmpRequest, SmpResponse};
 
-use crate::OpCode::{WriteRequest, WriteResponse};
+use crate::OpCode::WriteResponse;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -11,37 +11,33 @@ pub struct ShellCommand {
     pub argv: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ShellResponse {
-    /// argv containing cmd + arg, arg, ...
-    pub o: String,
-    pub ret: i32
+impl SmpRequest for ShellCommand {
+    const GROUP: Group = Group::ShellManagement;
+    /// shell command execute
+    const COMMAND: u8 = 0;
+    const OPERATION: OpCode = OpCode::WriteRequest;
+    type Response = ShellResponse;
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(untagged)]
-pub enum ShellResult {
-    Ok { o: String, ret: i32 },
-    Err { rc: i32 },
+pub struct ShellResponse {
+    /// output of the command
+    pub o: String,
+    /// return code of the command
+    pub ret: i32,
 }
 
-impl ShellResult {
-    pub fn into_result(self) -> Result<(String, i32), i32> {
-        match self {
-            ShellResult::Ok { o, ret } => Ok((o, ret)),
-            ShellResult::Err { rc } => Err(rc),
-        }
-    }
-}
+pub type ShellResult = SmpResponse<ShellResponse>;
 
 pub fn shell_command(sequence: u8, command_args: Vec<String>) -> SmpFrame<ShellCommand> {
-    let payload: ShellCommand = ShellCommand { argv: command_args };
-
-    SmpFrame::new(WriteRequest, sequence, Group::ShellManagement, 0, payload)
+    ShellCommand { argv: command_args }.into_frame(sequence)
 }
 
 pub fn shell_command_response(sequence: u8, command_args: String) -> SmpFrame<ShellResponse> {
-    let payload: ShellResponse = ShellResponse { ret: 0, o: command_args };
+    let payload: ShellResponse = ShellResponse {
+        ret: 0,
+        o: command_args,
+    };
 
     SmpFrame::new(WriteResponse, sequence, Group::ShellManagement, 0, payload)
 }
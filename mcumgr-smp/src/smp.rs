@@ -13,7 +13,7 @@ pub enum SmpError {
     UnexpectedSeq,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode {
     ReadRequest = 0,
     ReadResponse = 1,
@@ -44,11 +44,40 @@ impl From<OpCode> for u8 {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// SMP protocol version, encoded in bits 3 and 4 of the first header byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SmpVersion {
+    /// Original protocol, understood by all devices
+    #[default]
+    V1 = 0,
+    /// Adds the `{"err": {"group": g, "rc": rc}}` group error format
+    V2 = 1,
+}
+
+impl TryFrom<u8> for SmpVersion {
+    type Error = SmpError;
+
+    fn try_from(num: u8) -> Result<Self, Self::Error> {
+        match num {
+            0 => Ok(SmpVersion::V1),
+            1 => Ok(SmpVersion::V2),
+            _ => Err(SmpError::InvalidFrame),
+        }
+    }
+}
+
+impl From<SmpVersion> for u8 {
+    fn from(version: SmpVersion) -> Self {
+        version as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Group {
     Default,
     ApplicationManagement,
     Statistics,
+    Settings,
     FileManagement,
     ShellManagement,
     ZephyrCommand,
@@ -61,6 +90,7 @@ impl From<u16> for Group {
             0 => Self::Default,
             1 => Self::ApplicationManagement,
             2 => Self::Statistics,
+            3 => Self::Settings,
             8 => Self::FileManagement,
             9 => Self::ShellManagement,
             63 => Self::ZephyrCommand,
@@ -75,6 +105,7 @@ impl From<Group> for u16 {
             Group::Default => 0,
             Group::ApplicationManagement => 1,
             Group::Statistics => 2,
+            Group::Settings => 3,
             Group::FileManagement => 8,
             Group::ShellManagement => 9,
             Group::ZephyrCommand => 63,
@@ -83,18 +114,22 @@ impl From<Group> for u16 {
     }
 }
 
-pub enum ReturnCode {
-    Ok = 0,
-    Unknown = 1,
-    OutOfMemory = 2,
-    // ...
-    UserDefined = 256,
+pub use crate::device_error::ReturnCode;
+
+/// Group error as returned by SMP version 2 servers:
+/// `{"err": {"group": g, "rc": rc}}`
+#[cfg(feature = "payload-cbor")]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SmpGroupError {
+    pub group: u16,
+    pub rc: i32,
 }
 
 /// Definitition of a single SMP message.  
 /// SMP Requests and Responses always have this format.
 #[derive(Debug, Clone)]
 pub struct SmpFrame<T> {
+    pub version: SmpVersion,
     pub operation: OpCode,
     pub flags: u8,
     pub group: Group,
@@ -104,9 +139,10 @@ pub struct SmpFrame<T> {
 }
 
 impl<T> SmpFrame<T> {
-    ///  Create new with default flags
+    ///  Create new with default flags, using SMP version 1
     pub fn new(operation: OpCode, sequence: u8, group: Group, command: u8, payload: T) -> Self {
         Self {
+            version: SmpVersion::V1,
             operation,
             flags: 0,
             group,
@@ -115,6 +151,25 @@ impl<T> SmpFrame<T> {
             data: payload,
         }
     }
+
+    /// Set the protocol version of the frame
+    pub fn with_version(mut self, version: SmpVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Borrow the payload, e.g. to send the same request with a different header
+    pub fn as_ref(&self) -> SmpFrame<&T> {
+        SmpFrame {
+            version: self.version,
+            operation: self.operation,
+            flags: self.flags,
+            group: self.group,
+            sequence: self.sequence,
+            command: self.command,
+            data: &self.data,
+        }
+    }
 }
 
 impl<T> SmpFrame<T> {
@@ -129,7 +184,7 @@ impl<T> SmpFrame<T> {
         let encoded = encode_payload(&self.data)?;
         let data: &[u8] = encoded.as_ref();
 
-        buf.push(self.operation.into());
+        buf.push(u8::from(self.operation) | (u8::from(self.version) << 3));
         buf.push(self.flags);
         buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
         let group: u16 = self.group.into();
@@ -146,27 +201,42 @@ impl<T> SmpFrame<T> {
     /// For the common CBOR serialisation, see [SmpFrame::decode_with_cbor]
     pub fn decode(
         buf: &[u8],
-        decode_payload: impl FnOnce(&[u8]) -> Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>,
+        decode_payload: impl FnOnce(
+            &[u8],
+        )
+            -> Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>,
     ) -> Result<SmpFrame<T>, SmpError> {
         if buf.len() < 8 {
             return Err(SmpError::InvalidFrame);
         }
 
+        if buf[0] & 0x07 > 3 {
+            return Err(SmpError::InvalidFrame);
+        }
         let operation = OpCode::from(buf[0] & 0x07);
+        let version = SmpVersion::try_from((buf[0] >> 3) & 0x03)?;
         let group = Group::from(u16::from_be_bytes([buf[4], buf[5]]));
-        let data_len = u16::from_be_bytes([buf[2], buf[3]]);
-        let _flags = buf[1];
+        let data_len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        let flags = buf[1];
         let sequence = buf[6];
         let command = buf[7];
 
-        if buf.len() < (8 + data_len) as usize {
+        if buf.len() < 8 + data_len {
             return Err(SmpError::InvalidFrame);
         }
 
-        let data_buf = &buf[8..(8 + data_len as usize)];
+        let data_buf = &buf[8..8 + data_len];
         let data = decode_payload(data_buf)?;
 
-        Ok(SmpFrame::new(operation, sequence, group, command, data))
+        Ok(SmpFrame {
+            version,
+            operation,
+            flags,
+            group,
+            sequence,
+            command,
+            data,
+        })
     }
 }
 
@@ -198,3 +268,117 @@ impl<T: serde::de::DeserializeOwned> SmpFrame<T> {
         })
     }
 }
+
+/// Largest amount of data a chunk can carry without its encoded frame exceeding `frame_size`
+/// bytes, `empty_chunk` encodes the same chunk without any data
+#[cfg(feature = "payload-cbor")]
+pub(crate) fn max_chunk_len(frame_size: usize, empty_chunk: impl FnOnce() -> Vec<u8>) -> usize {
+    // an empty byte string is encoded with a one byte header
+    let overhead = empty_chunk().len() - 1;
+    max_byte_string_len(frame_size.saturating_sub(overhead))
+}
+
+/// Longest byte string that fits into `available` bytes together with its CBOR header
+#[cfg(feature = "payload-cbor")]
+fn max_byte_string_len(available: usize) -> usize {
+    // the byte string header grows with the data length
+    [1, 2, 3, 5]
+        .into_iter()
+        .filter_map(|header| {
+            let len = available.checked_sub(header)?;
+            (byte_string_header_len(len) <= header).then_some(len)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Length of the CBOR header of a byte string with `len` bytes
+#[cfg(feature = "payload-cbor")]
+fn byte_string_header_len(len: usize) -> usize {
+    match len {
+        0..=23 => 1,
+        24..=0xff => 2,
+        0x100..=0xffff => 3,
+        _ => 5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_raw(buf: &[u8]) -> Result<SmpFrame<Vec<u8>>, SmpError> {
+        SmpFrame::decode(buf, |data| Ok(data.to_vec()))
+    }
+
+    #[test]
+    fn test_v1_header_roundtrip() {
+        let frame = SmpFrame::new(OpCode::WriteRequest, 42, Group::Default, 0, vec![0xa0]);
+        let bytes = frame.encode(|d| Ok::<_, ()>(d.clone())).unwrap();
+        assert_eq!(bytes, [0x02, 0, 0, 1, 0, 0, 42, 0, 0xa0]);
+
+        let decoded = decode_raw(&bytes).unwrap();
+        assert_eq!(decoded.version, SmpVersion::V1);
+        assert_eq!(decoded.data, [0xa0]);
+    }
+
+    #[test]
+    fn test_v2_header_keeps_version_and_flags() {
+        let mut frame = SmpFrame::new(
+            OpCode::ReadRequest,
+            7,
+            Group::ApplicationManagement,
+            0,
+            vec![],
+        )
+        .with_version(SmpVersion::V2);
+        frame.flags = 0x5a;
+        let bytes = frame.encode(|d| Ok::<_, ()>(d.clone())).unwrap();
+        assert_eq!(bytes[0], 0x08);
+
+        let decoded = decode_raw(&bytes).unwrap();
+        assert_eq!(decoded.version, SmpVersion::V2);
+        assert!(matches!(decoded.operation, OpCode::ReadRequest));
+        assert_eq!(decoded.flags, 0x5a);
+    }
+
+    #[test]
+    fn test_decode_rejects_reserved_bits() {
+        assert!(matches!(
+            decode_raw(&[0x04, 0, 0, 0, 0, 0, 0, 0]),
+            Err(SmpError::InvalidFrame)
+        ));
+        assert!(matches!(
+            decode_raw(&[0x13, 0, 0, 0, 0, 0, 0, 0]),
+            Err(SmpError::InvalidFrame)
+        ));
+    }
+
+    #[test]
+    fn test_decode_rejects_truncated_long_frame() {
+        assert!(matches!(
+            decode_raw(&[0x00, 0, 0xff, 0xff, 0, 0, 0, 0]),
+            Err(SmpError::InvalidFrame)
+        ));
+    }
+
+    #[cfg(feature = "payload-cbor")]
+    #[test]
+    fn test_decode_v2_group_error() {
+        use crate::os_management::ResetResult;
+
+        #[derive(serde::Serialize)]
+        struct Err {
+            err: SmpGroupError,
+        }
+
+        let err = SmpGroupError { group: 0, rc: 3 };
+        let bytes = SmpFrame::new(OpCode::WriteResponse, 1, Group::Default, 5, Err { err })
+            .with_version(SmpVersion::V2)
+            .encode_with_cbor();
+
+        let decoded = SmpFrame::<ResetResult>::decode_with_cbor(&bytes).unwrap();
+        assert_eq!(decoded.version, SmpVersion::V2);
+        assert!(matches!(decoded.data, ResetResult::GroupErr(e) if e == err));
+    }
+}
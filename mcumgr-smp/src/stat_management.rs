@@ -0,0 +1,117 @@
+// Author: Sascha Zenglein <zenglein@gessler.de>
+// Copyright (c) 2023 Gessler GmbH.
+
+use crate::{Group, OpCode, SmpFrame, SmpRequest, SmpResponse};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Commands of the statistics group
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatManagementCommand {
+    GroupData = 0,
+    ListGroups = 1,
+}
+
+impl From<StatManagementCommand> for u8 {
+    fn from(cmd: StatManagementCommand) -> Self {
+        cmd as u8
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatGroupRequest {
+    pub name: String,
+}
+
+impl SmpRequest for StatGroupRequest {
+    const GROUP: Group = Group::Statistics;
+    const COMMAND: u8 = StatManagementCommand::GroupData as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = StatGroupPayload;
+}
+
+pub fn read(sequence: u8, name: String) -> SmpFrame<StatGroupRequest> {
+    StatGroupRequest { name }.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StatGroupPayload {
+    pub name: String,
+    pub fields: BTreeMap<String, u64>,
+}
+
+impl StatGroupPayload {
+    /// Change of every counter since `previous`.
+    /// Counters that are missing in `previous` are compared against 0,
+    /// a negative delta means the counter was reset, e.g. by a reboot.
+    pub fn delta(&self, previous: &StatGroupPayload) -> BTreeMap<String, i64> {
+        self.fields
+            .iter()
+            .map(|(name, value)| {
+                let old = previous.fields.get(name).copied().unwrap_or(0);
+                (name.clone(), (*value as i64).wrapping_sub(old as i64))
+            })
+            .collect()
+    }
+}
+
+pub type StatGroupResult = SmpResponse<StatGroupPayload>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatListRequest {}
+
+impl SmpRequest for StatListRequest {
+    const GROUP: Group = Group::Statistics;
+    const COMMAND: u8 = StatManagementCommand::ListGroups as u8;
+    const OPERATION: OpCode = OpCode::ReadRequest;
+    type Response = StatListPayload;
+}
+
+pub fn list(sequence: u8) -> SmpFrame<StatListRequest> {
+    StatListRequest {}.into_frame(sequence)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StatListPayload {
+    pub stat_list: Vec<String>,
+}
+
+pub type StatListResult = SmpResponse<StatListPayload>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(fields: &[(&str, u64)]) -> StatGroupPayload {
+        StatGroupPayload {
+            name: "net".into(),
+            fields: fields.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_delta() {
+        let old = group(&[("rx", 10), ("tx", 7)]);
+        let new = group(&[("rx", 15), ("tx", 2), ("err", 1)]);
+
+        let delta = new.delta(&old);
+        assert_eq!(delta["rx"], 5);
+        assert_eq!(delta["tx"], -5);
+        assert_eq!(delta["err"], 1);
+    }
+
+    #[test]
+    fn test_decode_group() {
+        let frame = SmpFrame::new(
+            OpCode::ReadResponse,
+            1,
+            Group::Statistics,
+            0,
+            group(&[("rx", 3)]),
+        );
+        let decoded =
+            SmpFrame::<StatGroupResult>::decode_with_cbor(&frame.encode_with_cbor()).unwrap();
+        assert_eq!(decoded.into_result().unwrap(), group(&[("rx", 3)]));
+    }
+}
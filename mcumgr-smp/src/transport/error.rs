@@ -4,6 +4,14 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("SMP: {0}")]
     Smp(#[from] crate::smp::SmpError),
+    #[error("{0}")]
+    Device(#[from] crate::device_error::DeviceError),
+    #[cfg(feature = "transport-serial")]
+    #[error("Serial framing: {0}")]
+    Framing(#[from] crate::transport::smp_framing::SmpTransportError),
+    #[cfg(feature = "transport-serial")]
+    #[error("Serial port: {0}")]
+    Serial(#[from] serialport::Error),
 }
 
 pub type Result<T = (), E = Error> = core::result::Result<T, E>;
@@ -0,0 +1,628 @@
+//! Seeded fault injection for any transport.
+//!
+//! [FaultyTransport] and [FaultyTransportAsync] wrap a transport and drop, duplicate, delay,
+//! reorder, truncate or bit-flip the frames passing through it. All decisions come from a random
+//! generator seeded by the caller, so a test that fails for a seed fails the same way again.
+//! Every fault is counted in [FaultStats].
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::transport::error::Error;
+use crate::transport::smp::SmpTransport;
+
+/// Probabilities, 0.0 to 1.0, of the faults applied to every frame
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Faults {
+    /// the frame is lost
+    pub drop: f64,
+    /// the frame arrives twice
+    pub duplicate: f64,
+    /// the frame is held back for up to `max_delay`
+    pub delay: f64,
+    pub max_delay: Duration,
+    /// the frame arrives after the next one
+    pub reorder: f64,
+    /// the end of the frame is cut off
+    pub truncate: f64,
+    /// a single bit of the frame is flipped
+    pub bit_flip: f64,
+}
+
+impl Faults {
+    pub fn with_drop(mut self, probability: f64) -> Self {
+        self.drop = probability;
+        self
+    }
+
+    pub fn with_duplicate(mut self, probability: f64) -> Self {
+        self.duplicate = probability;
+        self
+    }
+
+    pub fn with_delay(mut self, probability: f64, max_delay: Duration) -> Self {
+        self.delay = probability;
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_reorder(mut self, probability: f64) -> Self {
+        self.reorder = probability;
+        self
+    }
+
+    pub fn with_truncate(mut self, probability: f64) -> Self {
+        self.truncate = probability;
+        self
+    }
+
+    pub fn with_bit_flip(mut self, probability: f64) -> Self {
+        self.bit_flip = probability;
+        self
+    }
+}
+
+/// Frames of one direction and the faults applied to them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultCounts {
+    pub frames: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub delayed: u64,
+    pub reordered: u64,
+    pub truncated: u64,
+    pub bit_flipped: u64,
+}
+
+impl FaultCounts {
+    /// All faults together
+    pub fn faults(&self) -> u64 {
+        self.dropped
+            + self.duplicated
+            + self.delayed
+            + self.reordered
+            + self.truncated
+            + self.bit_flipped
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    pub sent: FaultCounts,
+    pub received: FaultCounts,
+}
+
+/// SplitMix64, small and good enough to pick faults
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// uniform in 0.0..1.0
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.unit() < probability
+    }
+
+    /// uniform in 0..n, n must not be 0
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// What happens to a frame
+#[derive(Default)]
+struct Plan {
+    /// nothing if dropped, two copies if duplicated
+    frames: Vec<Vec<u8>>,
+    delay: Duration,
+    reorder: bool,
+}
+
+struct Outgoing {
+    frame: Vec<u8>,
+    /// sent with `send_to`
+    reply: bool,
+}
+
+/// Fault decisions and frames held back, shared by the blocking and async wrappers
+struct Injector {
+    rng: Rng,
+    send: Faults,
+    receive: Faults,
+    stats: Arc<Mutex<FaultStats>>,
+    /// sent frames that are passed on after the next one
+    held_out: Vec<Outgoing>,
+    /// received frames that are returned after the next one
+    held_in: Vec<Vec<u8>>,
+    /// received frames and when they may be returned
+    pending: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl Injector {
+    fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng(seed),
+            send: Faults::default(),
+            receive: Faults::default(),
+            stats: Arc::default(),
+            held_out: Vec::new(),
+            held_in: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn plan(&mut self, outgoing: bool, mut frame: Vec<u8>) -> Plan {
+        let faults = if outgoing { &self.send } else { &self.receive };
+        let rng = &mut self.rng;
+        let mut stats = self.stats.lock().unwrap();
+        let counts = if outgoing {
+            &mut stats.sent
+        } else {
+            &mut stats.received
+        };
+
+        counts.frames += 1;
+        if rng.chance(faults.drop) {
+            counts.dropped += 1;
+            return Plan::default();
+        }
+        if !frame.is_empty() && rng.chance(faults.truncate) {
+            frame.truncate(rng.below(frame.len()));
+            counts.truncated += 1;
+        }
+        if !frame.is_empty() && rng.chance(faults.bit_flip) {
+            let bit = rng.below(frame.len() * 8);
+            frame[bit / 8] ^= 1 << (bit % 8);
+            counts.bit_flipped += 1;
+        }
+        let delay = if rng.chance(faults.delay) {
+            counts.delayed += 1;
+            faults.max_delay.mul_f64(rng.unit())
+        } else {
+            Duration::ZERO
+        };
+        let reorder = rng.chance(faults.reorder);
+        let mut frames = vec![frame];
+        if rng.chance(faults.duplicate) {
+            frames.push(frames[0].clone());
+            counts.duplicated += 1;
+        }
+
+        Plan {
+            frames,
+            delay,
+            reorder,
+        }
+    }
+
+    fn count_reorder(&self, outgoing: bool) {
+        let mut stats = self.stats.lock().unwrap();
+        if outgoing {
+            stats.sent.reordered += 1;
+        } else {
+            stats.received.reordered += 1;
+        }
+    }
+
+    /// Frames to pass on now for a sent frame and the pause before
+    fn outgoing(&mut self, frame: Vec<u8>, reply: bool) -> (Duration, Vec<Outgoing>) {
+        let plan = self.plan(true, frame);
+        let reorder = plan.reorder && self.held_out.is_empty() && !plan.frames.is_empty();
+        let mut frames: Vec<Outgoing> = plan
+            .frames
+            .into_iter()
+            .map(|frame| Outgoing { frame, reply })
+            .collect();
+
+        if reorder {
+            self.count_reorder(true);
+            self.held_out = frames;
+            return (plan.delay, Vec::new());
+        }
+        frames.append(&mut self.held_out);
+        (plan.delay, frames)
+    }
+
+    /// Held back frames that must not wait any longer because nothing else is sent
+    fn flush_outgoing(&mut self) -> Vec<Outgoing> {
+        std::mem::take(&mut self.held_out)
+    }
+
+    fn incoming(&mut self, frame: Vec<u8>) {
+        let plan = self.plan(false, frame);
+        if plan.reorder && self.held_in.is_empty() && !plan.frames.is_empty() {
+            self.count_reorder(false);
+            self.held_in = plan.frames;
+            return;
+        }
+
+        let ready = Instant::now() + plan.delay;
+        self.pending
+            .extend(plan.frames.into_iter().map(|frame| (ready, frame)));
+        self.pending
+            .extend(self.held_in.drain(..).map(|frame| (ready, frame)));
+    }
+
+    /// The transport has nothing more to receive, returns if held back frames are released
+    fn release_incoming(&mut self) -> bool {
+        let now = Instant::now();
+        let released = !self.held_in.is_empty();
+        self.pending
+            .extend(self.held_in.drain(..).map(|frame| (now, frame)));
+        released
+    }
+}
+
+/// Blocking transport that injects faults into the frames of another one
+pub struct FaultyTransport<T> {
+    transport: T,
+    injector: Injector,
+}
+
+impl<T: SmpTransport> FaultyTransport<T> {
+    /// Passes all frames unchanged until faults are configured
+    pub fn new(transport: T, seed: u64) -> Self {
+        Self {
+            transport,
+            injector: Injector::new(seed),
+        }
+    }
+
+    /// Faults in both directions
+    pub fn with_faults(self, faults: Faults) -> Self {
+        self.with_send_faults(faults.clone())
+            .with_receive_faults(faults)
+    }
+
+    pub fn with_send_faults(mut self, faults: Faults) -> Self {
+        self.injector.send = faults;
+        self
+    }
+
+    pub fn with_receive_faults(mut self, faults: Faults) -> Self {
+        self.injector.receive = faults;
+        self
+    }
+
+    pub fn stats(&self) -> FaultStats {
+        *self.injector.stats.lock().unwrap()
+    }
+
+    /// Statistics that stay readable after the transport is handed over, e.g. to a client
+    pub fn stats_handle(&self) -> Arc<Mutex<FaultStats>> {
+        self.injector.stats.clone()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    fn pass_on(&mut self, frames: Vec<Outgoing>) -> Result<(), Error> {
+        for Outgoing { frame, reply } in frames {
+            if reply {
+                self.transport.send_to(frame)?;
+            } else {
+                self.transport.send(frame)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_faulty(&mut self, frame: Vec<u8>, reply: bool) -> Result<(), Error> {
+        let (delay, frames) = self.injector.outgoing(frame, reply);
+        std::thread::sleep(delay);
+        self.pass_on(frames)
+    }
+}
+
+impl<T: SmpTransport> SmpTransport for FaultyTransport<T> {
+    fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        self.send_faulty(frame, false)
+    }
+
+    fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        self.send_faulty(frame, true)
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, Error> {
+        let held = self.injector.flush_outgoing();
+        self.pass_on(held)?;
+
+        loop {
+            if let Some((ready, _)) = self.injector.pending.front() {
+                std::thread::sleep(ready.saturating_duration_since(Instant::now()));
+                return Ok(self.injector.pending.pop_front().unwrap().1);
+            }
+            match self.transport.receive() {
+                Ok(frame) => self.injector.incoming(frame),
+                Err(_) if self.injector.release_incoming() => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        self.transport.mtu()
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.transport.set_timeout(timeout)
+    }
+}
+
+#[cfg(feature = "async")]
+pub use self::faulty_async::FaultyTransportAsync;
+
+#[cfg(feature = "async")]
+mod faulty_async {
+    use super::*;
+    use crate::transport::smp::SmpTransportAsync;
+    use async_trait::async_trait;
+
+    /// Async transport that injects faults into the frames of another one.
+    ///
+    /// Receiving is cancel safe as long as the wrapped transport's is, frames waiting for
+    /// their delay are kept until the next receive.
+    pub struct FaultyTransportAsync<T> {
+        transport: T,
+        injector: Injector,
+    }
+
+    impl<T: SmpTransportAsync> FaultyTransportAsync<T> {
+        /// Passes all frames unchanged until faults are configured
+        pub fn new(transport: T, seed: u64) -> Self {
+            Self {
+                transport,
+                injector: Injector::new(seed),
+            }
+        }
+
+        /// Faults in both directions
+        pub fn with_faults(self, faults: Faults) -> Self {
+            self.with_send_faults(faults.clone())
+                .with_receive_faults(faults)
+        }
+
+        pub fn with_send_faults(mut self, faults: Faults) -> Self {
+            self.injector.send = faults;
+            self
+        }
+
+        pub fn with_receive_faults(mut self, faults: Faults) -> Self {
+            self.injector.receive = faults;
+            self
+        }
+
+        pub fn stats(&self) -> FaultStats {
+            *self.injector.stats.lock().unwrap()
+        }
+
+        /// Statistics that stay readable after the transport is handed over, e.g. to a client
+        pub fn stats_handle(&self) -> Arc<Mutex<FaultStats>> {
+            self.injector.stats.clone()
+        }
+
+        pub fn into_inner(self) -> T {
+            self.transport
+        }
+
+        async fn pass_on(&mut self, frames: Vec<Outgoing>) -> Result<(), Error> {
+            for Outgoing { frame, reply } in frames {
+                if reply {
+                    self.transport.send_to(frame).await?;
+                } else {
+                    self.transport.send(frame).await?;
+                }
+            }
+            Ok(())
+        }
+
+        async fn send_faulty(&mut self, frame: Vec<u8>, reply: bool) -> Result<(), Error> {
+            let (delay, frames) = self.injector.outgoing(frame, reply);
+            tokio::time::sleep(delay).await;
+            self.pass_on(frames).await
+        }
+    }
+
+    #[async_trait]
+    impl<T: SmpTransportAsync + Send> SmpTransportAsync for FaultyTransportAsync<T> {
+        async fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.send_faulty(frame, false).await
+        }
+
+        async fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.send_faulty(frame, true).await
+        }
+
+        async fn receive(&mut self) -> Result<Vec<u8>, Error> {
+            let held = self.injector.flush_outgoing();
+            self.pass_on(held).await?;
+
+            loop {
+                if let Some((ready, _)) = self.injector.pending.front() {
+                    tokio::time::sleep(ready.saturating_duration_since(Instant::now())).await;
+                    return Ok(self.injector.pending.pop_front().unwrap().1);
+                }
+                match self.transport.receive().await {
+                    Ok(frame) => self.injector.incoming(frame),
+                    Err(_) if self.injector.release_incoming() => {}
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        fn mtu(&self) -> usize {
+            self.transport.mtu()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    /// Receives every frame it sends
+    #[derive(Default)]
+    struct Loopback {
+        sent: Vec<Vec<u8>>,
+        queue: VecDeque<Vec<u8>>,
+    }
+
+    impl SmpTransport for Loopback {
+        fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.sent.push(frame.clone());
+            self.queue.push_back(frame);
+            Ok(())
+        }
+
+        fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.send(frame)
+        }
+
+        fn receive(&mut self) -> Result<Vec<u8>, Error> {
+            self.queue
+                .pop_front()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut).into())
+        }
+    }
+
+    fn frames() -> impl Iterator<Item = Vec<u8>> {
+        (0..200u8).map(|i| vec![i; 16])
+    }
+
+    /// Sends all frames and returns what arrives
+    fn run(seed: u64) -> (Vec<Vec<u8>>, FaultStats) {
+        let faults = Faults::default()
+            .with_drop(0.1)
+            .with_duplicate(0.1)
+            .with_reorder(0.1)
+            .with_truncate(0.1)
+            .with_bit_flip(0.1);
+        let mut transport = FaultyTransport::new(Loopback::default(), seed).with_faults(faults);
+
+        let mut received = Vec::new();
+        for frame in frames() {
+            transport.send(frame).unwrap();
+            while let Ok(frame) = transport.receive() {
+                received.push(frame);
+            }
+        }
+        (received, transport.stats())
+    }
+
+    #[test]
+    fn test_same_seed_same_faults() {
+        let (received, stats) = run(7);
+        assert_eq!(run(7), (received.clone(), stats));
+        assert_ne!(run(8).0, received);
+
+        for counts in [stats.sent, stats.received] {
+            assert!(counts.dropped > 0);
+            assert!(counts.duplicated > 0);
+            assert!(counts.reordered > 0);
+            assert!(counts.truncated > 0);
+            assert!(counts.bit_flipped > 0);
+        }
+        assert_eq!(stats.sent.frames, 200);
+        assert_ne!(received, frames().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_no_faults_pass_frames_unchanged() {
+        let mut transport = FaultyTransport::new(Loopback::default(), 1);
+        for frame in frames() {
+            transport.send(frame.clone()).unwrap();
+            assert_eq!(transport.receive().unwrap(), frame);
+        }
+        assert_eq!(transport.stats().sent.faults(), 0);
+        assert_eq!(transport.stats().received.frames, 200);
+    }
+
+    #[test]
+    fn test_dropped_frame_times_out() {
+        let mut transport = FaultyTransport::new(Loopback::default(), 1)
+            .with_send_faults(Faults::default().with_drop(1.0));
+
+        transport.send(vec![1, 2, 3]).unwrap();
+        assert!(transport.receive().is_err());
+        assert!(transport.into_inner().sent.is_empty());
+    }
+
+    #[test]
+    fn test_reordered_frame_follows_next() {
+        let mut transport = FaultyTransport::new(Loopback::default(), 1)
+            .with_send_faults(Faults::default().with_reorder(1.0));
+
+        transport.send(vec![1]).unwrap();
+        transport.send(vec![2]).unwrap();
+        // the third frame is held back as well, until nothing else is sent
+        transport.send(vec![3]).unwrap();
+        assert_eq!(transport.receive().unwrap(), [2]);
+        assert_eq!(transport.stats().sent.reordered, 2);
+        assert_eq!(transport.into_inner().sent, [vec![2], vec![1], vec![3]]);
+    }
+
+    #[test]
+    fn test_reordered_response_is_released_on_timeout() {
+        let mut transport = FaultyTransport::new(Loopback::default(), 1)
+            .with_receive_faults(Faults::default().with_reorder(1.0));
+
+        transport.send(vec![1]).unwrap();
+        assert_eq!(transport.receive().unwrap(), [1]);
+        assert!(transport.receive().is_err());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_delay_survives_cancel() {
+        use crate::transport::smp::SmpTransportAsync;
+        use async_trait::async_trait;
+
+        struct AsyncLoopback(Loopback);
+
+        #[async_trait]
+        impl SmpTransportAsync for AsyncLoopback {
+            async fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+                SmpTransport::send(&mut self.0, frame)
+            }
+
+            async fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+                SmpTransport::send(&mut self.0, frame)
+            }
+
+            async fn receive(&mut self) -> Result<Vec<u8>, Error> {
+                SmpTransport::receive(&mut self.0)
+            }
+        }
+
+        let faults = Faults::default().with_delay(1.0, Duration::from_millis(200));
+        let mut transport = FaultyTransportAsync::new(AsyncLoopback(Loopback::default()), 3)
+            .with_receive_faults(faults);
+
+        SmpTransportAsync::send(&mut transport, vec![5])
+            .await
+            .unwrap();
+        let first = tokio::time::timeout(
+            Duration::from_millis(1),
+            SmpTransportAsync::receive(&mut transport),
+        )
+        .await;
+        assert!(first.is_err());
+
+        let frame = SmpTransportAsync::receive(&mut transport).await.unwrap();
+        assert_eq!(frame, [5]);
+        assert_eq!(transport.stats().received.delayed, 1);
+    }
+}
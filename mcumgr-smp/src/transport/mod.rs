@@ -5,6 +5,24 @@
 #[cfg(any(feature = "transport-udp", feature = "transport-udp-async"))]
 pub mod udp;
 
+/// Serial transport implementation
+#[cfg(any(feature = "transport-serial", feature = "transport-serial-async"))]
+pub mod serial;
+
+/// Line based framing used by the serial (console) transport
+#[cfg(feature = "transport-serial")]
+pub mod smp_framing;
+
 pub mod error;
 
+/// Retry, timeout and backoff policy for any transport
+pub mod retry;
+
+/// Seeded fault injection to test clients and devices on lossy links
+pub mod faulty;
+
+/// Record the frames of a transport to a log and replay them in tests
+#[cfg(feature = "transport-record")]
+pub mod record;
+
 pub mod smp;
@@ -0,0 +1,581 @@
+//! Recording and replay of the frames of a transport.
+//!
+//! [RecordingTransport] and [RecordingTransportAsync] wrap a transport and write every frame
+//! sent and received, and every receive that timed out, with its time to a JSON lines log:
+//!
+//! ```text
+//! {"time_us":0,"event":"start","mtu":1232}
+//! {"time_us":112,"event":"sent","frame":"000000010001002aa0"}
+//! {"time_us":5310,"event":"received","frame":"010000060001002aa1626463"}
+//! ```
+//!
+//! [ReplayTransport] answers the requests of a client with the recorded responses in order,
+//! so a problem seen once with a real device can be reproduced in a test without it.
+//! Sequence numbers of the responses are rewritten to those of the replayed requests.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::transport::error::Error;
+use crate::transport::smp::{SmpTransport, DEFAULT_MTU};
+
+/// Offset of the sequence number in the SMP header
+const SEQUENCE_OFFSET: usize = 6;
+
+/// Entry of a log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /// microseconds since the recording started
+    pub time_us: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// first entry, the frame size of the recorded transport
+    Start { mtu: usize },
+    Sent {
+        #[serde(with = "hex_frame")]
+        frame: Vec<u8>,
+    },
+    Received {
+        #[serde(with = "hex_frame")]
+        frame: Vec<u8>,
+    },
+    /// a receive returned a timeout
+    Timeout,
+}
+
+/// Frames as hex strings, readable in the log
+mod hex_frame {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(frame: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(frame))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        hex::decode(hex).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Read a log written by a recording transport
+pub fn read_records(reader: impl BufRead) -> io::Result<Vec<Record>> {
+    let mut records = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", number + 1, err),
+            )
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn is_timeout(err: &Error) -> bool {
+    matches!(err, Error::Io(err) if err.kind() == io::ErrorKind::TimedOut)
+}
+
+/// Writes the log, shared by the blocking and async wrappers
+struct Recorder {
+    writer: Box<dyn Write + Send + Sync>,
+    start: Instant,
+}
+
+impl Recorder {
+    fn new(mut writer: Box<dyn Write + Send + Sync>, mtu: usize) -> io::Result<Self> {
+        let start = Record {
+            time_us: 0,
+            event: Event::Start { mtu },
+        };
+        write_record(&mut writer, &start)?;
+        Ok(Self {
+            writer,
+            start: Instant::now(),
+        })
+    }
+
+    fn record(&mut self, event: Event) -> Result<(), Error> {
+        let record = Record {
+            time_us: self.start.elapsed().as_micros() as u64,
+            event,
+        };
+        Ok(write_record(&mut self.writer, &record)?)
+    }
+
+    fn sent(&mut self, frame: &[u8]) -> Result<(), Error> {
+        self.record(Event::Sent {
+            frame: frame.to_vec(),
+        })
+    }
+
+    /// Log the result of a receive, other errors than timeouts are not recorded
+    fn received(&mut self, result: &Result<Vec<u8>, Error>) -> Result<(), Error> {
+        match result {
+            Ok(frame) => self.record(Event::Received {
+                frame: frame.clone(),
+            }),
+            Err(err) if is_timeout(err) => self.record(Event::Timeout),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+/// One line per record, flushed so the log is complete when the program is killed
+fn write_record(writer: &mut dyn Write, record: &Record) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, record)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+fn create_log(path: &Path) -> io::Result<Box<dyn Write + Send + Sync>> {
+    Ok(Box::new(BufWriter::new(File::create(path)?)))
+}
+
+/// Blocking transport that logs the frames of another one
+pub struct RecordingTransport<T> {
+    transport: T,
+    recorder: Recorder,
+}
+
+impl<T: SmpTransport> RecordingTransport<T> {
+    /// Write the log to `writer`
+    pub fn new(transport: T, writer: impl Write + Send + Sync + 'static) -> io::Result<Self> {
+        let recorder = Recorder::new(Box::new(writer), transport.mtu())?;
+        Ok(Self {
+            transport,
+            recorder,
+        })
+    }
+
+    /// Write the log to a new file at `path`
+    pub fn create(transport: T, path: impl AsRef<Path>) -> io::Result<Self> {
+        let recorder = Recorder::new(create_log(path.as_ref())?, transport.mtu())?;
+        Ok(Self {
+            transport,
+            recorder,
+        })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+impl<T: SmpTransport> SmpTransport for RecordingTransport<T> {
+    fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        self.recorder.sent(&frame)?;
+        self.transport.send(frame)
+    }
+
+    fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        self.recorder.sent(&frame)?;
+        self.transport.send_to(frame)
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, Error> {
+        let result = self.transport.receive();
+        self.recorder.received(&result)?;
+        result
+    }
+
+    fn mtu(&self) -> usize {
+        self.transport.mtu()
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.transport.set_timeout(timeout)
+    }
+}
+
+/// Answers requests with the responses of a log, for the blocking and async traits.
+///
+/// Every sent frame must match the next recorded one apart from the sequence number,
+/// unless checking is turned off with [ReplayTransport::with_strict].
+/// A receive without a recorded response before the next request times out at once,
+/// just like the receive did while recording.
+pub struct ReplayTransport {
+    records: Vec<Record>,
+    next: usize,
+    mtu: usize,
+    strict: bool,
+    /// recorded sequence number to the one of the replayed request
+    sequences: HashMap<u8, u8>,
+}
+
+impl ReplayTransport {
+    pub fn new(records: Vec<Record>) -> Self {
+        let mtu = records
+            .iter()
+            .find_map(|record| match record.event {
+                Event::Start { mtu } => Some(mtu),
+                _ => None,
+            })
+            .unwrap_or(DEFAULT_MTU);
+        Self {
+            records,
+            next: 0,
+            mtu,
+            strict: true,
+            sequences: HashMap::new(),
+        }
+    }
+
+    /// Replay the log at `path`
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let records = read_records(BufReader::new(File::open(path)?))?;
+        Ok(Self::new(records))
+    }
+
+    /// Check the sent frames against the recorded ones, on by default.
+    /// Without checks a sent frame only moves the replay to the next recorded response.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Recorded frames sent or received that were not replayed yet
+    pub fn remaining(&self) -> usize {
+        self.records[self.next..]
+            .iter()
+            .filter(|record| matches!(record.event, Event::Sent { .. } | Event::Received { .. }))
+            .count()
+    }
+
+    fn next_event(&mut self) -> Option<&Event> {
+        while let Some(record) = self.records.get(self.next) {
+            if !matches!(record.event, Event::Start { .. }) {
+                return Some(&self.records[self.next].event);
+            }
+            self.next += 1;
+        }
+        None
+    }
+
+    fn replay_send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        // the client stopped waiting before the recorded timeout
+        while matches!(self.next_event(), Some(Event::Timeout)) {
+            self.next += 1;
+        }
+        let strict = self.strict;
+        let recorded = match self.next_event() {
+            Some(Event::Sent { frame }) => frame.clone(),
+            _ if strict => {
+                return Err(mismatch(format!(
+                    "{} was not sent in the recording",
+                    hex::encode(&frame)
+                )))
+            }
+            _ => return Ok(()),
+        };
+        self.next += 1;
+
+        if strict && without_sequence(&recorded) != without_sequence(&frame) {
+            return Err(mismatch(format!(
+                "sent {}, recorded {}",
+                hex::encode(&frame),
+                hex::encode(&recorded)
+            )));
+        }
+        if let (Some(&from), Some(&to)) =
+            (recorded.get(SEQUENCE_OFFSET), frame.get(SEQUENCE_OFFSET))
+        {
+            self.sequences.insert(from, to);
+        }
+        Ok(())
+    }
+
+    fn replay_receive(&mut self) -> Result<Vec<u8>, Error> {
+        let timeout = Err(io::Error::from(io::ErrorKind::TimedOut).into());
+        let mut frame = match self.next_event() {
+            Some(Event::Received { frame }) => frame.clone(),
+            Some(Event::Timeout) => {
+                self.next += 1;
+                return timeout;
+            }
+            // the next request or the end of the log
+            _ => return timeout,
+        };
+        self.next += 1;
+
+        if let Some(sequence) = frame.get_mut(SEQUENCE_OFFSET) {
+            if let Some(&replayed) = self.sequences.get(sequence) {
+                *sequence = replayed;
+            }
+        }
+        Ok(frame)
+    }
+}
+
+fn without_sequence(frame: &[u8]) -> (&[u8], &[u8]) {
+    match frame.len() > SEQUENCE_OFFSET {
+        true => (&frame[..SEQUENCE_OFFSET], &frame[SEQUENCE_OFFSET + 1..]),
+        false => (frame, &[]),
+    }
+}
+
+fn mismatch(message: String) -> Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("replay diverged from the recording: {}", message),
+    )
+    .into()
+}
+
+impl SmpTransport for ReplayTransport {
+    fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        self.replay_send(frame)
+    }
+
+    fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        self.replay_send(frame)
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, Error> {
+        self.replay_receive()
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn set_timeout(&mut self, _timeout: Option<Duration>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+pub use self::record_async::RecordingTransportAsync;
+
+#[cfg(feature = "async")]
+mod record_async {
+    use super::*;
+    use crate::transport::smp::SmpTransportAsync;
+    use async_trait::async_trait;
+
+    /// Async transport that logs the frames of another one.
+    ///
+    /// A receive that is cancelled, e.g. by the timeout of a retry policy, is not recorded,
+    /// the replay times out the same way when the next recorded frame is a request.
+    pub struct RecordingTransportAsync<T> {
+        transport: T,
+        recorder: Recorder,
+    }
+
+    impl<T: SmpTransportAsync> RecordingTransportAsync<T> {
+        /// Write the log to `writer`
+        pub fn new(transport: T, writer: impl Write + Send + Sync + 'static) -> io::Result<Self> {
+            let recorder = Recorder::new(Box::new(writer), transport.mtu())?;
+            Ok(Self {
+                transport,
+                recorder,
+            })
+        }
+
+        /// Write the log to a new file at `path`
+        pub fn create(transport: T, path: impl AsRef<Path>) -> io::Result<Self> {
+            let recorder = Recorder::new(create_log(path.as_ref())?, transport.mtu())?;
+            Ok(Self {
+                transport,
+                recorder,
+            })
+        }
+
+        pub fn into_inner(self) -> T {
+            self.transport
+        }
+    }
+
+    #[async_trait]
+    impl<T: SmpTransportAsync + Send> SmpTransportAsync for RecordingTransportAsync<T> {
+        async fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.recorder.sent(&frame)?;
+            self.transport.send(frame).await
+        }
+
+        async fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.recorder.sent(&frame)?;
+            self.transport.send_to(frame).await
+        }
+
+        async fn receive(&mut self) -> Result<Vec<u8>, Error> {
+            let result = self.transport.receive().await;
+            self.recorder.received(&result)?;
+            result
+        }
+
+        fn mtu(&self) -> usize {
+            self.transport.mtu()
+        }
+    }
+
+    #[async_trait]
+    impl SmpTransportAsync for ReplayTransport {
+        async fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.replay_send(frame)
+        }
+
+        async fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.replay_send(frame)
+        }
+
+        async fn receive(&mut self) -> Result<Vec<u8>, Error> {
+            self.replay_receive()
+        }
+
+        fn mtu(&self) -> usize {
+            self.mtu
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    /// Answers every request with its header and the payload reversed, the frame with `[0xff]` is lost
+    #[derive(Default)]
+    struct Device {
+        responses: VecDeque<Vec<u8>>,
+    }
+
+    impl SmpTransport for Device {
+        fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            if frame[8..] != [0xff] {
+                let mut response = frame[..8].to_vec();
+                response.extend(frame[8..].iter().rev());
+                self.responses.push_back(response);
+            }
+            Ok(())
+        }
+
+        fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.send(frame)
+        }
+
+        fn receive(&mut self) -> Result<Vec<u8>, Error> {
+            self.responses
+                .pop_front()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut).into())
+        }
+
+        fn mtu(&self) -> usize {
+            512
+        }
+    }
+
+    /// Log kept in memory
+    #[derive(Clone, Default)]
+    struct Log(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Log {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Log {
+        fn records(&self) -> Vec<Record> {
+            read_records(&self.0.lock().unwrap()[..]).unwrap()
+        }
+    }
+
+    fn request(sequence: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![2, 0, 0, payload.len() as u8, 0, 0, sequence, 0];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// Two requests, the second one is lost and resent
+    fn record() -> Log {
+        let log = Log::default();
+        let mut transport = RecordingTransport::new(Device::default(), log.clone()).unwrap();
+
+        transport.send(request(7, &[1, 2, 3])).unwrap();
+        transport.receive().unwrap();
+        transport.send(request(8, &[0xff])).unwrap();
+        assert!(transport.receive().is_err());
+        transport.send(request(8, &[4, 5])).unwrap();
+        transport.receive().unwrap();
+        log
+    }
+
+    #[test]
+    fn test_log_format() {
+        let log = record();
+        let text = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[0], r#"{"time_us":0,"event":"start","mtu":512}"#);
+        assert!(lines[1].ends_with(r#""event":"sent","frame":"0200000300000700010203"}"#));
+        assert!(lines[4].ends_with(r#""event":"timeout"}"#));
+
+        let records = log.records();
+        assert_eq!(
+            records[2].event,
+            Event::Received {
+                frame: request(7, &[3, 2, 1])
+            }
+        );
+        assert!(records.windows(2).all(|r| r[0].time_us <= r[1].time_us));
+    }
+
+    #[test]
+    fn test_replay_rewrites_sequence_numbers() {
+        let mut replay = ReplayTransport::new(record().records());
+        assert_eq!(replay.mtu(), 512);
+
+        replay.send(request(0, &[1, 2, 3])).unwrap();
+        assert_eq!(replay.receive().unwrap(), request(0, &[3, 2, 1]));
+        replay.send(request(1, &[0xff])).unwrap();
+        assert!(replay.receive().is_err());
+        replay.send(request(1, &[4, 5])).unwrap();
+        assert_eq!(replay.receive().unwrap(), request(1, &[5, 4]));
+
+        assert_eq!(replay.remaining(), 0);
+        // the device has nothing more to say
+        assert!(replay.receive().is_err());
+    }
+
+    #[test]
+    fn test_replay_detects_divergence() {
+        let mut replay = ReplayTransport::new(record().records());
+        let err = replay.send(request(0, &[9, 9, 9])).unwrap_err();
+        assert!(err.to_string().contains("diverged"), "{}", err);
+
+        let mut replay = ReplayTransport::new(record().records()).with_strict(false);
+        replay.send(request(0, &[9, 9, 9])).unwrap();
+        assert_eq!(replay.receive().unwrap(), request(0, &[3, 2, 1]));
+    }
+
+    #[test]
+    fn test_receive_before_next_request_times_out() {
+        let mut replay = ReplayTransport::new(record().records());
+        replay.send(request(0, &[1, 2, 3])).unwrap();
+        replay.receive().unwrap();
+        // a cancelled receive of the async client looks the same in the log
+        assert!(replay.receive().is_err());
+        replay.send(request(1, &[0xff])).unwrap();
+    }
+}
@@ -0,0 +1,560 @@
+//! Retries with timeout and backoff for any transport.
+//!
+//! [RetryTransport] and [RetryTransportAsync] wrap a transport and resend requests whose
+//! response doesn't arrive in time, as long as the [RetryPolicy] considers them idempotent.
+//! The wrappers remember every request until its response arrives. Responses nobody waits for,
+//! e.g. the late response of a request that was resent, are dropped, so the wrappers are meant
+//! for clients and not for servers that receive requests.
+
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::time::Duration;
+
+use crate::transport::error::Error;
+use crate::transport::smp::SmpTransport;
+use crate::{Group, OpCode, SmpFrame};
+
+/// Requests remembered without a response, older ones are forgotten
+const MAX_OUTSTANDING: usize = 256;
+
+/// When and how often requests are resent
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts per request including the first one
+    pub max_attempts: u32,
+    /// Wait for a response per attempt, `None` keeps the timeout of the transport
+    pub timeout: Option<Duration>,
+    /// Pause before the first resend, doubled for every further one
+    pub backoff: Duration,
+    /// Upper limit of the pause
+    pub max_backoff: Duration,
+    /// Share of the pause, 0.0 to 1.0, that is randomly added or removed so that
+    /// many clients don't resend at the same time
+    pub jitter: f64,
+    /// Write requests that may be resent as `(group, command)`, read requests are always idempotent
+    pub idempotent_writes: Vec<(Group, u8)>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            timeout: Some(Duration::from_secs(5)),
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            jitter: 0.2,
+            idempotent_writes: vec![
+                // echo
+                (Group::Default, 0),
+                // image state, setting the same state again has no further effect
+                (Group::ApplicationManagement, 0),
+                // image and file upload, chunks carry their offset and the device reports its own
+                (Group::ApplicationManagement, 1),
+                (Group::FileManagement, 0),
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Send every request once
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Duration, max_backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Allow resending a write request
+    pub fn with_idempotent_write(mut self, group: Group, command: u8) -> Self {
+        self.idempotent_writes.push((group, command));
+        self
+    }
+
+    /// The request can be sent again without changing the outcome
+    pub fn is_idempotent(&self, operation: OpCode, group: Group, command: u8) -> bool {
+        match operation {
+            OpCode::ReadRequest => true,
+            OpCode::WriteRequest => self.idempotent_writes.contains(&(group, command)),
+            _ => false,
+        }
+    }
+
+    /// Pause before the given resend, starting at 1
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(31);
+        let pause = self
+            .backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return pause;
+        }
+        // uniform in -1.0..=1.0
+        let random =
+            RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64 * 2.0 - 1.0;
+        pause.mul_f64(1.0 + jitter * random)
+    }
+}
+
+/// Request that waits for its response
+struct Request {
+    sequence: u8,
+    frame: Vec<u8>,
+    idempotent: bool,
+    attempts: u32,
+}
+
+/// Requests sent through a wrapper that were not answered yet
+#[derive(Default)]
+struct Outstanding {
+    requests: VecDeque<Request>,
+}
+
+fn header(frame: &[u8]) -> Option<SmpFrame<()>> {
+    SmpFrame::decode(frame, |_| Ok(())).ok()
+}
+
+impl Outstanding {
+    fn sent(&mut self, policy: &RetryPolicy, frame: &[u8]) {
+        let Some(header) = header(frame) else {
+            return;
+        };
+        if !matches!(header.operation, OpCode::ReadRequest | OpCode::WriteRequest) {
+            return;
+        }
+
+        self.requests.retain(|r| r.sequence != header.sequence);
+        if self.requests.len() == MAX_OUTSTANDING {
+            self.requests.pop_front();
+        }
+        self.requests.push_back(Request {
+            sequence: header.sequence,
+            frame: frame.to_vec(),
+            idempotent: policy.is_idempotent(header.operation, header.group, header.command),
+            attempts: 1,
+        });
+    }
+
+    /// The frame answers a request, other responses are stale or duplicates
+    fn answers(&mut self, frame: &[u8]) -> bool {
+        let Some(header) = header(frame) else {
+            // let the caller report the broken frame
+            return true;
+        };
+        if !matches!(
+            header.operation,
+            OpCode::ReadResponse | OpCode::WriteResponse
+        ) {
+            return true;
+        }
+
+        let before = self.requests.len();
+        self.requests.retain(|r| r.sequence != header.sequence);
+        self.requests.len() != before
+    }
+
+    /// Frames to resend after a timeout and the pause before, `None` gives up on all requests
+    fn retry(&mut self, policy: &RetryPolicy) -> Option<(Duration, Vec<Vec<u8>>)> {
+        let retryable = !self.requests.is_empty()
+            && self
+                .requests
+                .iter()
+                .all(|r| r.idempotent && r.attempts < policy.max_attempts);
+        if !retryable {
+            self.requests.clear();
+            return None;
+        }
+
+        let mut retry = 0;
+        let frames = self
+            .requests
+            .iter_mut()
+            .map(|r| {
+                retry = retry.max(r.attempts);
+                r.attempts += 1;
+                r.frame.clone()
+            })
+            .collect();
+        Some((policy.backoff(retry), frames))
+    }
+}
+
+fn is_timeout(err: &Error) -> bool {
+    matches!(err, Error::Io(err) if matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    ))
+}
+
+/// Blocking transport that resends requests according to a [RetryPolicy]
+pub struct RetryTransport<T> {
+    transport: T,
+    policy: RetryPolicy,
+    outstanding: Outstanding,
+}
+
+impl<T: SmpTransport> RetryTransport<T> {
+    /// Fails if the policy has a timeout the transport doesn't support
+    pub fn new(mut transport: T, policy: RetryPolicy) -> Result<Self, Error> {
+        if policy.timeout.is_some() {
+            transport.set_timeout(policy.timeout)?;
+        }
+        Ok(Self {
+            transport,
+            policy,
+            outstanding: Outstanding::default(),
+        })
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+impl<T: SmpTransport> SmpTransport for RetryTransport<T> {
+    fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        self.outstanding.sent(&self.policy, &frame);
+        self.transport.send(frame)
+    }
+
+    fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        self.outstanding.sent(&self.policy, &frame);
+        self.transport.send_to(frame)
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, Error> {
+        loop {
+            match self.transport.receive() {
+                Ok(frame) if self.outstanding.answers(&frame) => return Ok(frame),
+                Ok(_) => {}
+                Err(err) if is_timeout(&err) => {
+                    let Some((pause, frames)) = self.outstanding.retry(&self.policy) else {
+                        return Err(err);
+                    };
+                    std::thread::sleep(pause);
+                    for frame in frames {
+                        self.transport.send(frame)?;
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        self.transport.mtu()
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.transport.set_timeout(timeout)?;
+        self.policy.timeout = timeout;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+pub use self::retry_async::RetryTransportAsync;
+
+#[cfg(feature = "async")]
+mod retry_async {
+    use super::*;
+    use crate::transport::smp::SmpTransportAsync;
+    use async_trait::async_trait;
+
+    /// Async transport that resends requests according to a [RetryPolicy].
+    ///
+    /// The timeout of the policy is applied to every receive, whatever the transport supports.
+    pub struct RetryTransportAsync<T> {
+        transport: T,
+        policy: RetryPolicy,
+        outstanding: Outstanding,
+    }
+
+    impl<T: SmpTransportAsync> RetryTransportAsync<T> {
+        pub fn new(transport: T, policy: RetryPolicy) -> Self {
+            Self {
+                transport,
+                policy,
+                outstanding: Outstanding::default(),
+            }
+        }
+
+        pub fn policy(&self) -> &RetryPolicy {
+            &self.policy
+        }
+
+        pub fn into_inner(self) -> T {
+            self.transport
+        }
+
+        async fn receive_once(&mut self) -> Result<Vec<u8>, Error> {
+            let Some(timeout) = self.policy.timeout else {
+                return self.transport.receive().await;
+            };
+            match tokio::time::timeout(timeout, self.transport.receive()).await {
+                Ok(res) => res,
+                Err(elapsed) => Err(io::Error::new(io::ErrorKind::TimedOut, elapsed).into()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl<T: SmpTransportAsync + Send> SmpTransportAsync for RetryTransportAsync<T> {
+        async fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.outstanding.sent(&self.policy, &frame);
+            self.transport.send(frame).await
+        }
+
+        async fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.outstanding.sent(&self.policy, &frame);
+            self.transport.send_to(frame).await
+        }
+
+        async fn receive(&mut self) -> Result<Vec<u8>, Error> {
+            loop {
+                match self.receive_once().await {
+                    Ok(frame) if self.outstanding.answers(&frame) => return Ok(frame),
+                    Ok(_) => {}
+                    Err(err) if is_timeout(&err) => {
+                        let Some((pause, frames)) = self.outstanding.retry(&self.policy) else {
+                            return Err(err);
+                        };
+                        tokio::time::sleep(pause).await;
+                        for frame in frames {
+                            self.transport.send(frame).await?;
+                        }
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        fn mtu(&self) -> usize {
+            self.transport.mtu()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every request it receives, except the lost ones
+    #[derive(Default)]
+    struct LossyTransport {
+        /// indices of sent frames that never arrive
+        lost: Vec<usize>,
+        sent: Vec<Vec<u8>>,
+        responses: VecDeque<Vec<u8>>,
+    }
+
+    impl SmpTransport for LossyTransport {
+        fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            let index = self.sent.len();
+            self.sent.push(frame.clone());
+            if !self.lost.contains(&index) {
+                let mut response = header(&frame).unwrap();
+                response.operation = match response.operation {
+                    OpCode::ReadRequest => OpCode::ReadResponse,
+                    _ => OpCode::WriteResponse,
+                };
+                self.responses
+                    .push_back(response.encode(|_| Ok::<_, ()>([])).unwrap());
+            }
+            Ok(())
+        }
+
+        fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.send(frame)
+        }
+
+        fn receive(&mut self) -> Result<Vec<u8>, Error> {
+            self.responses
+                .pop_front()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut).into())
+        }
+
+        fn set_timeout(&mut self, _timeout: Option<Duration>) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn request(operation: OpCode, group: Group, sequence: u8, command: u8) -> Vec<u8> {
+        SmpFrame::new(operation, sequence, group, command, ())
+            .encode(|_| Ok::<_, ()>([]))
+            .unwrap()
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::default().with_backoff(Duration::ZERO, Duration::ZERO)
+    }
+
+    fn sequence(frame: &[u8]) -> u8 {
+        header(frame).unwrap().sequence
+    }
+
+    #[test]
+    fn test_lost_read_is_resent() {
+        let transport = LossyTransport {
+            lost: vec![0, 1],
+            ..Default::default()
+        };
+        let mut transport = RetryTransport::new(transport, policy()).unwrap();
+
+        transport
+            .send(request(OpCode::ReadRequest, Group::Default, 7, 6))
+            .unwrap();
+        assert_eq!(sequence(&transport.receive().unwrap()), 7);
+        assert_eq!(transport.into_inner().sent.len(), 3);
+    }
+
+    #[test]
+    fn test_gives_up_after_max_attempts() {
+        let transport = LossyTransport {
+            lost: vec![0, 1, 2],
+            ..Default::default()
+        };
+        let mut transport = RetryTransport::new(transport, policy()).unwrap();
+
+        transport
+            .send(request(OpCode::ReadRequest, Group::Default, 1, 6))
+            .unwrap();
+        assert!(is_timeout(&transport.receive().unwrap_err()));
+        assert_eq!(transport.into_inner().sent.len(), 3);
+    }
+
+    #[test]
+    fn test_reset_is_not_resent() {
+        let transport = LossyTransport {
+            lost: vec![0],
+            ..Default::default()
+        };
+        let mut transport = RetryTransport::new(transport, policy()).unwrap();
+
+        transport
+            .send(request(OpCode::WriteRequest, Group::Default, 1, 5))
+            .unwrap();
+        assert!(is_timeout(&transport.receive().unwrap_err()));
+        assert_eq!(transport.into_inner().sent.len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_response_is_dropped() {
+        let mut transport = RetryTransport::new(LossyTransport::default(), policy()).unwrap();
+
+        transport
+            .send(request(
+                OpCode::WriteRequest,
+                Group::ApplicationManagement,
+                1,
+                1,
+            ))
+            .unwrap();
+        // a duplicate of the first response arrives before the second one
+        let first = transport.receive().unwrap();
+        transport.transport.responses.push_back(first);
+        transport
+            .send(request(
+                OpCode::WriteRequest,
+                Group::ApplicationManagement,
+                2,
+                1,
+            ))
+            .unwrap();
+
+        assert_eq!(sequence(&transport.receive().unwrap()), 2);
+        assert!(transport.transport.responses.is_empty());
+    }
+
+    #[test]
+    fn test_backoff_grows_up_to_limit() {
+        let policy = RetryPolicy::default()
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(350))
+            .with_jitter(0.0);
+        let pauses: Vec<u128> = (1..=4)
+            .map(|retry| policy.backoff(retry).as_millis())
+            .collect();
+        assert_eq!(pauses, [100, 200, 350, 350]);
+
+        let policy = policy.with_jitter(0.5);
+        for _ in 0..100 {
+            let pause = policy.backoff(1).as_millis();
+            assert!((50..=150).contains(&pause), "{pause}");
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_timeout_resends() {
+        use crate::transport::smp::SmpTransportAsync;
+        use async_trait::async_trait;
+
+        /// Never answers the first request
+        struct SilentOnce(LossyTransport);
+
+        #[async_trait]
+        impl SmpTransportAsync for SilentOnce {
+            async fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+                SmpTransport::send(&mut self.0, frame)
+            }
+
+            async fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+                SmpTransport::send(&mut self.0, frame)
+            }
+
+            async fn receive(&mut self) -> Result<Vec<u8>, Error> {
+                match self.0.responses.pop_front() {
+                    Some(frame) => Ok(frame),
+                    None => std::future::pending().await,
+                }
+            }
+        }
+
+        let transport = SilentOnce(LossyTransport {
+            lost: vec![0],
+            ..Default::default()
+        });
+        let policy = policy().with_timeout(Some(Duration::from_millis(20)));
+        let mut transport = RetryTransportAsync::new(transport, policy);
+
+        SmpTransportAsync::send(
+            &mut transport,
+            request(OpCode::ReadRequest, Group::Default, 3, 6),
+        )
+        .await
+        .unwrap();
+        let response = SmpTransportAsync::receive(&mut transport).await.unwrap();
+        assert_eq!(sequence(&response), 3);
+        assert_eq!(transport.into_inner().0.sent.len(), 2);
+    }
+}
@@ -0,0 +1,12 @@
+/// Frame size of the Zephyr UART transport with the default `CONFIG_MCUMGR_TRANSPORT_UART_MTU`
+pub const SERIAL_MTU: usize = 256;
+
+#[cfg(feature = "transport-serial-async")]
+pub mod serial_async;
+#[cfg(feature = "transport-serial-async")]
+pub use serial_async::SerialTransportAsync;
+
+#[cfg(feature = "transport-serial")]
+pub mod serial_sync;
+#[cfg(feature = "transport-serial")]
+pub use serial_sync::SerialTransport;
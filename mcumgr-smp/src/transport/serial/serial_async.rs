@@ -0,0 +1,151 @@
+// Author: Sascha Zenglein <zenglein@gessler.de>
+// Copyright (c) 2023 Gessler GmbH.
+
+use crate::transport::error::Error;
+use crate::transport::serial::SERIAL_MTU;
+use crate::transport::smp::SmpTransportAsync;
+use crate::transport::smp_framing::{SmpTransportDecoder, SmpTransportEncoder, MAX_LINE_LEN};
+use async_trait::async_trait;
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::{timeout, Duration};
+#[cfg(unix)]
+use tokio_serial::SerialPort;
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+const BUF_SIZE: usize = 256;
+
+pub struct SerialTransportAsync {
+    port: SerialStream,
+    decoder: SmpTransportDecoder,
+    timeout: Option<Duration>,
+    /// the other end of a pseudo terminal, kept open for the peer
+    pty: Option<SerialStream>,
+}
+
+impl SerialTransportAsync {
+    /// Open the serial device at `path`, e.g. `/dev/ttyACM0` or `COM3`.  
+    /// Must be called from within a tokio runtime.
+    pub fn new(path: &str, baud_rate: u32, timeout: Option<Duration>) -> Result<Self, io::Error> {
+        let port = tokio_serial::new(path, baud_rate).open_native_async()?;
+        Ok(Self::from_stream(port, timeout))
+    }
+
+    /// Use an already opened serial stream
+    pub fn from_stream(port: SerialStream, timeout: Option<Duration>) -> Self {
+        Self {
+            port,
+            decoder: SmpTransportDecoder::new(),
+            timeout,
+            pty: None,
+        }
+    }
+
+    /// Create a pseudo terminal, e.g. to serve a simulated device.
+    /// Returns the transport on one end and the path of the other end that a client opens.
+    #[cfg(unix)]
+    pub fn new_pty(timeout: Option<Duration>) -> Result<(Self, String), io::Error> {
+        let (port, peer) = SerialStream::pair()?;
+        let path = peer
+            .name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "pty has no name"))?;
+        let mut transport = Self::from_stream(port, timeout);
+        // reads fail once the last handle of the peer end is closed
+        transport.pty = Some(peer);
+        Ok((transport, path))
+    }
+
+    /// Hand shell and log output that arrives between SMP frames to `handler`
+    pub fn set_console_handler(&mut self, handler: impl FnMut(&[u8]) + Send + Sync + 'static) {
+        self.decoder.set_console_handler(handler);
+    }
+
+    async fn receive_frame(&mut self) -> Result<Vec<u8>, Error> {
+        loop {
+            if let Some(frame) = self.decoder.next_frame() {
+                return Ok(frame?);
+            }
+
+            let mut chunk = [0u8; BUF_SIZE];
+            let len = self.port.read(&mut chunk).await?;
+            if len == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            self.decoder.input(&chunk[..len]);
+        }
+    }
+}
+
+#[async_trait]
+impl SmpTransportAsync for SerialTransportAsync {
+    async fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        let mut encoder = SmpTransportEncoder::new(&frame);
+        let mut line = [0u8; MAX_LINE_LEN];
+
+        while !encoder.is_complete() {
+            let len = encoder.write_line(&mut line)?;
+            self.port.write_all(&line[..len]).await?;
+        }
+        self.port.flush().await?;
+
+        Ok(())
+    }
+
+    async fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        self.send(frame).await
+    }
+
+    async fn receive(&mut self) -> Result<Vec<u8>, Error> {
+        let Some(dur) = self.timeout else {
+            return self.receive_frame().await;
+        };
+
+        match timeout(dur, self.receive_frame()).await {
+            Ok(res) => res,
+            Err(elapsed) => Err(io::Error::new(io::ErrorKind::TimedOut, elapsed).into()),
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        SERIAL_MTU
+    }
+}
+
+/// Round trip over a pseudo-terminal pair with a fake device on the other end
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_transceive_over_pty() {
+        let (host, device) = SerialStream::pair().unwrap();
+        let timeout = Some(Duration::from_secs(2));
+        let mut host = SerialTransportAsync::from_stream(host, timeout);
+        let mut device = SerialTransportAsync::from_stream(device, timeout);
+
+        let frame: Vec<u8> = (0..=255).cycle().take(300).collect();
+
+        let fake_device = async {
+            let frame = device.receive().await.unwrap();
+            device.send(frame).await.unwrap();
+        };
+        let client = async {
+            host.send(frame.clone()).await.unwrap();
+            host.receive().await.unwrap()
+        };
+
+        let (_, received) = tokio::join!(fake_device, client);
+        assert_eq!(received, frame);
+    }
+
+    #[tokio::test]
+    async fn test_receive_timeout() {
+        let (host, _device) = SerialStream::pair().unwrap();
+        let mut host = SerialTransportAsync::from_stream(host, Some(Duration::from_millis(50)));
+
+        match host.receive().await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            other => panic!("expected timeout, got {other:?}"),
+        }
+    }
+}
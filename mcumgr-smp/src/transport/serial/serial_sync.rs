@@ -0,0 +1,155 @@
+// Author: Sascha Zenglein <zenglein@gessler.de>
+// Copyright (c) 2023 Gessler GmbH.
+
+use crate::transport::error::Error;
+use crate::transport::serial::SERIAL_MTU;
+use crate::transport::smp::SmpTransport;
+use crate::transport::smp_framing::{SmpTransportDecoder, SmpTransportEncoder, MAX_LINE_LEN};
+use serialport::SerialPort;
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const BUF_SIZE: usize = 256;
+
+pub struct SerialTransport {
+    /// [SerialPort] is only Send. The mutex makes the transport Sync,
+    /// it is never locked because every access goes through `&mut self`.
+    port: Mutex<Box<dyn SerialPort>>,
+    decoder: SmpTransportDecoder,
+}
+
+impl SerialTransport {
+    /// Open the serial device at `path`, e.g. `/dev/ttyACM0` or `COM3`
+    pub fn new(path: &str, baud_rate: u32, timeout: Duration) -> Result<Self, Error> {
+        let port = serialport::new(path, baud_rate).timeout(timeout).open()?;
+        Ok(Self::from_port(port))
+    }
+
+    /// Use an already opened serial port
+    pub fn from_port(port: Box<dyn SerialPort>) -> Self {
+        Self {
+            port: Mutex::new(port),
+            decoder: SmpTransportDecoder::new(),
+        }
+    }
+
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.port_mut().set_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Hand shell and log output that arrives between SMP frames to `handler`
+    pub fn set_console_handler(&mut self, handler: impl FnMut(&[u8]) + Send + Sync + 'static) {
+        self.decoder.set_console_handler(handler);
+    }
+
+    fn port_mut(&mut self) -> &mut Box<dyn SerialPort> {
+        // the mutex cannot be poisoned, it is never locked
+        self.port.get_mut().unwrap()
+    }
+}
+
+impl SmpTransport for SerialTransport {
+    fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        let mut encoder = SmpTransportEncoder::new(&frame);
+        let mut line = [0u8; MAX_LINE_LEN];
+        let port = self.port_mut();
+
+        while !encoder.is_complete() {
+            let len = encoder.write_line(&mut line)?;
+            port.write_all(&line[..len])?;
+        }
+        port.flush()?;
+
+        Ok(())
+    }
+
+    fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        self.send(frame)
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, Error> {
+        loop {
+            if let Some(frame) = self.decoder.next_frame() {
+                return Ok(frame?);
+            }
+
+            let mut chunk = [0u8; BUF_SIZE];
+            let len = self.port_mut().read(&mut chunk)?;
+            if len == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            self.decoder.input(&chunk[..len]);
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        SERIAL_MTU
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        // the serial port always needs a timeout
+        let timeout = timeout.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "serial ports need a timeout")
+        })?;
+        self.recv_timeout(timeout)
+    }
+}
+
+/// Round trip over a pseudo-terminal pair with a fake device on the other end
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serialport::TTYPort;
+    use std::thread;
+
+    #[test]
+    fn test_transceive_over_pty() {
+        let (host, device) = TTYPort::pair().unwrap();
+        let mut host = SerialTransport::from_port(Box::new(host));
+        let mut device = SerialTransport::from_port(Box::new(device));
+        host.recv_timeout(Duration::from_secs(2)).unwrap();
+        device.recv_timeout(Duration::from_secs(2)).unwrap();
+
+        // echo every frame back, long enough to span multiple lines.
+        // The port is handed back, closing it early would hang up the pty.
+        let fake_device = thread::spawn(move || {
+            let frame = device.receive().unwrap();
+            device.send(frame).unwrap();
+            device
+        });
+
+        let frame: Vec<u8> = (0..=255).cycle().take(300).collect();
+        host.send(frame.clone()).unwrap();
+        assert_eq!(host.receive().unwrap(), frame);
+
+        fake_device.join().unwrap();
+    }
+
+    #[test]
+    fn test_receive_skips_console_output() {
+        let (host, mut device) = TTYPort::pair().unwrap();
+        let mut host = SerialTransport::from_port(Box::new(host));
+        host.recv_timeout(Duration::from_secs(2)).unwrap();
+        let (console_tx, console_rx) = std::sync::mpsc::channel();
+        host.set_console_handler(move |line| console_tx.send(line.to_vec()).unwrap());
+
+        let frame = vec![1, 0, 0, 2, 0, 0, 7, 0, 0xa0, 0xff];
+        let mut encoder = SmpTransportEncoder::new(&frame);
+        let mut line = [0u8; MAX_LINE_LEN];
+        device
+            .write_all(b"[00:00:01.000,000] <inf> main: booted\n")
+            .unwrap();
+        while !encoder.is_complete() {
+            let len = encoder.write_line(&mut line).unwrap();
+            device.write_all(&line[..len]).unwrap();
+        }
+
+        assert_eq!(host.receive().unwrap(), frame);
+        assert_eq!(
+            console_rx.try_recv().unwrap(),
+            b"[00:00:01.000,000] <inf> main: booted"
+        );
+    }
+}
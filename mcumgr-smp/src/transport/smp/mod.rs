@@ -1,3 +1,6 @@
+/// Frame size assumed for transports that don't know their limit
+pub const DEFAULT_MTU: usize = 256;
+
 #[cfg(feature = "async")]
 pub mod smp_async;
 #[cfg(all(feature = "payload-cbor", feature = "async"))]
@@ -5,6 +8,11 @@ pub use smp_async::cbor::CborSmpTransportAsync;
 #[cfg(feature = "async")]
 pub use smp_async::SmpTransportAsync;
 
+#[cfg(feature = "multiplex-async")]
+pub mod smp_multiplex;
+#[cfg(feature = "multiplex-async")]
+pub use smp_multiplex::MultiplexedClientAsync;
+
 pub mod smp_sync;
 #[cfg(feature = "payload-cbor")]
 pub use smp_sync::cbor::CborSmpTransport;
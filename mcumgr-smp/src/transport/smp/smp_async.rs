@@ -1,4 +1,5 @@
 use crate::transport::error::Error;
+use crate::transport::smp::DEFAULT_MTU;
 use async_trait::async_trait;
 
 #[async_trait]
@@ -11,13 +12,37 @@ pub trait SmpTransportAsync {
 
     /// receive a single frame
     async fn receive(&mut self) -> Result<Vec<u8>, Error>;
+
+    /// largest frame the transport can carry, including the SMP header
+    fn mtu(&self) -> usize {
+        DEFAULT_MTU
+    }
+}
+
+#[async_trait]
+impl<T: SmpTransportAsync + Send + ?Sized> SmpTransportAsync for Box<T> {
+    async fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        (**self).send(frame).await
+    }
+
+    async fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        (**self).send_to(frame).await
+    }
+
+    async fn receive(&mut self) -> Result<Vec<u8>, Error> {
+        (**self).receive().await
+    }
+
+    fn mtu(&self) -> usize {
+        (**self).mtu()
+    }
 }
 
 #[cfg(feature = "payload-cbor")]
 pub mod cbor {
     use crate::transport::error::Error;
     use crate::transport::smp::SmpTransportAsync;
-    use crate::SmpFrame;
+    use crate::{SmpFrame, SmpRequest, SmpResponse};
 
     pub struct CborSmpTransportAsync {
         pub transport: Box<dyn SmpTransportAsync + Sync + Send + 'static>,
@@ -33,6 +58,9 @@ pub mod cbor {
         pub async fn receive(&mut self) -> Result<Vec<u8>, Error> {
             self.transport.receive().await
         }
+        pub fn mtu(&self) -> usize {
+            self.transport.mtu()
+        }
 
         pub async fn transceive(&mut self, frame: Vec<u8>) -> Result<Vec<u8>, Error> {
             self.transport.send(frame).await?;
@@ -46,7 +74,10 @@ pub mod cbor {
             let bytes = frame.encode_with_cbor();
             self.send(bytes).await
         }
-        pub async fn send_to_cbor<T: serde::Serialize>(&mut self, frame: &SmpFrame<T>) -> Result<(), Error> {
+        pub async fn send_to_cbor<T: serde::Serialize>(
+            &mut self,
+            frame: &SmpFrame<T>,
+        ) -> Result<(), Error> {
             let bytes = frame.encode_with_cbor();
             self.send_to(bytes).await
         }
@@ -73,5 +104,19 @@ pub mod cbor {
             self.receive_cbor(check_sequence.then_some(frame.sequence))
                 .await
         }
+
+        /// Send a request and wait for its typed response.
+        ///
+        /// Error responses of the device are returned as [Error::Device].
+        pub async fn call<R: SmpRequest>(
+            &mut self,
+            sequence: u8,
+            request: R,
+        ) -> Result<R::Response, Error> {
+            let frame: SmpFrame<SmpResponse<R::Response>> = self
+                .transceive_cbor(&request.into_frame(sequence), true)
+                .await?;
+            Ok(frame.into_result()?)
+        }
     }
 }
@@ -0,0 +1,411 @@
+//! Async client that shares one transport between concurrent requests.
+//!
+//! The transport is moved into a background task that sends the queued frames and receives
+//! all responses. Sequence numbers are handed out by the client, responses are routed to the
+//! waiting request by sequence number, group and command. Responses nobody waits for, e.g. late
+//! responses of a request that timed out or duplicates, are dropped.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), mcumgr_smp::transport::error::Error> {
+//! use mcumgr_smp::os_management::EchoRequest;
+//! use mcumgr_smp::transport::smp::MultiplexedClientAsync;
+//! use mcumgr_smp::transport::udp::UdpTransportAsync;
+//! use std::time::Duration;
+//!
+//! let transport = UdpTransportAsync::new(&"[2001:db8::1]:1337", None).await?;
+//! let client = MultiplexedClientAsync::new(transport, Some(Duration::from_secs(5)));
+//!
+//! let other = client.clone();
+//! let (a, b) = tokio::join!(
+//!     client.call(EchoRequest { d: "a".to_string() }),
+//!     other.call(EchoRequest { d: "b".to_string() }),
+//! );
+//! assert_eq!(a?.r, "a");
+//! assert_eq!(b?.r, "b");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::transport::error::Error;
+use crate::transport::smp::SmpTransportAsync;
+use crate::{Group, SmpFrame, SmpRequest, SmpResponse, SmpVersion};
+
+/// Frames that can wait for the background task before requests block
+const QUEUE_LEN: usize = 32;
+
+struct Pending {
+    /// tells apart requests that reuse a sequence number
+    id: u64,
+    group: Group,
+    command: u8,
+    reply: oneshot::Sender<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct State {
+    pending: HashMap<u8, Pending>,
+    next_sequence: u8,
+    next_id: u64,
+}
+
+impl State {
+    /// Reserve the next sequence number that is not in use
+    fn register(
+        &mut self,
+        group: Group,
+        command: u8,
+    ) -> Result<(u8, u64, oneshot::Receiver<Vec<u8>>), Error> {
+        let sequence = (0..=u8::MAX)
+            .map(|i| self.next_sequence.wrapping_add(i))
+            .find(|sequence| !self.pending.contains_key(sequence))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::WouldBlock, "all sequence numbers are in use")
+            })?;
+        self.next_sequence = sequence.wrapping_add(1);
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let (reply, response) = oneshot::channel();
+        self.pending.insert(
+            sequence,
+            Pending {
+                id,
+                group,
+                command,
+                reply,
+            },
+        );
+        Ok((sequence, id, response))
+    }
+
+    /// Hand a received frame to its request, stale and duplicate frames are dropped
+    fn route(&mut self, frame: Vec<u8>) {
+        let Ok(header) = SmpFrame::<IgnoredAny>::decode_with_cbor(&frame) else {
+            return;
+        };
+        let matches = self
+            .pending
+            .get(&header.sequence)
+            .is_some_and(|p| p.group == header.group && p.command == header.command);
+        if matches {
+            let pending = self
+                .pending
+                .remove(&header.sequence)
+                .expect("checked above");
+            // the request may have been cancelled in the meantime
+            let _ = pending.reply.send(frame);
+        }
+    }
+}
+
+/// Removes the pending entry of a request that finished or was cancelled
+struct Registration<'a> {
+    state: &'a Mutex<State>,
+    sequence: u8,
+    id: u64,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .pending
+            .get(&self.sequence)
+            .is_some_and(|p| p.id == self.id)
+        {
+            state.pending.remove(&self.sequence);
+        }
+    }
+}
+
+struct Outgoing {
+    frame: Vec<u8>,
+    sent: oneshot::Sender<Result<(), Error>>,
+}
+
+/// Cloneable handle of a transport that is owned by a background task.
+///
+/// All clones share the transport and the sequence numbers, requests can be made concurrently
+/// from any number of tasks. The background task stops when the last handle is dropped or the
+/// transport is closed.
+#[derive(Clone)]
+pub struct MultiplexedClientAsync {
+    state: Arc<Mutex<State>>,
+    outgoing: mpsc::Sender<Outgoing>,
+    mtu: usize,
+    timeout: Option<Duration>,
+    version: SmpVersion,
+}
+
+impl MultiplexedClientAsync {
+    /// Move `transport` into a background task, must be called from within a tokio runtime.
+    ///
+    /// `timeout` limits the wait for each response. A receive timeout of the transport only
+    /// wakes up the background task and is not reported to requests.
+    pub fn new(
+        transport: impl SmpTransportAsync + Send + Sync + 'static,
+        timeout: Option<Duration>,
+    ) -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let (outgoing, queue) = mpsc::channel(QUEUE_LEN);
+        let mtu = transport.mtu();
+        tokio::spawn(run(Box::new(transport), queue, state.clone()));
+
+        Self {
+            state,
+            outgoing,
+            mtu,
+            timeout,
+            version: SmpVersion::V1,
+        }
+    }
+
+    /// SMP version of the frames sent by [MultiplexedClientAsync::call]
+    pub fn with_version(mut self, version: SmpVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// largest frame the transport can carry, including the SMP header
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Send a frame and wait for its response, the sequence number of `frame` is replaced
+    pub async fn transceive_cbor<Req: Serialize, Resp: DeserializeOwned>(
+        &self,
+        mut frame: SmpFrame<Req>,
+    ) -> Result<SmpFrame<Resp>, Error> {
+        let (sequence, id, response) = self
+            .state
+            .lock()
+            .unwrap()
+            .register(frame.group, frame.command)?;
+        let _registration = Registration {
+            state: &self.state,
+            sequence,
+            id,
+        };
+        frame.sequence = sequence;
+
+        let (sent, sent_result) = oneshot::channel();
+        let outgoing = Outgoing {
+            frame: frame.encode_with_cbor(),
+            sent,
+        };
+        self.outgoing.send(outgoing).await.map_err(|_| stopped())?;
+        sent_result.await.map_err(|_| stopped())??;
+
+        let response = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, response)
+                .await
+                .map_err(|elapsed| io::Error::new(io::ErrorKind::TimedOut, elapsed))?,
+            None => response.await,
+        };
+        let bytes = response.map_err(|_| stopped())?;
+        Ok(SmpFrame::decode_with_cbor(&bytes)?)
+    }
+
+    /// Send a request and wait for its typed response.
+    ///
+    /// Error responses of the device are returned as [Error::Device].
+    pub async fn call<R: SmpRequest>(&self, request: R) -> Result<R::Response, Error> {
+        let frame = request.into_frame(0).with_version(self.version);
+        let response: SmpFrame<SmpResponse<R::Response>> = self.transceive_cbor(frame).await?;
+        Ok(response.into_result()?)
+    }
+}
+
+fn stopped() -> Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the transport task stopped").into()
+}
+
+/// The transport can't be used anymore
+fn is_closed(err: &Error) -> bool {
+    matches!(err, Error::Io(err) if matches!(
+        err.kind(),
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe | io::ErrorKind::NotConnected
+    ))
+}
+
+async fn run(
+    mut transport: Box<dyn SmpTransportAsync + Send + Sync>,
+    mut queue: mpsc::Receiver<Outgoing>,
+    state: Arc<Mutex<State>>,
+) {
+    loop {
+        // receiving is cancelled whenever a frame is queued, both transports keep partial input
+        tokio::select! {
+            biased;
+            outgoing = queue.recv() => {
+                // all handles were dropped
+                let Some(outgoing) = outgoing else { break };
+                let _ = outgoing.sent.send(transport.send(outgoing.frame).await);
+            }
+            received = transport.receive() => match received {
+                Ok(frame) => state.lock().unwrap().route(frame),
+                Err(err) if is_closed(&err) => break,
+                // timeouts and undecodable frames, the affected request times out on its own
+                Err(_) => {}
+            }
+        }
+    }
+
+    // waiting requests see the closed reply channel
+    state.lock().unwrap().pending.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::os_management::{EchoRequest, EchoResponse};
+    use crate::OpCode;
+    use async_trait::async_trait;
+
+    /// Connected to the test through channels, the test plays the device
+    struct ChannelTransport {
+        requests: mpsc::UnboundedSender<Vec<u8>>,
+        responses: mpsc::UnboundedReceiver<Vec<u8>>,
+    }
+
+    #[async_trait]
+    impl SmpTransportAsync for ChannelTransport {
+        async fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.requests.send(frame).map_err(|_| stopped())
+        }
+
+        async fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+            self.send(frame).await
+        }
+
+        async fn receive(&mut self) -> Result<Vec<u8>, Error> {
+            self.responses
+                .recv()
+                .await
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof).into())
+        }
+    }
+
+    struct Device {
+        requests: mpsc::UnboundedReceiver<Vec<u8>>,
+        responses: mpsc::UnboundedSender<Vec<u8>>,
+    }
+
+    impl Device {
+        async fn request(&mut self) -> SmpFrame<EchoRequest> {
+            let bytes = self.requests.recv().await.unwrap();
+            SmpFrame::decode_with_cbor(&bytes).unwrap()
+        }
+
+        fn echo(&self, sequence: u8, text: &str) {
+            let frame = EchoRequest { d: String::new() }.into_frame(sequence);
+            let response = SmpFrame::new(
+                OpCode::WriteResponse,
+                sequence,
+                frame.group,
+                frame.command,
+                EchoResponse {
+                    r: text.to_string(),
+                },
+            );
+            self.responses.send(response.encode_with_cbor()).unwrap();
+        }
+    }
+
+    fn connect(timeout: Option<Duration>) -> (MultiplexedClientAsync, Device) {
+        let (requests, device_requests) = mpsc::unbounded_channel();
+        let (device_responses, responses) = mpsc::unbounded_channel();
+        let transport = ChannelTransport {
+            requests,
+            responses,
+        };
+        let device = Device {
+            requests: device_requests,
+            responses: device_responses,
+        };
+        (MultiplexedClientAsync::new(transport, timeout), device)
+    }
+
+    fn echo(
+        client: &MultiplexedClientAsync,
+        text: &str,
+    ) -> impl std::future::Future<Output = Result<EchoResponse, Error>> {
+        let client = client.clone();
+        let request = EchoRequest {
+            d: text.to_string(),
+        };
+        async move { client.call(request).await }
+    }
+
+    #[tokio::test]
+    async fn test_responses_are_routed_by_sequence() {
+        let (client, mut device) = connect(Some(Duration::from_secs(2)));
+
+        let requests = tokio::spawn(async move {
+            tokio::join!(echo(&client, "a"), echo(&client, "b"), echo(&client, "c"))
+        });
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let request = device.request().await;
+            received.push((request.sequence, request.data.d));
+        }
+        let sequences: Vec<u8> = received.iter().map(|(sequence, _)| *sequence).collect();
+        assert_eq!(sequences, [0, 1, 2]);
+
+        // a stale frame, the responses in reverse order and a duplicate
+        device.echo(200, "stale");
+        for (sequence, text) in received.iter().rev() {
+            device.echo(*sequence, text);
+        }
+        device.echo(received[0].0, "duplicate");
+
+        let (a, b, c) = requests.await.unwrap();
+        assert_eq!(a.unwrap().r, "a");
+        assert_eq!(b.unwrap().r, "b");
+        assert_eq!(c.unwrap().r, "c");
+    }
+
+    #[tokio::test]
+    async fn test_late_response_is_dropped() {
+        let (client, mut device) = connect(Some(Duration::from_millis(50)));
+
+        let first = echo(&client, "first").await;
+        match first {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            other => panic!("expected timeout, got {other:?}"),
+        }
+        let late = device.request().await;
+
+        let second = tokio::spawn(echo(&client, "second"));
+        let request = device.request().await;
+        assert_ne!(request.sequence, late.sequence);
+        device.echo(late.sequence, "first");
+        device.echo(request.sequence, "second");
+
+        assert_eq!(second.await.unwrap().unwrap().r, "second");
+        assert!(client.state.lock().unwrap().pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_closed_transport_fails_requests() {
+        let (client, device) = connect(None);
+
+        let request = tokio::spawn(echo(&client, "lost"));
+        drop(device);
+
+        match request.await.unwrap() {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::BrokenPipe),
+            other => panic!("expected a stopped transport, got {other:?}"),
+        }
+    }
+}
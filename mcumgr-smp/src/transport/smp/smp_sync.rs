@@ -1,4 +1,7 @@
 use crate::transport::error::Error;
+use crate::transport::smp::DEFAULT_MTU;
+use std::io;
+use std::time::Duration;
 
 pub trait SmpTransport {
     /// send a single frame
@@ -9,6 +12,39 @@ pub trait SmpTransport {
 
     /// receive a single frame
     fn receive(&mut self) -> Result<Vec<u8>, Error>;
+
+    /// largest frame the transport can carry, including the SMP header
+    fn mtu(&self) -> usize {
+        DEFAULT_MTU
+    }
+
+    /// limit the wait of [SmpTransport::receive], `None` waits forever.
+    /// Transports without timeout support return [io::ErrorKind::Unsupported]
+    fn set_timeout(&mut self, _timeout: Option<Duration>) -> Result<(), Error> {
+        Err(io::Error::from(io::ErrorKind::Unsupported).into())
+    }
+}
+
+impl<T: SmpTransport + ?Sized> SmpTransport for Box<T> {
+    fn send(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        (**self).send(frame)
+    }
+
+    fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
+        (**self).send_to(frame)
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, Error> {
+        (**self).receive()
+    }
+
+    fn mtu(&self) -> usize {
+        (**self).mtu()
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        (**self).set_timeout(timeout)
+    }
 }
 
 #[cfg(feature = "payload-cbor")]
@@ -16,6 +52,7 @@ pub mod cbor {
     use crate::smp::SmpFrame;
     use crate::transport::error::Error;
     use crate::transport::smp::SmpTransport;
+    use crate::{SmpRequest, SmpResponse};
 
     pub struct CborSmpTransport {
         pub transport: Box<dyn SmpTransport + Sync + Send + 'static>,
@@ -31,6 +68,9 @@ pub mod cbor {
         pub fn receive(&mut self) -> Result<Vec<u8>, Error> {
             self.transport.receive()
         }
+        pub fn mtu(&self) -> usize {
+            self.transport.mtu()
+        }
 
         pub fn transceive(&mut self, frame: Vec<u8>) -> Result<Vec<u8>, Error> {
             self.transport.send(frame)?;
@@ -41,7 +81,10 @@ pub mod cbor {
             let bytes = frame.encode_with_cbor();
             self.send(bytes)
         }
-        pub fn send_to_cbor<T: serde::Serialize>(&mut self, frame: &SmpFrame<T>) -> Result<(), Error> {
+        pub fn send_to_cbor<T: serde::Serialize>(
+            &mut self,
+            frame: &SmpFrame<T>,
+        ) -> Result<(), Error> {
             let bytes = frame.encode_with_cbor();
             self.send_to(bytes)
         }
@@ -67,5 +110,18 @@ pub mod cbor {
             self.send_cbor(frame)?;
             self.receive_cbor(check_sequence.then_some(frame.sequence))
         }
+
+        /// Send a request and wait for its typed response.
+        ///
+        /// Error responses of the device are returned as [Error::Device].
+        pub fn call<R: SmpRequest>(
+            &mut self,
+            sequence: u8,
+            request: R,
+        ) -> Result<R::Response, Error> {
+            let frame: SmpFrame<SmpResponse<R::Response>> =
+                self.transceive_cbor(&request.into_frame(sequence), true)?;
+            Ok(frame.into_result()?)
+        }
     }
 }
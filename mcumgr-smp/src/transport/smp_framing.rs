@@ -5,6 +5,7 @@ use base64::engine::general_purpose;
 use base64::{EncodeSliceError, Engine};
 use crc::Crc;
 use std::cmp::min;
+use std::collections::VecDeque;
 
 /// there are multiple possible CRC implementations. This matches the results from mcumgr
 const CALC_CRC: Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_XMODEM);
@@ -13,20 +14,51 @@ const CALC_CRC: Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_XMODEM);
 pub enum SmpTransportError {
     #[error("unexpected frame")]
     UnexpectedFrame,
-    #[error("unknown frame start: {0:?}")]
-    UnknownFrameStart([u8; 2]),
+    #[error("frame line truncated or garbled")]
+    TruncatedLine,
     #[error("packet length invalid")]
     PacketLength(u16, usize),
     #[error("wrong crc")]
     CRCError,
     #[error("base64 decoding error: {0}")]
     Base64DecodeError(#[from] base64::DecodeError),
+    #[error("base64 encoding error: {0}")]
+    Base64EncodeError(#[from] EncodeSliceError),
 }
 
+/// Console output longer than this is handed to the console handler in pieces
+const MAX_CONSOLE_LINE_LEN: usize = 1024;
+
+type ConsoleHandler = Box<dyn FnMut(&[u8]) + Send + Sync + 'static>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LineState {
+    /// outside of an SMP line, bytes are console (shell / log) output
+    Console,
+    /// the first byte of a frame start marker has been seen
+    Marker(u8),
+    /// inside the base64 body of an SMP line
+    Smp { start: bool },
+}
+
+/// Incremental decoder for the SMP console framing.
+///
+/// Accepts arbitrary chunks of bytes, for example straight from a UART that is shared
+/// with the Zephyr shell and logging. Anything that is not part of an SMP line is skipped
+/// or passed to the console handler. After an invalid line the decoder resynchronises
+/// on the next frame start marker. Decoded frames and errors are queued in order
+/// and can be taken with [SmpTransportDecoder::next_frame].
 pub struct SmpTransportDecoder {
-    /// length + 2 bytes CRC
+    state: LineState,
+    /// base64 body of the current SMP line
+    line: Vec<u8>,
+    /// console output of the current line
+    console: Vec<u8>,
+    console_handler: Option<ConsoleHandler>,
+    /// length + 2 bytes CRC, 0 while no frame is in progress
     content_length: u16,
     buf: Vec<u8>,
+    frames: VecDeque<Result<Vec<u8>, SmpTransportError>>,
 }
 
 impl Default for SmpTransportDecoder {
@@ -38,153 +70,380 @@ impl Default for SmpTransportDecoder {
 impl SmpTransportDecoder {
     pub fn new() -> Self {
         Self {
+            state: LineState::Console,
+            line: Vec::with_capacity(MAX_LINE_LEN),
+            console: Vec::new(),
+            console_handler: None,
             content_length: 0,
             buf: Vec::with_capacity(127),
+            frames: VecDeque::new(),
         }
     }
 
-    /// attempt to parse a packet from the input buffer and return whether the frame is complete
-    pub fn input_line(&mut self, input: &[u8]) -> Result<bool, SmpTransportError> {
-        let start = (input[0], input[1]);
-        let base64_packet = general_purpose::STANDARD.decode(&input[2..input.len() - 1])?;
+    /// Hand every line of non-SMP output to `handler` instead of dropping it
+    pub fn set_console_handler(&mut self, handler: impl FnMut(&[u8]) + Send + Sync + 'static) {
+        self.console_handler = Some(Box::new(handler));
+    }
 
-        let packet_body = match start {
-            (0x06, 0x09) => {
-                if self.content_length > 0 {
-                    return Err(SmpTransportError::UnexpectedFrame);
-                }
+    /// Feed the next bytes received from the device
+    pub fn input(&mut self, input: &[u8]) {
+        for &byte in input {
+            self.input_byte(byte);
+        }
+    }
 
-                self.content_length = u16::from_be_bytes([base64_packet[0], base64_packet[1]]);
+    /// Take the next decoded frame payload or error, in the order they were received
+    pub fn next_frame(&mut self) -> Option<Result<Vec<u8>, SmpTransportError>> {
+        self.frames.pop_front()
+    }
 
-                &base64_packet[2..]
-            }
-            (0x04, 0x14) => {
-                if self.content_length == 0 {
-                    return Err(SmpTransportError::UnexpectedFrame);
+    fn input_byte(&mut self, byte: u8) {
+        match self.state {
+            LineState::Console => match byte {
+                0x06 | 0x04 => self.state = LineState::Marker(byte),
+                0x0a => self.flush_console(),
+                _ => {
+                    self.console.push(byte);
+                    if self.console.len() >= MAX_CONSOLE_LINE_LEN {
+                        self.flush_console();
+                    }
                 }
-                &base64_packet
-            }
-            (a, b) => return Err(SmpTransportError::UnknownFrameStart([a, b])),
-        };
+            },
+            LineState::Marker(first) => match (first, byte) {
+                (0x06, 0x09) | (0x04, 0x14) => {
+                    self.flush_console();
+                    self.line.clear();
+                    self.state = LineState::Smp {
+                        start: first == 0x06,
+                    };
+                }
+                _ => {
+                    // not a marker after all
+                    self.state = LineState::Console;
+                    self.console.push(first);
+                    self.input_byte(byte);
+                }
+            },
+            LineState::Smp { start } => match byte {
+                0x0a => {
+                    self.state = LineState::Console;
+                    self.finish_line(start);
+                }
+                b'\r' => {}
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'+' | b'/' | b'=' => {
+                    if self.line.len() >= MAX_LINE_LEN - 3 {
+                        self.abort_line();
+                    } else {
+                        self.line.push(byte);
+                    }
+                }
+                _ => {
+                    // an interrupted line, possibly followed by the next marker
+                    self.abort_line();
+                    self.input_byte(byte);
+                }
+            },
+        }
+    }
 
-        let total_len = self.buf.len() + packet_body.len();
-        if total_len > self.content_length as usize {
-            return Err(SmpTransportError::PacketLength(
-                self.content_length,
-                total_len,
-            ));
+    fn flush_console(&mut self) {
+        if let Some(handler) = self.console_handler.as_mut() {
+            if !self.console.is_empty() {
+                handler(&self.console);
+            }
         }
+        self.console.clear();
+    }
 
-        self.buf.extend_from_slice(packet_body);
+    /// drop the current line and the frame it belongs to
+    fn abort_line(&mut self) {
+        self.state = LineState::Console;
+        self.line.clear();
+        self.reset_frame();
+        self.frames.push_back(Err(SmpTransportError::TruncatedLine));
+    }
 
-        Ok(self.is_complete())
+    fn reset_frame(&mut self) {
+        self.content_length = 0;
+        self.buf.clear();
     }
 
-    pub fn is_complete(&self) -> bool {
-        self.content_length != 0 && self.buf.len() >= self.content_length as usize
+    fn fail(&mut self, err: SmpTransportError) {
+        self.reset_frame();
+        self.frames.push_back(Err(err));
     }
 
-    pub fn into_frame_payload(self) -> Result<Vec<u8>, SmpTransportError> {
-        if self.buf.len() < 2 || self.buf.len() != self.content_length as usize {
-            return Err(SmpTransportError::PacketLength(
-                self.content_length,
-                self.buf.len(),
-            ));
+    fn finish_line(&mut self, start: bool) {
+        let decoded = general_purpose::STANDARD.decode(&self.line);
+        self.line.clear();
+
+        let packet = match decoded {
+            Ok(packet) => packet,
+            Err(e) => return self.fail(e.into()),
+        };
+
+        let body = if start {
+            if self.content_length > 0 {
+                // the previous frame was never completed
+                self.fail(SmpTransportError::UnexpectedFrame);
+            }
+
+            if packet.len() < 2 {
+                return self.fail(SmpTransportError::PacketLength(0, packet.len()));
+            }
+
+            let content_length = u16::from_be_bytes([packet[0], packet[1]]);
+            if content_length < 2 {
+                return self.fail(SmpTransportError::PacketLength(content_length, 0));
+            }
+            self.content_length = content_length;
+
+            &packet[2..]
+        } else {
+            if self.content_length == 0 {
+                return self.fail(SmpTransportError::UnexpectedFrame);
+            }
+            &packet[..]
+        };
+
+        let total_len = self.buf.len() + body.len();
+        if total_len > self.content_length as usize {
+            let content_length = self.content_length;
+            return self.fail(SmpTransportError::PacketLength(content_length, total_len));
         }
 
-        let mut body = self.buf;
-        let crc = u16::from_be_bytes([body[body.len() - 2], body[body.len() - 1]]);
-        body.truncate(body.len() - 2);
+        self.buf.extend_from_slice(body);
+
+        if self.buf.len() == self.content_length as usize {
+            let frame = self.check_crc();
+            self.reset_frame();
+            self.frames.push_back(frame);
+        }
+    }
 
-        let mut digest = CALC_CRC.digest();
-        digest.update(&body);
-        let crc_result = digest.finalize();
+    fn check_crc(&self) -> Result<Vec<u8>, SmpTransportError> {
+        let (body, crc) = self.buf.split_at(self.buf.len() - 2);
+        let crc = u16::from_be_bytes([crc[0], crc[1]]);
 
-        if crc != crc_result {
+        if crc != CALC_CRC.checksum(body) {
             return Err(SmpTransportError::CRCError);
         }
 
-        Ok(body)
+        Ok(body.to_vec())
     }
 }
 
-pub struct SmpTransportEncoder<'a> {
+/// max 127 bytes per line, including the frame start marker and newline
+pub const MAX_LINE_LEN: usize = 127;
+
+/// raw bytes that fit into a single line after base64 encoding
+const MAX_RAW_BODY_LEN: usize = 93; // 124.0 / 4.0 * 3.0 as usize;
+
+pub struct SmpTransportEncoder {
     written_len: usize,
-    payload: &'a [u8],
+    /// length + payload + 2 bytes CRC
+    packet: Vec<u8>,
 }
 
-impl<'a> SmpTransportEncoder<'a> {
-    pub fn new(payload: &'a [u8]) -> Self {
+impl SmpTransportEncoder {
+    pub fn new(payload: &[u8]) -> Self {
+        let mut packet = Vec::with_capacity(payload.len() + 4);
+        packet.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        packet.extend_from_slice(payload);
+        packet.extend_from_slice(&CALC_CRC.checksum(payload).to_be_bytes());
+
         Self {
             written_len: 0,
-            payload,
+            packet,
         }
     }
 
     /// Write the next line for the given payload to the supplied buffer.   
-    /// returns an error if out_buf is smaller than 127 bytes
-    pub fn write_line(&mut self, out_buf: &mut [u8]) -> Result<usize, EncodeSliceError> {
-        // max 127 with header and newline in base64 encoding
-        const MAX_RAW_BODY_LEN: usize = 93; // 124.0 / 4.0 * 3.0 as usize;
-        let mut base64_payload = Vec::with_capacity(MAX_RAW_BODY_LEN);
-
-        // println!(
-        //     "write_line: Written: {}; Total: {}",
-        //     self.written_len,
-        //     self.payload.len()
-        // );
+    /// returns an error if out_buf is smaller than [MAX_LINE_LEN] bytes
+    pub fn write_line(&mut self, out_buf: &mut [u8]) -> Result<usize, SmpTransportError> {
+        if out_buf.len() < MAX_LINE_LEN {
+            return Err(EncodeSliceError::OutputSliceTooSmall.into());
+        }
 
         if self.written_len == 0 {
             out_buf[0] = 0x06;
             out_buf[1] = 0x09;
-            base64_payload.extend_from_slice(&(self.payload.len() as u16 + 2).to_be_bytes());
         } else {
             out_buf[0] = 0x04;
             out_buf[1] = 0x14;
         }
 
-        let remaining_len = self.payload.len() - self.written_len;
-        let last_frame = remaining_len <= MAX_RAW_BODY_LEN - base64_payload.len() - 2;
+        let end = min(self.packet.len(), self.written_len + MAX_RAW_BODY_LEN);
+        let base64_len = general_purpose::STANDARD
+            .encode_slice(&self.packet[self.written_len..end], &mut out_buf[2..])?;
+        self.written_len = end;
 
-        let payload_len = if last_frame {
-            remaining_len
-        } else {
-            min(MAX_RAW_BODY_LEN - base64_payload.len(), remaining_len)
-        };
+        out_buf[2 + base64_len] = 0x0a; // newline
+
+        Ok(base64_len + 3)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.written_len >= self.packet.len()
+    }
+}
 
-        base64_payload
-            .extend_from_slice(&self.payload[self.written_len..self.written_len + payload_len]);
-        self.written_len += payload_len;
-
-        if last_frame {
-            let mut digest = CALC_CRC.digest();
-            digest.update(self.payload);
-            let crc_result = digest.finalize();
-            // println!(
-            //     "appending crc16 {:x?} to last frame",
-            //     &crc_result.to_be_bytes()
-            // );
-            base64_payload.extend_from_slice(&crc_result.to_be_bytes());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn encode(payload: &[u8]) -> Vec<u8> {
+        let mut encoder = SmpTransportEncoder::new(payload);
+        let mut line = [0u8; MAX_LINE_LEN];
+        let mut out = Vec::new();
+        while !encoder.is_complete() {
+            let len = encoder.write_line(&mut line).unwrap();
+            out.extend_from_slice(&line[..len]);
         }
+        out
+    }
 
-        // println!("base64 payload: {:x?}", base64_payload);
+    fn decode_all(decoder: &mut SmpTransportDecoder) -> Vec<Result<Vec<u8>, SmpTransportError>> {
+        std::iter::from_fn(|| decoder.next_frame()).collect()
+    }
 
-        let base64_len =
-            general_purpose::STANDARD.encode_slice(base64_payload, &mut out_buf[2..])?;
+    #[test]
+    fn test_roundtrip_line_boundaries() {
+        // payload lengths around the point where the CRC spills into the next line
+        for len in [0, 1, 87, 88, 89, 90, 91, 92, 93, 181, 182, 183, 184, 1024] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let encoded = encode(&payload);
+            assert!(encoded
+                .split(|&b| b == 0x0a)
+                .all(|l| l.len() < MAX_LINE_LEN));
+
+            let mut decoder = SmpTransportDecoder::new();
+            decoder.input(&encoded);
+            assert_eq!(decoder.next_frame().unwrap().unwrap(), payload, "len {len}");
+            assert!(decoder.next_frame().is_none());
+        }
+    }
 
-        // println!(
-        //     "resulting base64 string: {}",
-        //     String::from_utf8(Vec::from(&out_buf[2..2 + base64_len])).unwrap()
-        // );
+    #[test]
+    fn test_byte_by_byte_with_console_output() {
+        let payload: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let encoded = encode(&payload);
+        let split = encoded.iter().position(|&b| b == 0x0a).unwrap() + 1;
+
+        let mut input = b"uart:~$ kernel uptime\r\nUptime: 1234 ms\n".to_vec();
+        input.extend_from_slice(&encoded[..split]);
+        input.extend_from_slice(b"[00:00:01.234,000] <wrn> net: dropped\n");
+        input.extend_from_slice(&encoded[split..]);
+        input.extend_from_slice(b"\x06not a marker");
+
+        let console = Arc::new(Mutex::new(Vec::new()));
+        let mut decoder = SmpTransportDecoder::new();
+        let lines = console.clone();
+        decoder.set_console_handler(move |line| lines.lock().unwrap().push(line.to_vec()));
+
+        for byte in input {
+            decoder.input(&[byte]);
+        }
+        decoder.input(b"\n");
+
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), payload);
+        assert!(decoder.next_frame().is_none());
+        assert_eq!(
+            *console.lock().unwrap(),
+            vec![
+                b"uart:~$ kernel uptime\r".to_vec(),
+                b"Uptime: 1234 ms".to_vec(),
+                b"[00:00:01.234,000] <wrn> net: dropped".to_vec(),
+                b"\x06not a marker".to_vec(),
+            ]
+        );
+    }
 
-        assert!(base64_len <= 124);
+    #[test]
+    fn test_recover_after_crc_error() {
+        let payload = b"first frame".to_vec();
+        let mut corrupted = encode(&payload);
+        // flip a base64 character in the body
+        corrupted[6] = if corrupted[6] == b'A' { b'B' } else { b'A' };
+
+        let mut decoder = SmpTransportDecoder::new();
+        decoder.input(&corrupted);
+        decoder.input(&encode(b"second frame"));
+
+        let frames = decode_all(&mut decoder);
+        assert_eq!(frames.len(), 2);
+        assert!(matches!(
+            frames[0],
+            Err(SmpTransportError::CRCError) | Err(SmpTransportError::PacketLength(..))
+        ));
+        assert_eq!(frames[1].as_ref().unwrap(), b"second frame");
+    }
 
-        out_buf[2 + base64_len] = 0x0a; // newline
+    #[test]
+    fn test_resync_on_interrupted_frame() {
+        let first: Vec<u8> = (0..150).map(|i| i as u8).collect();
+        let encoded = encode(&first);
+        let split = encoded.iter().position(|&b| b == 0x0a).unwrap() + 1;
 
-        Ok(base64_len + 3)
+        let mut decoder = SmpTransportDecoder::new();
+        // first line of a frame, then half of a line cut off by a new frame
+        decoder.input(&encoded[..split + 10]);
+        decoder.input(&encode(b"next"));
+
+        let frames = decode_all(&mut decoder);
+        assert!(matches!(frames[0], Err(SmpTransportError::TruncatedLine)));
+        assert_eq!(frames.last().unwrap().as_ref().unwrap(), b"next");
     }
 
-    pub fn is_complete(&self) -> bool {
-        self.written_len >= self.payload.len()
+    #[test]
+    fn test_short_and_malformed_lines() {
+        let inputs: [&[u8]; 8] = [
+            b"",
+            b"\n",
+            b"\x06\x09\n",
+            b"\x06\x09A\n",
+            b"\x06\x09AA==\n",
+            b"\x06\x09AAA=\n",
+            b"\x04\x14AAAA\n",
+            b"\x06\x09AAIAAQ==\n",
+        ];
+
+        for input in inputs {
+            let mut decoder = SmpTransportDecoder::new();
+            decoder.input(input);
+            assert!(decode_all(&mut decoder).iter().all(|f| f.is_err()));
+        }
+    }
+
+    #[test]
+    fn test_random_input_never_panics() {
+        // xorshift, deterministic and biased towards the interesting bytes
+        let mut state: u32 = 0x1234_5678;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        let alphabet = b"\x06\x09\x04\x14\n\rAQgw+/=09";
+
+        let mut decoder = SmpTransportDecoder::new();
+        for _ in 0..200_000 {
+            let r = next();
+            let byte = if r & 1 == 0 {
+                alphabet[(r >> 8) as usize % alphabet.len()]
+            } else {
+                (r >> 8) as u8
+            };
+            decoder.input(&[byte]);
+            while decoder.next_frame().is_some() {}
+        }
+
+        decoder.input(&encode(b"still alive"));
+        assert_eq!(
+            decode_all(&mut decoder).last().unwrap().as_ref().unwrap(),
+            b"still alive"
+        );
     }
 }
@@ -1,3 +1,6 @@
+/// Largest UDP payload that is not fragmented on links with the IPv6 minimum MTU of 1280 bytes
+pub const UDP_MTU: usize = 1232;
+
 #[cfg(feature = "transport-udp-async")]
 pub mod udp_async;
 #[cfg(feature = "transport-udp-async")]
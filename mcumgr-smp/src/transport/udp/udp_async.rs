@@ -3,6 +3,7 @@
 
 use crate::transport::error::Error;
 use crate::transport::smp::SmpTransportAsync;
+use crate::transport::udp::UDP_MTU;
 use async_trait::async_trait;
 use std::io;
 use std::net::{Ipv6Addr, SocketAddr};
@@ -20,7 +21,10 @@ pub struct UdpTransportAsync {
 }
 
 impl UdpTransportAsync {
-    pub async fn new<A: ToSocketAddrs>(target: &A, timeout: Option<Duration>) -> Result<Self, io::Error> {
+    pub async fn new<A: ToSocketAddrs>(
+        target: &A,
+        timeout: Option<Duration>,
+    ) -> Result<Self, io::Error> {
         let socket = UdpSocket::bind(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)).await?;
         let mut addrs = lookup_host(target).await?;
         let target_addr = addrs
@@ -30,13 +34,25 @@ impl UdpTransportAsync {
         let local_addr = socket.local_addr().unwrap();
         let buf = vec![0; BUF_SIZE];
 
-        Ok(Self { socket, buf, target_addr:Some(target_addr), local_addr, timeout})
+        Ok(Self {
+            socket,
+            buf,
+            target_addr: Some(target_addr),
+            local_addr,
+            timeout,
+        })
     }
 
     pub async fn new_server<A: ToSocketAddrs>(bind_addr: A) -> Result<Self, io::Error> {
         let socket: UdpSocket = UdpSocket::bind(bind_addr).await?;
         let local_addr = socket.local_addr().unwrap();
-        Ok(Self { socket, buf: vec![0; BUF_SIZE], target_addr: None, local_addr, timeout: None})
+        Ok(Self {
+            socket,
+            buf: vec![0; BUF_SIZE],
+            target_addr: None,
+            local_addr,
+            timeout: None,
+        })
     }
 }
 
@@ -48,7 +64,9 @@ impl SmpTransportAsync for UdpTransportAsync {
     }
 
     async fn send_to(&mut self, frame: Vec<u8>) -> Result<(), Error> {
-        self.socket.send_to(&frame, self.target_addr.unwrap()).await?;
+        self.socket
+            .send_to(&frame, self.target_addr.unwrap())
+            .await?;
         Ok(())
     }
 
@@ -78,4 +96,8 @@ impl SmpTransportAsync for UdpTransportAsync {
             }
         }
     }
+
+    fn mtu(&self) -> usize {
+        UDP_MTU
+    }
 }
@@ -3,6 +3,7 @@
 
 use crate::transport::error::Error;
 use crate::transport::smp::SmpTransport;
+use crate::transport::udp::UDP_MTU;
 use std::io;
 use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
 use std::time::Duration;
@@ -23,15 +24,23 @@ impl UdpTransport {
             .next()
             .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no addresses"))?;
         socket.connect(target)?;
-       
-        let buf = vec![0; BUF_SIZE]; 
 
-        Ok(Self { socket, buf, target_addr:Some(target_addr) })
+        let buf = vec![0; BUF_SIZE];
+
+        Ok(Self {
+            socket,
+            buf,
+            target_addr: Some(target_addr),
+        })
     }
 
     pub fn new_server<A: ToSocketAddrs>(bind_addr: A) -> Result<Self, io::Error> {
         let socket: UdpSocket = UdpSocket::bind(bind_addr)?;
-        Ok(Self { socket, buf: vec![0; BUF_SIZE], target_addr: None })
+        Ok(Self {
+            socket,
+            buf: vec![0; BUF_SIZE],
+            target_addr: None,
+        })
     }
 
     pub fn recv_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
@@ -57,6 +66,14 @@ impl SmpTransport for UdpTransport {
 
         Ok(Vec::from(&self.buf[0..len]))
     }
+
+    fn mtu(&self) -> usize {
+        UDP_MTU
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.recv_timeout(timeout)
+    }
 }
 /// Unit tests for setting the buffer size and recieve timeout
 #[cfg(test)]
@@ -75,4 +92,4 @@ mod tests {
         assert!(transport.recv_timeout(Some(Duration::from_secs(1))).is_ok());
         assert!(transport.recv_timeout(None).is_ok());
     }
-}
\ No newline at end of file
+}
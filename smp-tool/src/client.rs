@@ -1,22 +1,32 @@
 // smp-tool/src/client.rs
 
 use core::time;
+use mcumgr_smp::application_management::{GetImageStatePayload, SlotInfoResponse};
 use std::path::Path;
 use std::sync::atomic::{AtomicU8, Ordering};
-use mcumgr_smp::application_management::GetImageStatePayload;
 use tokio::net::ToSocketAddrs;
 
-use crate::ops::{os_grp, shell_grp};
+pub use crate::ops::img_grp::{FlashOptions, DEFAULT_WINDOW};
+use crate::ops::{fs_grp, os_grp, settings_grp, shell_grp, stat_grp};
 use crate::{error::Result, ops::img_grp};
 use mcumgr_smp::{
-    smp::SmpFrame,
-    transport::{smp::CborSmpTransportAsync, udp::UdpTransportAsync},
+    smp::{SmpFrame, SmpVersion},
+    transport::{
+        record::RecordingTransportAsync,
+        retry::{RetryPolicy, RetryTransportAsync},
+        serial::SerialTransportAsync,
+        smp::{CborSmpTransportAsync, SmpTransportAsync},
+        udp::UdpTransportAsync,
+    },
+    SmpRequest, SmpResponse,
 };
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, de::IgnoredAny, Serialize};
+use tracing::debug;
 
 pub struct Client {
     transport: CborSmpTransportAsync,
     seq: AtomicU8,
+    version: SmpVersion,
 }
 
 impl Client {
@@ -27,10 +37,67 @@ impl Client {
                 transport: Box::new(udp),
             },
             seq: 0.into(),
+            version: SmpVersion::V1,
         })
     }
 
-    fn next_seq(&self) -> u8 {
+    pub fn new_serial(path: &str, baud_rate: u32, timeout: Option<time::Duration>) -> Result<Self> {
+        let serial = SerialTransportAsync::new(path, baud_rate, timeout)?;
+        Ok(Self::from_transport(serial))
+    }
+
+    /// Client on top of any transport, e.g. a custom transport or one used in tests
+    pub fn from_transport(transport: impl SmpTransportAsync + Send + Sync + 'static) -> Self {
+        Self {
+            transport: CborSmpTransportAsync {
+                transport: Box::new(transport),
+            },
+            seq: 0.into(),
+            version: SmpVersion::V1,
+        }
+    }
+
+    /// Resend requests whose response is lost according to `policy`
+    pub fn with_retry_policy(self, policy: RetryPolicy) -> Self {
+        let transport = RetryTransportAsync::new(self.transport.transport, policy);
+        Self {
+            transport: CborSmpTransportAsync {
+                transport: Box::new(transport),
+            },
+            ..self
+        }
+    }
+
+    /// Log every frame to a new file at `path`, to replay the session with a
+    /// [ReplayTransport](mcumgr_smp::transport::record::ReplayTransport).
+    /// Apply before a retry policy so resent requests and timeouts are logged as well.
+    pub fn with_recording(self, path: &Path) -> Result<Self> {
+        let transport = RecordingTransportAsync::create(self.transport.transport, path)?;
+        Ok(Self {
+            transport: CborSmpTransportAsync {
+                transport: Box::new(transport),
+            },
+            ..self
+        })
+    }
+
+    /// SMP version used for requests.  
+    /// Devices that only support version 1 answer with a version 1 header,
+    /// in which case the client falls back to version 1 for all further requests.
+    pub fn set_smp_version(&mut self, version: SmpVersion) {
+        self.version = version;
+    }
+
+    pub fn smp_version(&self) -> SmpVersion {
+        self.version
+    }
+
+    /// Largest frame the transport can carry
+    pub fn mtu(&self) -> usize {
+        self.transport.mtu()
+    }
+
+    pub(crate) fn next_seq(&self) -> u8 {
         self.seq.fetch_add(1, Ordering::Relaxed)
     }
 
@@ -42,67 +109,188 @@ impl Client {
         Req: Serialize,
         Resp: DeserializeOwned,
     {
-        Ok(self.transport.transceive_cbor(frame, true).await?)
+        self.send_cbor(frame).await?;
+        self.receive_cbor(|sequence| sequence == frame.sequence)
+            .await
+    }
+
+    /// Send a request without waiting for its response
+    pub(crate) async fn send_cbor<Req: Serialize>(&mut self, frame: &SmpFrame<Req>) -> Result<()> {
+        let request = frame.as_ref().with_version(self.version);
+        self.transport.send_cbor(&request).await?;
+        Ok(())
+    }
+
+    /// Receive the next response with a sequence number accepted by `expected`.
+    /// Responses to earlier requests that timed out may still arrive, they are skipped.
+    pub(crate) async fn receive_cbor<Resp: DeserializeOwned>(
+        &mut self,
+        expected: impl Fn(u8) -> bool,
+    ) -> Result<SmpFrame<Resp>> {
+        loop {
+            let bytes = self.transport.receive().await?;
+            let header = SmpFrame::<IgnoredAny>::decode_with_cbor(&bytes)?;
+            if !expected(header.sequence) {
+                debug!("skipping response with sequence {}", header.sequence);
+                continue;
+            }
+
+            let response = SmpFrame::<Resp>::decode_with_cbor(&bytes)?;
+            self.version = response.version;
+            return Ok(response);
+        }
+    }
+
+    /// Send a request and return its typed response.
+    /// Error responses of the device are returned as [Error::Device](crate::error::Error::Device).
+    pub async fn call<R: SmpRequest>(&mut self, request: R) -> Result<R::Response> {
+        let frame = request.into_frame(self.next_seq());
+        let response: SmpFrame<SmpResponse<R::Response>> = self.transceive_cbor(&frame).await?;
+        Ok(response.into_result()?)
     }
 
     // --------------- IMG GRP ---------------
 
     pub async fn info(&mut self) -> Result<()> {
-        let seq = self.next_seq();
-        img_grp::info(self, seq).await
+        img_grp::info(self).await
     }
 
     pub async fn get_img_state(&mut self) -> Result<GetImageStatePayload> {
-        img_grp::get_img_state(self,self.next_seq()).await
+        img_grp::get_img_state(self).await
     }
 
-    pub async fn flash(
-        &mut self,
-        slot: Option<u8>,
-        update_file: &Path,
-        chunk_size: usize,
-        upgrade: bool,
-        hash: &str,
-    ) -> Result<()> {
-        img_grp::flash(self, slot, update_file, chunk_size, upgrade, hash).await
+    pub async fn flash(&mut self, update_file: &Path, options: &FlashOptions) -> Result<()> {
+        img_grp::flash(self, update_file, options).await
+    }
+
+    pub async fn erase(&mut self, slot: Option<u32>) -> Result<()> {
+        img_grp::erase(self, slot).await
+    }
+
+    pub async fn slots(&mut self) -> Result<()> {
+        img_grp::slots(self).await
+    }
+
+    pub async fn slot_info(&mut self) -> Result<SlotInfoResponse> {
+        img_grp::slot_info(self).await
     }
 
     pub async fn confirm(&mut self, hash_hex: &str) -> Result<()> {
-        let seq = self.next_seq();
-        img_grp::confirm(self, hash_hex, seq).await
+        img_grp::confirm(self, hash_hex).await
     }
 
     pub async fn test_next_boot(&mut self, hash_hex: &str) -> Result<()> {
-        let seq = self.next_seq();
-        img_grp::test_next_boot(self, hash_hex, seq).await
+        img_grp::test_next_boot(self, hash_hex).await
+    }
+
+    // --------------- FS GRP ---------------
+
+    pub async fn fs_put(
+        &mut self,
+        local: &Path,
+        remote: &str,
+        chunk_size: Option<usize>,
+    ) -> Result<()> {
+        fs_grp::put(self, local, remote, chunk_size).await
+    }
+
+    pub async fn fs_get(&mut self, remote: &str, local: &Path, resume: bool) -> Result<()> {
+        fs_grp::get(self, remote, local, resume).await
+    }
+
+    pub async fn fs_stat(&mut self, remote: &str) -> Result<()> {
+        fs_grp::stat(self, remote).await
+    }
+
+    pub async fn fs_hash(&mut self, remote: &str, type_: Option<String>) -> Result<()> {
+        fs_grp::hash(self, remote, type_).await
+    }
+
+    // --------------- STAT GRP ---------------
+
+    pub async fn stat_list(&mut self) -> Result<()> {
+        stat_grp::list(self).await
+    }
+
+    pub async fn stat_show(&mut self, group: &str) -> Result<()> {
+        stat_grp::show(self, group).await
+    }
+
+    pub async fn stat_watch(&mut self, group: &str, interval: time::Duration) -> Result<()> {
+        stat_grp::watch(self, group, interval).await
+    }
+
+    // --------------- SETTINGS GRP ---------------
+
+    pub async fn settings_get(&mut self, name: &str) -> Result<()> {
+        settings_grp::get(self, name).await
+    }
+
+    pub async fn settings_set(&mut self, name: &str, val: Vec<u8>) -> Result<()> {
+        settings_grp::set(self, name, val).await
+    }
+
+    pub async fn settings_delete(&mut self, name: &str) -> Result<()> {
+        settings_grp::delete(self, name).await
+    }
+
+    pub async fn settings_commit(&mut self) -> Result<()> {
+        settings_grp::commit(self).await
+    }
+
+    pub async fn settings_export(&mut self, names: &[String], file: &Path) -> Result<()> {
+        settings_grp::export(self, names, file).await
+    }
+
+    pub async fn settings_import(&mut self, file: &Path, save: bool) -> Result<()> {
+        settings_grp::import(self, file, save).await
     }
 
     // --------------- OS GRP ---------------
 
     pub async fn echo(&mut self, msg: String) -> Result<()> {
-        let seq = self.next_seq();
-        os_grp::echo(self, msg, seq).await
+        os_grp::echo(self, msg).await
+    }
+
+    pub async fn reset(&mut self, force: bool, boot_mode: Option<u8>) -> Result<()> {
+        os_grp::reset(self, force, boot_mode).await
+    }
+
+    pub async fn tasks(&mut self) -> Result<()> {
+        os_grp::tasks(self).await
+    }
+
+    pub async fn mpools(&mut self) -> Result<()> {
+        os_grp::mpools(self).await
+    }
+
+    pub async fn datetime(&mut self, set: Option<String>) -> Result<()> {
+        os_grp::datetime(self, set).await
+    }
+
+    pub async fn params(&mut self) -> Result<()> {
+        os_grp::params(self).await
+    }
+
+    pub async fn os_info(&mut self, format: String) -> Result<()> {
+        os_grp::info(self, format).await
     }
 
-    pub async fn reset(&mut self) -> Result<()> {
-        let seq = self.next_seq();
-        os_grp::reset(self, seq).await
+    pub async fn bootloader_info(&mut self, query: Option<String>) -> Result<()> {
+        os_grp::bootloader(self, query).await
     }
 
     // --------------- SHELL GRP ---------------
 
     pub async fn transceive(&mut self, cmd: Vec<String>) -> Result<String> {
-        let seq = self.next_seq();
-        shell_grp::transceive(self, cmd, seq).await
+        shell_grp::transceive(self, cmd).await
     }
 
     pub async fn exec(&mut self, cmd: Vec<String>) -> Result<()> {
-        let seq = self.next_seq();
-        shell_grp::exec(self, cmd, seq).await
+        shell_grp::exec(self, cmd).await
     }
 
     pub async fn interactive(&mut self) -> Result<()> {
-        let seq = self.next_seq();
-        shell_grp::interactive(self, seq).await
+        shell_grp::interactive(self).await
     }
 }
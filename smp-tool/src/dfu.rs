@@ -0,0 +1,370 @@
+// smp-tool/src/dfu.rs
+
+//! Firmware update from the first request to the confirmed image.
+//!
+//! [Dfu] runs the steps one at a time: wait until the device answers, compare the running
+//! image, upload, mark the image for test, reset, wait for the reboot, verify that the new
+//! image runs and confirm it. An image that cannot be confirmed is rolled back by resetting
+//! the device without confirming, MCUboot then boots the previous image again.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use mcumgr_smp::application_management::{GetImageStatePayload, ImageState};
+use mcumgr_smp::mcuboot_image::McubootImage;
+use serde::Serialize;
+use tracing::debug;
+
+use crate::client::{Client, FlashOptions};
+use crate::error::{Error, Result};
+use crate::ops::img_grp::decode_hash_hex;
+
+/// Pause between requests while waiting for the device
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DfuStep {
+    WaitOnline,
+    CheckRunning,
+    Upload,
+    MarkTest,
+    Reset,
+    WaitReboot,
+    Verify,
+    Confirm,
+    Rollback,
+}
+
+impl fmt::Display for DfuStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DfuStep::WaitOnline => "wait online",
+            DfuStep::CheckRunning => "check running",
+            DfuStep::Upload => "upload",
+            DfuStep::MarkTest => "mark test",
+            DfuStep::Reset => "reset",
+            DfuStep::WaitReboot => "wait reboot",
+            DfuStep::Verify => "verify",
+            DfuStep::Confirm => "confirm",
+            DfuStep::Rollback => "rollback",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DfuOutcome {
+    /// The device already runs the image, nothing was uploaded
+    AlreadyRunning,
+    /// The new image runs and is confirmed
+    Updated,
+    /// The new image runs unconfirmed, the next reset reverts it
+    Testing,
+    /// The new image did not boot or could not be confirmed, the previous image runs again
+    RolledBack,
+    /// The update stopped at a failed step
+    Failed,
+}
+
+impl DfuOutcome {
+    /// The device runs the requested image
+    pub fn is_success(self) -> bool {
+        matches!(
+            self,
+            DfuOutcome::AlreadyRunning | DfuOutcome::Updated | DfuOutcome::Testing
+        )
+    }
+}
+
+impl fmt::Display for DfuOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            DfuOutcome::AlreadyRunning => "already running",
+            DfuOutcome::Updated => "updated",
+            DfuOutcome::Testing => "running unconfirmed",
+            DfuOutcome::RolledBack => "rolled back",
+            DfuOutcome::Failed => "failed",
+        };
+        f.write_str(text)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DfuOptions {
+    pub flash: FlashOptions,
+    /// How long the device may take to answer, before the update and after a reset
+    pub online_timeout: Duration,
+    /// Pause after a reset before the device is polled
+    pub reboot_delay: Duration,
+    /// Confirm the new image once it runs, otherwise it stays in test mode
+    pub confirm: bool,
+}
+
+impl Default for DfuOptions {
+    fn default() -> Self {
+        Self {
+            flash: FlashOptions::default(),
+            online_timeout: Duration::from_secs(60),
+            reboot_delay: Duration::from_secs(1),
+            confirm: true,
+        }
+    }
+}
+
+/// Result of a single step
+#[derive(Debug, Clone)]
+pub struct StepReport {
+    pub step: DfuStep,
+    pub elapsed: Duration,
+    /// what the step did, or why it failed
+    pub result: std::result::Result<String, String>,
+}
+
+impl fmt::Display for StepReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (status, message) = match &self.result {
+            Ok(message) => ("ok", message),
+            Err(message) => ("failed", message),
+        };
+        write!(
+            f,
+            "{:<14} {:<7} {:>7.1}s  {}",
+            self.step,
+            status,
+            self.elapsed.as_secs_f32(),
+            message
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DfuReport {
+    pub steps: Vec<StepReport>,
+    /// set once the update finished
+    pub outcome: Option<DfuOutcome>,
+}
+
+pub struct Dfu<'c> {
+    client: &'c mut Client,
+    image_file: PathBuf,
+    options: DfuOptions,
+    next: Option<DfuStep>,
+    /// hash of the new image
+    hash: Vec<u8>,
+    /// hash of the image that ran before the update
+    previous: Option<Vec<u8>>,
+    report: DfuReport,
+}
+
+impl<'c> Dfu<'c> {
+    pub fn new(
+        client: &'c mut Client,
+        image_file: impl Into<PathBuf>,
+        options: DfuOptions,
+    ) -> Self {
+        Self {
+            client,
+            image_file: image_file.into(),
+            options,
+            next: Some(DfuStep::WaitOnline),
+            hash: Vec::new(),
+            previous: None,
+            report: DfuReport::default(),
+        }
+    }
+
+    /// Step that runs on the next call of [Dfu::step], `None` once the update finished
+    pub fn next_step(&self) -> Option<DfuStep> {
+        self.next
+    }
+
+    pub fn report(&self) -> &DfuReport {
+        &self.report
+    }
+
+    /// Run the next step, `None` if the update already finished
+    pub async fn step(&mut self) -> Option<&StepReport> {
+        let step = self.next?;
+        let start = Instant::now();
+
+        let result = match self.run_step(step).await {
+            Ok((message, next)) => {
+                self.next = next;
+                Ok(message)
+            }
+            Err(err) => {
+                // a running image that can't be confirmed is rolled back
+                self.next = match step {
+                    DfuStep::Confirm => Some(DfuStep::Rollback),
+                    _ => None,
+                };
+                Err(err.to_string())
+            }
+        };
+        if self.next.is_none() && self.report.outcome.is_none() {
+            self.report.outcome = Some(DfuOutcome::Failed);
+        }
+
+        self.report.steps.push(StepReport {
+            step,
+            elapsed: start.elapsed(),
+            result,
+        });
+        self.report.steps.last()
+    }
+
+    /// Run all remaining steps
+    pub async fn run(mut self) -> DfuReport {
+        while self.step().await.is_some() {}
+        self.report
+    }
+
+    fn finish(&mut self, outcome: DfuOutcome) -> Option<DfuStep> {
+        self.report.outcome = Some(outcome);
+        None
+    }
+
+    async fn run_step(&mut self, step: DfuStep) -> Result<(String, Option<DfuStep>)> {
+        match step {
+            DfuStep::WaitOnline => {
+                self.wait_online().await?;
+                Ok(("device answers".to_string(), Some(DfuStep::CheckRunning)))
+            }
+            DfuStep::CheckRunning => {
+                self.hash = self.image_hash()?;
+                let state = self.client.get_img_state().await?;
+                self.previous = self
+                    .active(&state)
+                    .and_then(|slot| slot.hash.clone())
+                    .map(|h| h.to_vec());
+
+                if self.previous.as_ref() == Some(&self.hash) {
+                    let message = format!("{} already runs", hex::encode(&self.hash));
+                    return Ok((message, self.finish(DfuOutcome::AlreadyRunning)));
+                }
+                let message = match &self.previous {
+                    Some(previous) => format!("{} runs", hex::encode(previous)),
+                    None => "no running image reported".to_string(),
+                };
+                Ok((message, Some(DfuStep::Upload)))
+            }
+            DfuStep::Upload => {
+                self.client
+                    .flash(&self.image_file, &self.options.flash)
+                    .await?;
+                let message = format!("{} uploaded", self.image_file.display());
+                Ok((message, Some(DfuStep::MarkTest)))
+            }
+            DfuStep::MarkTest => {
+                self.client.test_next_boot(&hex::encode(&self.hash)).await?;
+                Ok((
+                    "boots once on the next reset".to_string(),
+                    Some(DfuStep::Reset),
+                ))
+            }
+            DfuStep::Reset => {
+                self.reset().await?;
+                Ok(("reset requested".to_string(), Some(DfuStep::WaitReboot)))
+            }
+            DfuStep::WaitReboot => {
+                tokio::time::sleep(self.options.reboot_delay).await;
+                self.wait_online().await?;
+                Ok(("device answers".to_string(), Some(DfuStep::Verify)))
+            }
+            DfuStep::Verify => {
+                let state = self.client.get_img_state().await?;
+                let running = self.active(&state).and_then(|slot| slot.hash.as_ref());
+                if running.map(|hash| hash.as_slice()) != Some(self.hash.as_slice()) {
+                    self.finish(DfuOutcome::RolledBack);
+                    return Err(Error::Dfu(
+                        "the new image did not boot, the bootloader reverted to the previous image"
+                            .to_string(),
+                    ));
+                }
+
+                if self.options.confirm {
+                    Ok(("new image runs".to_string(), Some(DfuStep::Confirm)))
+                } else {
+                    let message = "new image runs unconfirmed".to_string();
+                    Ok((message, self.finish(DfuOutcome::Testing)))
+                }
+            }
+            DfuStep::Confirm => {
+                self.client.confirm(&hex::encode(&self.hash)).await?;
+                Ok((
+                    "new image confirmed".to_string(),
+                    self.finish(DfuOutcome::Updated),
+                ))
+            }
+            DfuStep::Rollback => {
+                // the unconfirmed image is replaced by the previous one on the next boot
+                self.reset().await?;
+                tokio::time::sleep(self.options.reboot_delay).await;
+                let state = self.wait_online().await?;
+
+                let running = self
+                    .active(&state)
+                    .and_then(|slot| slot.hash.clone())
+                    .map(|h| h.to_vec());
+                if running.is_some() && running == self.previous {
+                    Ok((
+                        "previous image runs".to_string(),
+                        self.finish(DfuOutcome::RolledBack),
+                    ))
+                } else {
+                    Err(Error::Dfu("the previous image does not run".to_string()))
+                }
+            }
+        }
+    }
+
+    /// Hash of the image file, or the hash passed in the flash options
+    fn image_hash(&self) -> Result<Vec<u8>> {
+        match &self.options.flash.hash {
+            Some(hash) => Ok(decode_hash_hex(hash)?.to_vec()),
+            None => {
+                let data = std::fs::read(&self.image_file)?;
+                Ok(McubootImage::parse(&data)?.hash()?.to_vec())
+            }
+        }
+    }
+
+    /// Running slot of the image number that is updated
+    fn active<'s>(&self, state: &'s GetImageStatePayload) -> Option<&'s ImageState> {
+        let image = i32::from(self.options.flash.image.unwrap_or(0));
+        state
+            .images
+            .iter()
+            .find(|slot| slot.image_number() == image && slot.active)
+    }
+
+    /// Poll the image state until the device answers or the online timeout passed
+    async fn wait_online(&mut self) -> Result<GetImageStatePayload> {
+        let deadline = Instant::now() + self.options.online_timeout;
+        loop {
+            match self.client.get_img_state().await {
+                Ok(state) => return Ok(state),
+                Err(err) if Instant::now() < deadline => {
+                    debug!("device does not answer yet: {}", err);
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Reset the device, the response may be lost when the device resets before sending it
+    async fn reset(&mut self) -> Result<()> {
+        match self.client.reset(false, None).await {
+            Err(err @ Error::Device(_)) => Err(err),
+            Err(err) => {
+                debug!("no response to the reset: {}", err);
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+}
@@ -12,28 +12,51 @@ pub enum Error {
     #[error(transparent)]
     ParseInt(#[from] std::num::ParseIntError),
 
-    #[error("WriteImageChunkError: {0:?}")]
-    WriteImageChunkError(mcumgr_smp::application_management::WriteImageChunkError),
-
     #[error("Transceive got a non zero return code: {err_code} with output {output}")]
     TransceiveReturnErrorCode { err_code: i32, output: String },
 
-    #[error("ShellResult returned error code: {0}")]
-    ShellResultError(i32),
-
     #[error(transparent)]
     Fmt(#[from] std::fmt::Error),
 
     #[error("Image confirm failed, {0}")]
     Confirm(String),
 
-    #[error(
-        "GetImageStateError: {0:?}, please verify that fw is signed with the correct private key!"
-    )]
-    GetImageStateError(mcumgr_smp::application_management::GetImageStateError),
+    #[error("Marking image for test failed, {0}")]
+    Test(String),
+
+    #[error("Firmware update failed, {0}")]
+    Dfu(String),
+
+    #[error("Fleet update failed, {0}")]
+    Fleet(String),
+
+    #[error("Image upload failed, {0}")]
+    Upload(String),
+
+    #[error("File transfer failed, {0}")]
+    Fs(String),
+
+    #[error("Settings failed, {0}")]
+    Settings(String),
 
     #[error(transparent)]
     Smp(#[from] mcumgr_smp::SmpError),
+
+    #[error(transparent)]
+    Device(#[from] mcumgr_smp::DeviceError),
+
+    #[error(transparent)]
+    Image(#[from] mcumgr_smp::mcuboot_image::ImageError),
+
+    #[error("Signature verification failed, {0}")]
+    Signature(#[from] mcumgr_smp::image_signature::SignatureError),
+}
+
+impl Error {
+    /// The request failed on the way and sending it again can succeed
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::McumgrTransport(_) | Error::Io(_))
+    }
 }
 
 pub type Result<T = (), E = Error> = core::result::Result<T, E>;
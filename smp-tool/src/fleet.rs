@@ -0,0 +1,284 @@
+// smp-tool/src/fleet.rs
+
+//! Firmware rollout to many devices.
+//!
+//! The devices of an inventory are updated with [Dfu], up to [FleetOptions::concurrency] at the
+//! same time. An optional canary stage updates the first devices of the inventory before all
+//! others and stops the rollout if any of them fails. Afterwards no new updates are started once
+//! the share of failed devices passes [FleetOptions::max_failure_rate].
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::client::Client;
+use crate::dfu::{Dfu, DfuOptions, DfuOutcome, DfuStep};
+use crate::error::{Error, Result};
+
+/// Device list, the format of `devices.json`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Inventory {
+    pub measurement_devices: Vec<FleetDevice>,
+}
+
+impl Inventory {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        serde_json::from_str(&data)
+            .map_err(|err| Error::Fleet(format!("invalid inventory {}: {}", path.display(), err)))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FleetDevice {
+    /// UDP address of the device, e.g. `[2001:db8::1]:1337`
+    pub socket_addr: String,
+    /// Label shown instead of the address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl FleetDevice {
+    /// Connect to the device over UDP
+    pub async fn connect(&self, timeout: Option<Duration>) -> Result<Client> {
+        let addr: SocketAddr = self.socket_addr.parse().map_err(|err| {
+            Error::Fleet(format!("invalid address {}: {}", self.socket_addr, err))
+        })?;
+        Client::new(addr, timeout).await
+    }
+
+    fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.socket_addr)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FleetOptions {
+    pub dfu: DfuOptions,
+    /// Devices updated at the same time
+    pub concurrency: usize,
+    /// Devices updated first, all of them must succeed before the others are started
+    pub canary: usize,
+    /// Share of failed devices, 0.0 to 1.0, above which no new updates are started
+    pub max_failure_rate: f64,
+}
+
+impl Default for FleetOptions {
+    fn default() -> Self {
+        Self {
+            dfu: DfuOptions::default(),
+            concurrency: 4,
+            canary: 0,
+            max_failure_rate: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceStatus {
+    /// The device runs the new image
+    Succeeded,
+    Failed,
+    /// Not updated because the rollout stopped
+    Skipped,
+}
+
+/// Result of a single device, one entry of the summary
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceResult {
+    #[serde(flatten)]
+    pub device: FleetDevice,
+    pub status: DeviceStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<DfuOutcome>,
+    /// last step that failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_step: Option<DfuStep>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub elapsed_secs: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FleetReport {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    /// why the rollout stopped before all devices were updated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stopped: Option<String>,
+    /// in inventory order
+    pub devices: Vec<DeviceResult>,
+}
+
+impl FleetReport {
+    /// All devices run the new image
+    pub fn is_success(&self) -> bool {
+        self.failed == 0 && self.skipped == 0
+    }
+}
+
+/// Update all devices, `connect` opens the connection to a device
+pub async fn update<C, F>(
+    devices: &[FleetDevice],
+    image_file: &Path,
+    options: &FleetOptions,
+    connect: C,
+) -> FleetReport
+where
+    C: Fn(&FleetDevice) -> F,
+    F: Future<Output = Result<Client>> + Send + 'static,
+{
+    let mut rollout = Rollout {
+        devices,
+        image_file,
+        options,
+        connect,
+        results: vec![None; devices.len()],
+    };
+
+    let canary = options.canary.min(devices.len());
+    let stopped = match rollout.stage(0..canary, 0.0).await {
+        Some(reason) => Some(format!("canary stage failed, {}", reason)),
+        None => {
+            rollout
+                .stage(canary..devices.len(), options.max_failure_rate)
+                .await
+        }
+    };
+
+    let devices: Vec<DeviceResult> = rollout
+        .results
+        .into_iter()
+        .zip(devices)
+        .map(|(result, device)| {
+            result.unwrap_or_else(|| DeviceResult {
+                device: device.clone(),
+                status: DeviceStatus::Skipped,
+                outcome: None,
+                failed_step: None,
+                error: None,
+                elapsed_secs: 0.0,
+            })
+        })
+        .collect();
+    let count = |status| devices.iter().filter(|d| d.status == status).count();
+
+    FleetReport {
+        succeeded: count(DeviceStatus::Succeeded),
+        failed: count(DeviceStatus::Failed),
+        skipped: count(DeviceStatus::Skipped),
+        stopped,
+        devices,
+    }
+}
+
+struct Rollout<'a, C> {
+    devices: &'a [FleetDevice],
+    image_file: &'a Path,
+    options: &'a FleetOptions,
+    connect: C,
+    results: Vec<Option<DeviceResult>>,
+}
+
+impl<C, F> Rollout<'_, C>
+where
+    C: Fn(&FleetDevice) -> F,
+    F: Future<Output = Result<Client>> + Send + 'static,
+{
+    /// Update a range of devices, returns why the stage stopped early
+    async fn stage(&mut self, range: Range<usize>, max_failure_rate: f64) -> Option<String> {
+        let mut tasks = JoinSet::new();
+        let mut next = range.start;
+        let mut stopped = None;
+
+        loop {
+            while stopped.is_none()
+                && next < range.end
+                && tasks.len() < self.options.concurrency.max(1)
+            {
+                let device = self.devices[next].clone();
+                let connection = (self.connect)(&device);
+                tasks.spawn(update_device(
+                    next,
+                    device,
+                    connection,
+                    self.image_file.to_path_buf(),
+                    self.options.dfu.clone(),
+                ));
+                next += 1;
+            }
+
+            let Some(joined) = tasks.join_next().await else {
+                break;
+            };
+            let (index, result) =
+                joined.unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()));
+            self.results[index] = Some(result);
+
+            let finished = self.results.iter().flatten();
+            let failed = finished
+                .clone()
+                .filter(|r| r.status == DeviceStatus::Failed)
+                .count();
+            let finished = finished.count();
+            if stopped.is_none() && failed as f64 > max_failure_rate * finished as f64 {
+                stopped = Some(format!("{} of {} devices failed", failed, finished));
+            }
+        }
+        stopped
+    }
+}
+
+async fn update_device(
+    index: usize,
+    device: FleetDevice,
+    connection: impl Future<Output = Result<Client>>,
+    image_file: PathBuf,
+    options: DfuOptions,
+) -> (usize, DeviceResult) {
+    let start = Instant::now();
+    let label = device.label().to_string();
+
+    let (outcome, failed_step, error) = match connection.await {
+        Ok(mut client) => {
+            let mut dfu = Dfu::new(&mut client, image_file, options);
+            while let Some(step) = dfu.step().await {
+                println!("{}: {}", label, step);
+            }
+            let report = dfu.report();
+            let failed = report.steps.iter().rev().find(|step| step.result.is_err());
+            (
+                report.outcome,
+                failed.map(|step| step.step),
+                failed.and_then(|step| step.result.clone().err()),
+            )
+        }
+        Err(err) => (None, None, Some(err.to_string())),
+    };
+
+    let status = match outcome {
+        Some(outcome) if outcome.is_success() => DeviceStatus::Succeeded,
+        _ => DeviceStatus::Failed,
+    };
+    match outcome {
+        Some(outcome) => println!("{}: firmware {}", label, outcome),
+        None => println!("{}: connection failed", label),
+    }
+
+    let result = DeviceResult {
+        device,
+        status,
+        outcome,
+        failed_step,
+        error,
+        elapsed_secs: start.elapsed().as_secs_f64(),
+    };
+    (index, result)
+}
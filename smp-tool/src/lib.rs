@@ -1,6 +1,10 @@
 // smp-tool/src/lib.rs
 
-pub mod error;
 pub mod client;
+pub mod dfu;
+pub mod error;
+pub mod fleet;
+pub mod inspect;
+mod ops; // ops::{fs_grp, img_grp, os_grp, settings_grp, shell_grp, stat_grp}
 pub mod server;
-mod ops; // ops::{img_grp, os_grp, shell_grp}
\ No newline at end of file
+pub mod simulator;
@@ -4,19 +4,38 @@
 use core::time;
 use std::net::IpAddr;
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Mutex;
 use std::{error::Error, net::SocketAddr};
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use tracing::warn;
 use tracing_subscriber::prelude::*;
 
-use smp_tool::client::Client;
+use mcumgr_smp::transport::retry::RetryPolicy;
+#[cfg(unix)]
+use mcumgr_smp::transport::serial::SerialTransportAsync;
+use mcumgr_smp::transport::udp::UdpTransportAsync;
+use mcumgr_smp::SmpVersion;
+use smp_tool::client::{Client, FlashOptions, DEFAULT_WINDOW};
+use smp_tool::dfu::{Dfu, DfuOptions};
+use smp_tool::fleet::{self, FleetOptions, Inventory};
+use smp_tool::inspect;
+use smp_tool::simulator::{self, Simulator, SimulatorOptions};
 
 #[derive(ValueEnum, Copy, Clone, Debug)]
 pub enum Transport {
+    Serial,
     Udp,
 }
 
+#[derive(ValueEnum, Copy, Clone, Debug)]
+pub enum ProtocolVersion {
+    V1,
+    V2,
+}
+
 #[derive(Parser, Debug)]
 #[command(
     author,
@@ -26,8 +45,15 @@ pub enum Transport {
     help_template = "{about-with-newline}\nAuthor: {author-with-newline}{before-help}{usage-heading} {usage}\n\n{all-args}"
 )]
 struct Cli {
+    /// Required for all commands that talk to a device
     #[arg(short, long, value_enum)]
-    transport: Transport,
+    transport: Option<Transport>,
+
+    #[arg(short = 's', long, required_if_eq("transport", "serial"))]
+    serial_device: Option<String>,
+
+    #[arg(short = 'b', long, default_value_t = 115200)]
+    baud: u32,
 
     #[arg(short = 'd', long, required_if_eq("transport", "udp"))]
     dest_host: Option<String>,
@@ -35,9 +61,22 @@ struct Cli {
     #[arg(short = 'p', long, default_value_t = 1337)]
     udp_port: u16,
 
+    /// Wait for each response, per attempt
     #[arg(long, default_value_t = 5000)]
     timeout_ms: u64,
 
+    /// Resend read and idempotent write requests this many times when their response is lost
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+
+    /// Log every frame sent and received to this file, e.g. to replay a problem in a test
+    #[arg(long, value_name = "JSONL")]
+    record: Option<PathBuf>,
+
+    /// SMP protocol version for requests, v2 falls back to v1 for legacy devices
+    #[arg(long, value_enum, default_value_t = ProtocolVersion::V1)]
+    smp_version: ProtocolVersion,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -53,6 +92,39 @@ enum Commands {
     /// Send a command in the application group
     #[command(subcommand)]
     App(ApplicationCmd),
+    /// Send a command in the file system group
+    #[command(subcommand)]
+    Fs(FsCmd),
+    /// Send a command in the statistics group
+    #[command(subcommand)]
+    Stat(StatCmd),
+    /// Send a command in the settings group
+    #[command(subcommand)]
+    Settings(SettingsCmd),
+    /// Work with firmware image files, no device is needed
+    #[command(subcommand)]
+    Image(ImageCmd),
+    /// Work with many devices listed in an inventory, no transport is needed
+    #[command(subcommand)]
+    Fleet(FleetCmd),
+    /// Run a simulated device with MCUboot that other smp-tool instances connect to, no transport is needed
+    Simulate {
+        /// Listen for SMP over UDP on this address
+        #[arg(long, default_value = "127.0.0.1:1337")]
+        udp: String,
+        /// Serve on a new pseudo terminal instead of UDP, its path is printed on start
+        #[arg(long)]
+        pty: bool,
+        /// MCUboot image in the primary slot, a generated 1.0.0 image if not set
+        #[arg(long)]
+        image: Option<PathBuf>,
+        /// Size of each image slot in bytes
+        #[arg(long, default_value_t = SimulatorOptions::default().slot_size)]
+        slot_size: u32,
+        /// Time the device doesn't answer after a reset
+        #[arg(long, default_value_t = 200)]
+        reboot_time_ms: u64,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -60,7 +132,38 @@ enum OsCmd {
     /// Send an SMP Echo request
     Echo { msg: String },
     /// Send an SMP Reset request
-    Reset {},
+    Reset {
+        /// Reset even if the application vetoes it
+        #[arg(short, long)]
+        force: bool,
+        /// Boot mode after the reset, e.g. 1 to stay in the bootloader
+        #[arg(long)]
+        boot_mode: Option<u8>,
+    },
+    /// Show thread statistics
+    Tasks,
+    /// Show memory pool statistics
+    Mpools,
+    /// Read or set the device date and time
+    Datetime {
+        /// Set instead of read, yyyy-MM-ddTHH:mm:ss
+        #[arg(long)]
+        set: Option<String>,
+    },
+    /// Show the SMP buffer size and count of the device
+    Params,
+    /// Show OS and application info
+    Info {
+        /// Fields to request, `a` for all, see the Zephyr uname format
+        #[arg(short, long, default_value = "a")]
+        format: String,
+    },
+    /// Show bootloader info
+    Bootloader {
+        /// Bootloader specific query, e.g. `mode` for MCUboot
+        #[arg(short, long)]
+        query: Option<String>,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -71,29 +174,106 @@ enum ShellCmd {
     Interactive,
 }
 
+/// Image upload arguments shared by `app flash` and `app update`
+#[derive(Args, Debug)]
+struct UploadArgs {
+    #[arg()]
+    update_file: PathBuf,
+    /// Image number, e.g. the core on multi-core devices
+    #[arg(short, long, alias = "slot", short_alias = 's')]
+    image: Option<u8>,
+    /// Upper limit of the data per chunk, by default chunks fill the largest frame
+    /// the transport and the device accept
+    #[arg(short, long)]
+    chunk_size: Option<usize>,
+    /// Only allow newer firmware versions
+    #[arg(long)]
+    upgrade: bool,
+    /// 32-byte hash as hex, taken from the image if not set
+    #[arg(long, value_name = "HEX64")]
+    hash: Option<String>,
+    /// Check the image signature with this PEM key before uploading
+    #[arg(long, value_name = "PEM")]
+    verify_key: Option<PathBuf>,
+    /// Chunks sent before waiting for their responses
+    #[arg(short, long, default_value_t = DEFAULT_WINDOW)]
+    window: usize,
+}
+
+impl UploadArgs {
+    fn flash_options(&self, resume: bool) -> FlashOptions {
+        FlashOptions {
+            image: self.image,
+            chunk_size: self.chunk_size,
+            upgrade: self.upgrade,
+            hash: self.hash.clone(),
+            verify_key: self.verify_key.clone(),
+            resume,
+            window: self.window,
+        }
+    }
+}
+
+/// Update arguments shared by `app update` and `fleet update`
+#[derive(Args, Debug)]
+struct DfuArgs {
+    /// Leave the new image unconfirmed, the next reset reverts it
+    #[arg(long)]
+    no_confirm: bool,
+    /// How long the device may take to answer, before the update and after the reset
+    #[arg(long, default_value_t = 60000)]
+    online_timeout_ms: u64,
+    /// Pause after the reset before the device is polled
+    #[arg(long, default_value_t = 1000)]
+    reboot_delay_ms: u64,
+}
+
+impl DfuArgs {
+    fn dfu_options(&self, upload: &UploadArgs) -> DfuOptions {
+        DfuOptions {
+            flash: upload.flash_options(false),
+            online_timeout: time::Duration::from_millis(self.online_timeout_ms),
+            reboot_delay: time::Duration::from_millis(self.reboot_delay_ms),
+            confirm: !self.no_confirm,
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 enum ApplicationCmd {
     /// Request firmware info
     Info,
     /// Flash a firmware to an image slot
     Flash {
-        #[arg()]
-        update_file: PathBuf,
-        #[arg(short, long)]
-        slot: Option<u8>,
-        #[arg(short, long, default_value_t = 256)]
-        chunk_size: usize,
-        /// Only allow newer firmware versions
+        #[command(flatten)]
+        upload: UploadArgs,
+        /// Continue an interrupted upload of the same image
         #[arg(long)]
-        upgrade: bool,
+        resume: bool,
     },
-    /// Confirm image
+    /// Upload, test, reset, verify and confirm a firmware in one go.
+    /// Exits with an error if the device does not run the new image afterwards
+    Update {
+        #[command(flatten)]
+        upload: UploadArgs,
+        #[command(flatten)]
+        dfu: DfuArgs,
+    },
+    /// Erase a slot so it can take a new upload
+    Erase {
+        /// Slot to erase, the device erases the secondary slot if not set
+        #[arg(short, long)]
+        slot: Option<u32>,
+    },
+    /// Show the slot sizes of every image
+    Slots,
+    /// Confirm image, the image and slot are found by hash
     Confirm {
         /// 32-byte hash as hex
         #[arg(long, value_name = "HEX64")]
         hash: String,
     },
-    /// Test image in the next boot
+    /// Test image in the next boot, the image and slot are found by hash
     Test {
         /// 32-byte hash as hex
         #[arg(long, value_name = "HEX64")]
@@ -101,8 +281,126 @@ enum ApplicationCmd {
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum FsCmd {
+    /// Upload a file to the device
+    Put {
+        local: PathBuf,
+        /// Path on the device, e.g. /lfs/calibration.bin
+        remote: String,
+        /// Upper limit of the data per chunk, by default chunks fill the largest frame
+        /// the transport and the device accept
+        #[arg(short, long)]
+        chunk_size: Option<usize>,
+    },
+    /// Download a file from the device
+    Get {
+        remote: String,
+        local: PathBuf,
+        /// Continue a previous download by appending to the local file
+        #[arg(long)]
+        resume: bool,
+    },
+    /// Show the size of a file on the device
+    Stat { remote: String },
+    /// Let the device calculate a hash or checksum of a file
+    Hash {
+        remote: String,
+        /// e.g. sha256 or crc32, the device default is used if not set
+        #[arg(short = 'H', long = "type")]
+        type_: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum StatCmd {
+    /// List the statistics groups of the device
+    List,
+    /// Print all counters of a group
+    Show { group: String },
+    /// Poll the counters of a group and print the changes
+    Watch {
+        group: String,
+        /// Polling interval in milliseconds
+        #[arg(short, long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SettingsCmd {
+    /// Read a setting
+    Get { name: String },
+    /// Write a setting, call commit to apply it
+    Set {
+        name: String,
+        value: String,
+        /// The value is hex encoded binary data instead of a string
+        #[arg(long)]
+        hex: bool,
+    },
+    /// Delete a setting
+    Delete { name: String },
+    /// Apply written settings
+    Commit,
+    /// Save settings to a file, TOML for .toml files and JSON otherwise
+    Export {
+        file: PathBuf,
+        /// Settings to export, the device cannot list its settings
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+    /// Write, commit and save all settings of an exported file
+    Import {
+        file: PathBuf,
+        /// Only apply the settings without saving them to persistent storage
+        #[arg(long)]
+        no_save: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ImageCmd {
+    /// Print the MCUboot header and TLVs of an image file
+    Inspect {
+        file: PathBuf,
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+        /// Also check the image signature with this PEM key
+        #[arg(long, value_name = "PEM")]
+        verify_key: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum FleetCmd {
+    /// Update all devices of an inventory over UDP, several at the same time
+    Update {
+        /// JSON file with the `socket_addr` of every device in `measurement_devices`
+        #[arg(long)]
+        inventory: PathBuf,
+        #[command(flatten)]
+        upload: UploadArgs,
+        #[command(flatten)]
+        dfu: DfuArgs,
+        /// Devices updated at the same time
+        #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u64).range(1..))]
+        concurrency: u64,
+        /// Update this many devices first and stop if any of them fails
+        #[arg(long, default_value_t = 0)]
+        canary: usize,
+        /// Stop starting updates once more than this share of devices failed, 0.0 to 1.0
+        #[arg(long, default_value_t = 1.0)]
+        max_failure_rate: f64,
+        /// Write the per-device results as JSON to this file
+        #[arg(long)]
+        summary: Option<PathBuf>,
+    },
+}
+
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> ExitCode {
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "".into()))
         .with(tracing_subscriber::fmt::layer())
@@ -110,21 +408,184 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let cli: Cli = Cli::parse();
 
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
     warn!("{:?}", cli);
-    let host = cli.dest_host.as_ref().unwrap(); // &String
+    let timeout = Some(time::Duration::from_millis(cli.timeout_ms));
+    let retry = (cli.retries > 0).then(|| {
+        RetryPolicy::default()
+            .with_attempts(cli.retries + 1)
+            .with_timeout(timeout)
+    });
+
+    // offline commands
+    if let Commands::Image(ImageCmd::Inspect {
+        file,
+        json,
+        verify_key,
+    }) = &cli.command
+    {
+        inspect::inspect(file, *json, verify_key.as_deref())?;
+        return Ok(());
+    }
+
+    if let Commands::Simulate {
+        udp,
+        pty,
+        image,
+        slot_size,
+        reboot_time_ms,
+    } = &cli.command
+    {
+        let mut options = SimulatorOptions {
+            slot_size: *slot_size,
+            reboot_time: time::Duration::from_millis(*reboot_time_ms),
+            ..Default::default()
+        };
+        if let Some(image) = image {
+            options = options.with_image_file(image)?;
+        }
+        let simulator = Mutex::new(Simulator::new(options));
+
+        if *pty {
+            #[cfg(unix)]
+            {
+                let (transport, path) = SerialTransportAsync::new_pty(None)?;
+                println!("simulated device on {}", path);
+                simulator::serve(&simulator, transport).await?;
+            }
+            #[cfg(not(unix))]
+            return Err("--pty is only supported on unix".into());
+        } else {
+            let transport = UdpTransportAsync::new_server(udp.as_str()).await?;
+            println!("simulated device on udp {}", transport.local_addr);
+            simulator::serve(&simulator, transport).await?;
+        }
+        return Ok(());
+    }
+
+    if let Commands::Fleet(FleetCmd::Update {
+        inventory,
+        upload,
+        dfu,
+        concurrency,
+        canary,
+        max_failure_rate,
+        summary,
+    }) = &cli.command
+    {
+        // the sessions with all devices would end up in one log
+        if cli.record.is_some() {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "the argument '--record <JSONL>' cannot be used with 'fleet update'",
+                )
+                .exit();
+        }
+        let devices = Inventory::load(inventory)?.measurement_devices;
+        let options = FleetOptions {
+            dfu: dfu.dfu_options(upload),
+            concurrency: *concurrency as usize,
+            canary: *canary,
+            max_failure_rate: *max_failure_rate,
+        };
+        let version = smp_version(cli.smp_version);
+        let report = fleet::update(&devices, &upload.update_file, &options, |device| {
+            let device = device.clone();
+            let retry = retry.clone();
+            async move {
+                let mut client = device.connect(timeout).await?;
+                if let Some(retry) = retry {
+                    client = client.with_retry_policy(retry);
+                }
+                client.set_smp_version(version);
+                Ok(client)
+            }
+        })
+        .await;
+
+        println!(
+            "{} succeeded, {} failed, {} skipped",
+            report.succeeded, report.failed, report.skipped
+        );
+        if let Some(summary) = summary {
+            std::fs::write(summary, serde_json::to_string_pretty(&report)?)?;
+        }
+        if let Some(reason) = report.stopped {
+            return Err(format!("rollout stopped, {}", reason).into());
+        }
+        if !report.is_success() {
+            return Err(format!("{} devices failed", report.failed).into());
+        }
+        return Ok(());
+    }
 
-    let ip: IpAddr = host.parse()?; // parse into IpAddr
+    let Some(transport) = cli.transport else {
+        Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "the following required arguments were not provided:\n  --transport <TRANSPORT>",
+            )
+            .exit();
+    };
 
-    let addr = SocketAddr::new(ip, cli.udp_port);
+    let mut client = match transport {
+        Transport::Serial => {
+            let device = cli.serial_device.as_ref().unwrap();
+            Client::new_serial(device, cli.baud, timeout)?
+        }
+        Transport::Udp => {
+            let host = cli.dest_host.as_ref().unwrap(); // &String
+
+            let ip: IpAddr = host.parse()?; // parse into IpAddr
+
+            let addr = SocketAddr::new(ip, cli.udp_port);
+
+            Client::new(addr, timeout).await?
+        }
+    };
+    if let Some(record) = &cli.record {
+        client = client.with_recording(record)?;
+    }
+    if let Some(retry) = retry {
+        client = client.with_retry_policy(retry);
+    }
+    client.set_smp_version(smp_version(cli.smp_version));
 
-    let mut client = Client::new(addr, Some(time::Duration::from_millis(5000))).await?;
     match cli.command {
         // OS group
         Commands::Os(OsCmd::Echo { msg }) => {
             client.echo(msg).await?;
         }
-        Commands::Os(OsCmd::Reset {}) => {
-            client.reset().await?;
+        Commands::Os(OsCmd::Reset { force, boot_mode }) => {
+            client.reset(force, boot_mode).await?;
+        }
+        Commands::Os(OsCmd::Tasks) => {
+            client.tasks().await?;
+        }
+        Commands::Os(OsCmd::Mpools) => {
+            client.mpools().await?;
+        }
+        Commands::Os(OsCmd::Datetime { set }) => {
+            client.datetime(set).await?;
+        }
+        Commands::Os(OsCmd::Params) => {
+            client.params().await?;
+        }
+        Commands::Os(OsCmd::Info { format }) => {
+            client.os_info(format).await?;
+        }
+        Commands::Os(OsCmd::Bootloader { query }) => {
+            client.bootloader_info(query).await?;
         }
 
         // Shell group
@@ -136,16 +597,25 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
 
         // Application (image) group
-        Commands::App(ApplicationCmd::Flash {
-            slot,
-            update_file,
-            chunk_size,
-            upgrade,
-        }) => {
-            let hash = "1f22547da114895af757c9ddba823a12eb7964bab2946b6534ecaea2f71dca0e";
-            client
-                .flash(slot, &update_file, chunk_size, upgrade, hash)
-                .await?;
+        Commands::App(ApplicationCmd::Flash { upload, resume }) => {
+            let options = upload.flash_options(resume);
+            client.flash(&upload.update_file, &options).await?;
+        }
+        Commands::App(ApplicationCmd::Update { upload, dfu }) => {
+            let options = dfu.dfu_options(&upload);
+            let mut dfu = Dfu::new(&mut client, &upload.update_file, options);
+            while let Some(step) = dfu.step().await {
+                println!("{}", step);
+            }
+
+            let outcome = dfu
+                .report()
+                .outcome
+                .expect("finished update has an outcome");
+            println!("firmware {}", outcome);
+            if !outcome.is_success() {
+                return Err(format!("firmware update {}", outcome).into());
+            }
         }
         Commands::App(ApplicationCmd::Info) => {
             client.info().await?;
@@ -156,7 +626,83 @@ async fn main() -> Result<(), Box<dyn Error>> {
         Commands::App(ApplicationCmd::Test { hash }) => {
             client.test_next_boot(&hash).await?;
         }
+        Commands::App(ApplicationCmd::Erase { slot }) => {
+            client.erase(slot).await?;
+        }
+        Commands::App(ApplicationCmd::Slots) => {
+            client.slots().await?;
+        }
+
+        // File system group
+        Commands::Fs(FsCmd::Put {
+            local,
+            remote,
+            chunk_size,
+        }) => {
+            client.fs_put(&local, &remote, chunk_size).await?;
+        }
+        Commands::Fs(FsCmd::Get {
+            remote,
+            local,
+            resume,
+        }) => {
+            client.fs_get(&remote, &local, resume).await?;
+        }
+        Commands::Fs(FsCmd::Stat { remote }) => {
+            client.fs_stat(&remote).await?;
+        }
+        Commands::Fs(FsCmd::Hash { remote, type_ }) => {
+            client.fs_hash(&remote, type_).await?;
+        }
+
+        // handled before connecting
+        Commands::Image(_) | Commands::Fleet(_) | Commands::Simulate { .. } => unreachable!(),
+
+        // Settings group
+        Commands::Settings(SettingsCmd::Get { name }) => {
+            client.settings_get(&name).await?;
+        }
+        Commands::Settings(SettingsCmd::Set { name, value, hex }) => {
+            let value = if hex {
+                hex::decode(value)?
+            } else {
+                value.into_bytes()
+            };
+            client.settings_set(&name, value).await?;
+        }
+        Commands::Settings(SettingsCmd::Delete { name }) => {
+            client.settings_delete(&name).await?;
+        }
+        Commands::Settings(SettingsCmd::Commit) => {
+            client.settings_commit().await?;
+        }
+        Commands::Settings(SettingsCmd::Export { file, names }) => {
+            client.settings_export(&names, &file).await?;
+        }
+        Commands::Settings(SettingsCmd::Import { file, no_save }) => {
+            client.settings_import(&file, !no_save).await?;
+        }
+
+        // Statistics group
+        Commands::Stat(StatCmd::List) => {
+            client.stat_list().await?;
+        }
+        Commands::Stat(StatCmd::Show { group }) => {
+            client.stat_show(&group).await?;
+        }
+        Commands::Stat(StatCmd::Watch { group, interval }) => {
+            client
+                .stat_watch(&group, time::Duration::from_millis(interval))
+                .await?;
+        }
     }
 
     Ok(())
 }
+
+fn smp_version(version: ProtocolVersion) -> SmpVersion {
+    match version {
+        ProtocolVersion::V1 => SmpVersion::V1,
+        ProtocolVersion::V2 => SmpVersion::V2,
+    }
+}
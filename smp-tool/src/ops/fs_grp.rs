@@ -0,0 +1,220 @@
+// smp-tool/src/ops/fs_grp.rs
+
+use crate::error::Error;
+use crate::error::Result;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use indicatif::{ProgressBar, ProgressStyle};
+use tracing::debug;
+
+use mcumgr_smp::fs_management::{
+    FileCloseRequest, FileDownloadRequest, FileHashPayload, FileHashRequest, FileStatusRequest,
+    FileUploadResult, FileWriter,
+};
+use mcumgr_smp::smp::SmpFrame;
+
+use crate::client::Client;
+use crate::ops::img_grp::frame_size;
+
+/// Responses without progress after which an upload is given up
+const STALLED_RESPONSES: usize = 3;
+
+fn progress_bar(total: u64) -> ProgressBar {
+    let pb = ProgressBar::new(total);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner} [{bar:40}] {bytes}/{total_bytes} ({eta})")
+            .unwrap()
+            .progress_chars("=>-"),
+    );
+    pb
+}
+
+/// Upload a local file to `remote`.
+/// Chunks fill the largest frame the transport and the device accept, up to `chunk_size` bytes.
+pub async fn put(
+    client: &mut Client,
+    local: &Path,
+    remote: &str,
+    chunk_size: Option<usize>,
+) -> Result<()> {
+    let content = std::fs::read(local)?;
+    if chunk_size == Some(0) {
+        return Err(Error::Fs("chunk size must not be 0".to_string()));
+    }
+    let pb = progress_bar(content.len() as u64);
+
+    let result = upload(client, &content, remote, chunk_size, &pb).await;
+    close(client).await;
+    if result.is_err() {
+        pb.finish_and_clear();
+        return result;
+    }
+
+    pb.finish_with_message("upload complete");
+    println!("sent all bytes: {}", content.len());
+    Ok(())
+}
+
+async fn upload(
+    client: &mut Client,
+    content: &[u8],
+    remote: &str,
+    chunk_size: Option<usize>,
+    pb: &ProgressBar,
+) -> Result<()> {
+    let mut writer = FileWriter::new(remote, content.len());
+    let frame_size = frame_size(client).await;
+    debug!("frame size: {} bytes", frame_size);
+    if writer.max_chunk_len(0, frame_size) == 0 {
+        return Err(Error::Fs(format!(
+            "first chunk does not fit into {} bytes",
+            frame_size
+        )));
+    }
+
+    // responses since the offset last advanced
+    let mut stalled = 0;
+
+    // an empty file still needs one request to be created
+    loop {
+        let offset = writer.offset;
+        let len = writer
+            .max_chunk_len(offset, frame_size)
+            .min(chunk_size.unwrap_or(usize::MAX))
+            .min(content.len() - offset);
+        let chunk = &content[offset..offset + len];
+
+        let ret: SmpFrame<FileUploadResult> =
+            client.transceive_cbor(&writer.write_chunk(chunk)).await?;
+        let payload = ret.into_result()?;
+        if payload.off as usize > offset {
+            stalled = 0;
+        } else {
+            stalled += 1;
+        }
+        writer.offset = payload.off as usize;
+        pb.set_position(writer.offset as u64);
+
+        if writer.offset >= content.len() {
+            return Ok(());
+        }
+        if stalled >= STALLED_RESPONSES {
+            return Err(Error::Fs(format!(
+                "device does not accept more data, upload stuck at offset {}",
+                writer.offset
+            )));
+        }
+    }
+}
+
+/// Download `remote` into a local file.
+/// With `resume`, an existing local file is treated as the beginning of the remote file
+/// and only the missing part is requested.
+pub async fn get(client: &mut Client, remote: &str, local: &Path, resume: bool) -> Result<()> {
+    let total = client
+        .call(FileStatusRequest {
+            name: remote.to_string(),
+        })
+        .await?
+        .len as usize;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(resume)
+        .write(true)
+        .truncate(!resume)
+        .open(local)?;
+
+    let offset = file.metadata()?.len() as usize;
+    if offset > total {
+        return Err(Error::Fs(format!(
+            "local file is larger than the remote file ({} > {} bytes)",
+            offset, total
+        )));
+    }
+
+    let pb = progress_bar(total as u64);
+    pb.set_position(offset as u64);
+
+    let result = download(client, remote, &mut file, offset, total, &pb).await;
+    close(client).await;
+    if result.is_err() {
+        pb.finish_and_clear();
+        return result;
+    }
+
+    pb.finish_with_message("download complete");
+    println!("received all bytes: {}", total);
+    Ok(())
+}
+
+async fn download(
+    client: &mut Client,
+    remote: &str,
+    file: &mut File,
+    mut offset: usize,
+    total: usize,
+    pb: &ProgressBar,
+) -> Result<()> {
+    while offset < total {
+        let payload = client
+            .call(FileDownloadRequest {
+                name: remote.to_string(),
+                off: offset,
+            })
+            .await?;
+        debug!("off: {}, {} bytes", payload.off, payload.data.len());
+
+        if payload.off as usize != offset || payload.data.is_empty() {
+            return Err(Error::Fs(format!(
+                "unexpected chunk at offset {} with {} bytes, expected offset {}",
+                payload.off,
+                payload.data.len(),
+                offset
+            )));
+        }
+
+        file.write_all(&payload.data)?;
+        offset += payload.data.len();
+        pb.set_position(offset as u64);
+    }
+    Ok(())
+}
+
+/// Let the device close the file instead of waiting for its timeout.
+/// Devices without the close command keep the file open until then.
+async fn close(client: &mut Client) {
+    if let Err(err) = client.call(FileCloseRequest {}).await {
+        debug!("closing the file failed: {}", err);
+    }
+}
+
+pub async fn stat(client: &mut Client, remote: &str) -> Result<()> {
+    let ret = client
+        .call(FileStatusRequest {
+            name: remote.to_string(),
+        })
+        .await?;
+
+    println!("{}: {} bytes", remote, ret.len);
+    Ok(())
+}
+
+pub async fn hash(client: &mut Client, remote: &str, type_: Option<String>) -> Result<()> {
+    let FileHashPayload {
+        type_, len, output, ..
+    } = client
+        .call(FileHashRequest {
+            name: remote.to_string(),
+            type_,
+            off: None,
+            len: None,
+        })
+        .await?;
+
+    println!("{} ({} bytes): {}", type_, len, output);
+    Ok(())
+}
@@ -2,25 +2,35 @@
 use crate::error::Error;
 use crate::error::Result;
 use std::cmp::min;
-use std::path::Path;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 
 use indicatif::{ProgressBar, ProgressStyle};
 
-use mcumgr_smp::application_management::GetImageStatePayload;
-use mcumgr_smp::{
-    application_management::{self, GetImageStateResult, WriteImageChunkResult},
-    smp::SmpFrame,
+use mcumgr_smp::application_management::{self, ImageWriter, WriteImageChunkResult};
+use mcumgr_smp::application_management::{
+    EraseRequest, GetImageStatePayload, GetStatePayload, ImageState, SetConfirmState,
+    SlotInfoRequest, SlotInfoResponse,
 };
+use mcumgr_smp::image_signature::PublicKey;
+use mcumgr_smp::mcuboot_image::McubootImage;
+use mcumgr_smp::os_management::McumgrParamsRequest;
 
 use crate::client::Client;
-use tracing::debug;
+use tracing::{debug, warn};
+
+/// How often a chunk is resent without the upload making progress
+const CHUNK_RETRIES: usize = 5;
+
+/// Chunks in flight during an upload if not configured otherwise
+pub const DEFAULT_WINDOW: usize = 2;
 
 fn to_hex(bytes: &[u8]) -> String {
     bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
 /// decode "hash" argument from CLI
-fn decode_hash_hex(s: &str) -> Result<[u8; 32]> {
+pub(crate) fn decode_hash_hex(s: &str) -> Result<[u8; 32]> {
     let cleaned: String = s.chars().filter(|c| c.is_ascii_hexdigit()).collect();
     if cleaned.len() != 64 {
         return Err(Error::HashHexLengthMismatch {
@@ -35,76 +45,225 @@ fn decode_hash_hex(s: &str) -> Result<[u8; 32]> {
     Ok(out)
 }
 
-pub async fn info(client: &mut Client, sequence: u8) -> Result<()> {
-    let ret: SmpFrame<GetImageStateResult> = client
-        .transceive_cbor(&application_management::get_state(sequence))
-        .await?;
+pub async fn info(client: &mut Client) -> Result<()> {
+    let payload = client.call(GetStatePayload {}).await?;
 
-    match ret.data {
-        GetImageStateResult::Ok(payload) => {
-            println!("---------------------------------------------------------------------------");
-            for img in payload.images {
-                if let Some(h) = img.hash {
-                    if h.len() == 32 {
-                        println!("slot:      {}", img.slot);
-                        println!("version:   {}", img.version);
-                        println!("active:    {}", img.active);
-                        println!("confirmed: {}", img.confirmed);
-                        println!("bootable:  {}", img.bootable);
-                        println!("pending:   {}", img.pending);
-                        println!("hash:      {}", to_hex(&h));
-                        println!("---------------------------------------------------------------------------");
-                    } else {
-                        eprintln!("unexpected hash length: {}", h.len());
-                    }
+    for (image, slots) in payload.by_image() {
+        println!("image {}", image);
+        println!("---------------------------------------------------------------------------");
+        for img in slots {
+            if let Some(h) = &img.hash {
+                if h.len() == 32 {
+                    println!("slot:      {}", img.slot);
+                    println!("version:   {}", img.version);
+                    println!("active:    {}", img.active);
+                    println!("confirmed: {}", img.confirmed);
+                    println!("bootable:  {}", img.bootable);
+                    println!("pending:   {}", img.pending);
+                    println!("permanent: {}", img.permanent);
+                    println!("hash:      {}", to_hex(h));
+                    println!("---------------------------------------------------------------------------");
+                } else {
+                    eprintln!("unexpected hash length: {}", h.len());
                 }
             }
         }
-        GetImageStateResult::Err(err) => {
-            eprintln!("rc: {}", err.rc);
-            if let Some(msg) = err.rsn {
-                eprintln!("rsn: {:?}", msg);
+    }
+    Ok(())
+}
+
+pub async fn get_img_state(client: &mut Client) -> Result<GetImageStatePayload> {
+    client.call(GetStatePayload {}).await
+}
+
+pub async fn erase(client: &mut Client, slot: Option<u32>) -> Result<()> {
+    client.call(EraseRequest { slot }).await?;
+    Ok(())
+}
+
+pub async fn slot_info(client: &mut Client) -> Result<SlotInfoResponse> {
+    client.call(SlotInfoRequest {}).await
+}
+
+pub async fn slots(client: &mut Client) -> Result<()> {
+    let info = slot_info(client).await?;
+
+    for image in info.images {
+        match image.max_image_size {
+            Some(max) => println!("image {} (max image size: {} bytes)", image.image, max),
+            None => println!("image {}", image.image),
+        }
+        for slot in image.slots {
+            match slot.upload_image_id {
+                Some(id) => println!(
+                    "  slot {}: {} bytes, upload to image {}",
+                    slot.slot, slot.size, id
+                ),
+                None => println!("  slot {}: {} bytes", slot.slot, slot.size),
             }
         }
     }
     Ok(())
 }
 
-pub async fn get_img_state(
-    client: &mut Client,
+/// Options of an image upload
+#[derive(Debug, Clone)]
+pub struct FlashOptions {
+    /// Image number to upload to, e.g. the core on multi-core devices
+    pub image: Option<u8>,
+    /// Upper limit of the data in a chunk, chunks are as large as the frame size allows
+    pub chunk_size: Option<usize>,
+    /// Only allow newer firmware versions
+    pub upgrade: bool,
+    /// 32-byte hash as hex, overrides the hash of the image
+    pub hash: Option<String>,
+    /// PEM key to check the image signature with before the upload
+    pub verify_key: Option<PathBuf>,
+    /// Continue an upload the device already holds part of
+    pub resume: bool,
+    /// Maximum number of chunks sent before their responses arrived, 1 is stop-and-wait
+    pub window: usize,
+}
+
+impl Default for FlashOptions {
+    fn default() -> Self {
+        Self {
+            image: None,
+            chunk_size: None,
+            upgrade: false,
+            hash: None,
+            verify_key: None,
+            resume: false,
+            window: DEFAULT_WINDOW,
+        }
+    }
+}
+
+/// Check the signature of an image file with a PEM key
+pub fn verify_signature(firmware: &[u8], key_file: &Path) -> Result<()> {
+    let key = PublicKey::from_pem(&std::fs::read_to_string(key_file)?)?;
+    McubootImage::parse(firmware)?.verify_signature(&key)?;
+    Ok(())
+}
+
+/// Chunk sent to the device whose response has not arrived yet
+struct InFlight {
     sequence: u8,
-) -> Result<GetImageStatePayload> {
-    let ret: SmpFrame<GetImageStateResult> = client
-        .transceive_cbor(&application_management::get_state(sequence))
-        .await?;
+    offset: usize,
+    /// offset the device reports once the chunk is written
+    end: usize,
+}
+
+/// Send one chunk at `offset`, an empty `data` probes the offset of an upload in progress
+async fn send_chunk(
+    transport: &mut Client,
+    updater: &mut ImageWriter<'_>,
+    offset: usize,
+    data: &[u8],
+) -> Result<InFlight> {
+    updater.offset = offset;
+    let mut frame = updater.write_chunk(data);
+    frame.sequence = transport.next_seq();
+    transport.send_cbor(&frame).await?;
 
-    match ret.data {
-        GetImageStateResult::Ok(payload) => Ok(payload),
+    Ok(InFlight {
+        sequence: frame.sequence,
+        offset,
+        end: offset + data.len(),
+    })
+}
 
-        GetImageStateResult::Err(err) => {
-            Err(Error::GetImageStateError(err))
+/// Largest request the transport and the device accept.
+/// Devices that don't report their buffer size are limited by the transport only.
+pub(crate) async fn frame_size(transport: &mut Client) -> usize {
+    let mtu = transport.mtu();
+    match transport.call(McumgrParamsRequest {}).await {
+        Ok(params) => {
+            debug!(
+                "device buffers: {} x {} bytes",
+                params.buf_count, params.buf_size
+            );
+            min(mtu, params.buf_size as usize)
+        }
+        Err(err) => {
+            debug!("MCUmgr parameters not available: {}", err);
+            mtu
         }
     }
 }
 
+/// Refuse images that don't fit into the slot they are uploaded to.
+/// Devices without the slot info command are not checked.
+async fn check_slot_size(transport: &mut Client, image: u32, len: usize) -> Result<()> {
+    let max = match slot_info(transport).await {
+        Ok(info) => info.max_upload_size(image),
+        Err(err) => {
+            debug!("slot info not available: {}", err);
+            None
+        }
+    };
+
+    match max {
+        Some(max) if len as u64 > max => Err(Error::Upload(format!(
+            "image of {} bytes does not fit into image {}, at most {} bytes",
+            len, image, max
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Upload an image with up to `options.window` chunks in flight.
+///
+/// The device only writes chunks in order and answers every request with the offset it has
+/// reached. A lost chunk or response rewinds the upload to that offset and halves the window,
+/// every written chunk grows it again.
 pub async fn flash(
     transport: &mut Client,
-    slot: Option<u8>,
     update_file: &Path,
-    chunk_size: usize,
-    upgrade: bool,
-    hash: &str,
+    options: &FlashOptions,
 ) -> Result<()> {
+    let FlashOptions {
+        image,
+        chunk_size,
+        upgrade,
+        ..
+    } = *options;
     let firmware = std::fs::read(update_file)?;
 
-    let decoded = decode_hash_hex(hash)?;
-    let hash: &[u8] = &decoded;
+    // fail before the upload instead of after the reboot
+    if let Some(key_file) = &options.verify_key {
+        verify_signature(&firmware, key_file)?;
+        println!("signature verified");
+    }
 
+    // the device reports the hash TLV for the slot, an explicit hash overrides it
+    let image_hash = match options.hash.as_deref() {
+        Some(hash) => decode_hash_hex(hash)?.to_vec(),
+        None => McubootImage::parse(&firmware)?.hash()?.to_vec(),
+    };
+    let sha = application_management::upload_sha(&firmware);
     let mut updater =
-        application_management::ImageWriter::new(slot, firmware.len(), Some(hash), upgrade);
+        application_management::ImageWriter::new(image, firmware.len(), Some(&sha), upgrade);
+    if let Some(image) = image {
+        println!("uploading to image {}", image);
+    }
+    println!("image hash: {}", to_hex(&image_hash));
+
+    check_slot_size(transport, image.unwrap_or(0).into(), firmware.len()).await?;
+
+    if chunk_size == Some(0) {
+        return Err(Error::Upload("chunk size must not be 0".to_string()));
+    }
+    let frame_size = frame_size(transport).await;
+    debug!("frame size: {} bytes", frame_size);
+    if updater.max_chunk_len(0, frame_size) == 0 {
+        return Err(Error::Upload(format!(
+            "first chunk does not fit into {} bytes",
+            frame_size
+        )));
+    }
 
     let mut verified = None;
-    let mut offset = 0usize;
 
     // progress bar setup
     let total = firmware.len() as u64;
@@ -116,32 +275,139 @@ pub async fn flash(
             .progress_chars("=>-"),
     );
 
-    while offset < firmware.len() {
-        let chunk = &firmware[offset..min(firmware.len(), offset + chunk_size)];
+    let max_window = options.window.max(1);
+    let mut window = max_window;
+    let mut in_flight: VecDeque<InFlight> = VecDeque::new();
+    // offset confirmed by the device
+    let mut acked = 0usize;
+    // offset of the next chunk to send
+    let mut next = 0usize;
+    // failures since the offset last advanced
+    let mut failures = 0;
+    // an empty first chunk makes the device report how far the upload already is
+    let mut probe = options.resume;
+    // only one chunk is sent until the device reported where the upload continues
+    let mut synced = false;
 
-        let resp_frame: SmpFrame<WriteImageChunkResult> = transport
-            .transceive_cbor(&updater.write_chunk(chunk))
-            .await?;
+    while acked < firmware.len() {
+        let limit = if synced { window } else { 1 };
+        let mut result = Ok(());
+        while in_flight.len() < limit && next < firmware.len() {
+            let len = match probe {
+                true => 0,
+                false => updater
+                    .max_chunk_len(next, frame_size)
+                    .min(chunk_size.unwrap_or(usize::MAX))
+                    .min(firmware.len() - next),
+            };
+            let chunk = &firmware[next..next + len];
+            match send_chunk(transport, &mut updater, next, chunk).await {
+                Ok(sent) => {
+                    next = sent.end;
+                    in_flight.push_back(sent);
+                }
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
 
-        match resp_frame.data {
-            WriteImageChunkResult::Ok(payload) => {
-                offset = payload.off as usize;
-                updater.offset = offset;
-                verified = payload.match_;
+        let response = match result {
+            Ok(()) => {
+                transport
+                    .receive_cbor::<WriteImageChunkResult>(|sequence| {
+                        in_flight.iter().any(|sent| sent.sequence == sequence)
+                    })
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+
+        let failure = match response {
+            Ok(frame) => {
+                let index = in_flight
+                    .iter()
+                    .position(|sent| sent.sequence == frame.sequence)
+                    .expect("only responses to chunks in flight are received");
+                let sent = in_flight.remove(index).expect("index is in bounds");
 
-                // advance progress bar by written chunk size
-                pb.set_position(offset as u64);
+                match frame.into_result() {
+                    // the first response decides where the upload continues
+                    Ok(payload) if !synced || payload.off as usize == sent.end => {
+                        let off = payload.off as usize;
+                        if !synced {
+                            if probe && off > 0 {
+                                println!("resuming upload at offset {}", off);
+                            }
+                            synced = true;
+                            probe = false;
+                            acked = off;
+                            next = off;
+                            failures = 0;
+                        } else if off > acked {
+                            acked = off;
+                            failures = 0;
+                            window = (window + 1).min(max_window);
+                        }
+                        if off == firmware.len() {
+                            verified = payload.match_;
+                        }
+
+                        // advance progress bar by written chunk size
+                        pb.set_position(acked as u64);
+                        continue;
+                    }
+                    // an earlier chunk was lost, continue where the device is
+                    Ok(payload) => {
+                        let off = payload.off as usize;
+                        if off > acked {
+                            failures = 0;
+                        }
+                        acked = off;
+                        next = off;
+                        in_flight.clear();
+                        Error::Upload(format!("chunk at offset {} not written", sent.offset))
+                    }
+                    // the device lost the upload, e.g. after a reboot, let it report where to go on
+                    Err(err) if sent.offset != 0 => {
+                        synced = false;
+                        probe = true;
+                        acked = 0;
+                        next = 0;
+                        in_flight.clear();
+                        err.into()
+                    }
+                    Err(err) => {
+                        pb.finish_and_clear();
+                        return Err(err.into());
+                    }
+                }
             }
-            WriteImageChunkResult::Err(err) => {
+            // chunks or responses were lost, resend everything that is not confirmed
+            Err(err) if err.is_transient() => {
+                next = acked;
+                in_flight.clear();
+                err
+            }
+            Err(err) => {
                 pb.finish_and_clear();
-                return Err(Error::WriteImageChunkError(err));
+                return Err(err);
             }
+        };
+
+        failures += 1;
+        window = (window / 2).max(1);
+        if failures > CHUNK_RETRIES {
+            pb.finish_and_clear();
+            return Err(failure);
         }
+        warn!("{}, continuing at offset {}", failure, next);
     }
 
     pb.finish_with_message("upload complete");
 
-    println!("sent all bytes: {}", offset);
+    println!("sent all bytes: {}", acked);
 
     if let Some(verified) = verified {
         if verified {
@@ -154,48 +420,53 @@ pub async fn flash(
     Ok(())
 }
 
-pub async fn confirm(transport: &mut Client, hash_hex: &str, sequence: u8) -> Result<()> {
+/// Describe a slot for error messages
+fn slot_name(state: &ImageState) -> String {
+    format!("image {} slot {}", state.image_number(), state.slot)
+}
+
+pub async fn confirm(transport: &mut Client, hash_hex: &str) -> Result<()> {
     let h: [u8; 32] = decode_hash_hex(hash_hex)?;
-    let ret: SmpFrame<GetImageStateResult> = transport
-        .transceive_cbor(&application_management::set_confirm(
-            h.to_vec(),
-            true,
-            sequence,
-        ))
+    let get_image_state_payload = transport
+        .call(SetConfirmState {
+            hash: h.to_vec(),
+            confirm: true,
+        })
         .await?;
-    match ret.data {
-        GetImageStateResult::Ok(get_image_state_payload) => {
-            let slot0 = get_image_state_payload
-                .images
-                .get(0)
-                .ok_or(Error::Confirm("slot0 does not exist".to_string()))?;
-
-            let incoming_hash = slot0
-                .hash
-                .as_ref()
-                .ok_or(Error::Confirm("hash does not exist".to_string()))?;
-
-            if to_hex(&incoming_hash) == hash_hex && slot0.confirmed == true {
-                Ok(())
-            } else {
-                Err(Error::Confirm("hash mismatch".to_string()))
-            }
-        }
-        GetImageStateResult::Err(get_image_state_error) => {
-            Err(Error::GetImageStateError(get_image_state_error))
-        }
+
+    // the device finds the image by hash, on multi-image devices it can be in any image
+    let state = get_image_state_payload
+        .find_hash(&h)
+        .ok_or(Error::Confirm("no slot holds the image".to_string()))?;
+
+    // a running image is confirmed directly, one in the secondary slot is swapped in permanently
+    if state.confirmed || (state.pending && state.permanent) {
+        Ok(())
+    } else {
+        Err(Error::Confirm(format!(
+            "{} is not confirmed",
+            slot_name(state)
+        )))
     }
 }
 
-pub async fn test_next_boot(transport: &mut Client, hash_hex: &str, sequence: u8) -> Result<()> {
+pub async fn test_next_boot(transport: &mut Client, hash_hex: &str) -> Result<()> {
     let h = decode_hash_hex(hash_hex)?;
-    let ret: SmpFrame<GetImageStateResult> = transport
-        .transceive_cbor(&application_management::set_pending(
-            h.to_vec(),
-            true,
-            sequence,
-        ))
+    // Zephyr's state write only knows hash and confirm, an unconfirmed write marks the image for test
+    let ret = transport
+        .call(SetConfirmState {
+            hash: h.to_vec(),
+            confirm: false,
+        })
         .await?;
     debug!("{:?}", ret);
-    Ok(())
+
+    let state = ret
+        .find_hash(&h)
+        .ok_or(Error::Test("no slot holds the image".to_string()))?;
+    if state.pending {
+        Ok(())
+    } else {
+        Err(Error::Test(format!("{} is not pending", slot_name(state))))
+    }
 }
@@ -1,5 +1,8 @@
 // smp-tool/src/ops/mod.rs
 
+pub mod fs_grp;
 pub mod img_grp;
 pub mod os_grp;
+pub mod settings_grp;
 pub mod shell_grp;
+pub mod stat_grp;
@@ -4,43 +4,135 @@ use crate::error::Result;
 
 use tracing::debug;
 
-use mcumgr_smp::{
-    os_management::{self, EchoResult, ResetResult},
-    smp::SmpFrame,
+use mcumgr_smp::os_management::{
+    BootloaderInfoRequest, EchoRequest, GetDateTimeRequest, GetInfoRequest, McumgrParamsRequest,
+    MemPoolStatRequest, OsInfo, ResetRequest, SetDateTimeRequest, TaskStatRequest,
 };
 
 use crate::client::Client;
 
-pub async fn echo(transport: &mut Client, msg: String, sequence: u8) -> Result<()> {
-    let ret: SmpFrame<EchoResult> = transport
-        .transceive_cbor(&os_management::echo(sequence, msg))
+fn opt<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_string(), |v| v.to_string())
+}
+
+pub async fn echo(transport: &mut Client, msg: String) -> Result<()> {
+    let ret = transport.call(EchoRequest { d: msg }).await?;
+    debug!("{:?}", ret);
+
+    println!("{}", ret.r);
+    Ok(())
+}
+
+pub async fn reset(transport: &mut Client, force: bool, boot_mode: Option<u8>) -> Result<()> {
+    let ret = transport
+        .call(ResetRequest {
+            force: force.then_some(true),
+            boot_mode,
+        })
         .await?;
     debug!("{:?}", ret);
 
-    match ret.data {
-        EchoResult::Ok { r } => {
-            println!("{}", r);
+    println!("Rebooted");
+    Ok(())
+}
+
+pub async fn tasks(transport: &mut Client) -> Result<()> {
+    let ret = transport.call(TaskStatRequest {}).await?;
+    debug!("{:?}", ret);
+
+    println!(
+        "{:<24} {:>5} {:>10} {:>6} {:>8} {:>8} {:>10}",
+        "task", "prio", "tid", "state", "stkuse", "stksiz", "cswcnt"
+    );
+    for (name, task) in ret.tasks {
+        println!(
+            "{:<24} {:>5} {:>10} {:>6} {:>8} {:>8} {:>10}",
+            name,
+            task.prio,
+            task.tid,
+            task.state,
+            opt(task.stkuse),
+            opt(task.stksiz),
+            opt(task.cswcnt)
+        );
+    }
+    Ok(())
+}
+
+pub async fn mpools(transport: &mut Client) -> Result<()> {
+    let ret = transport.call(MemPoolStatRequest {}).await?;
+    debug!("{:?}", ret);
+
+    println!(
+        "{:<24} {:>8} {:>8} {:>8} {:>8}",
+        "pool", "blksiz", "nblks", "nfree", "min"
+    );
+    for (name, pool) in ret.mpools {
+        println!(
+            "{:<24} {:>8} {:>8} {:>8} {:>8}",
+            name,
+            pool.blksiz,
+            pool.nblks,
+            pool.nfree,
+            opt(pool.min)
+        );
+    }
+    Ok(())
+}
+
+pub async fn datetime(transport: &mut Client, set: Option<String>) -> Result<()> {
+    match set {
+        Some(datetime) => {
+            let ret = transport.call(SetDateTimeRequest { datetime }).await?;
+            debug!("{:?}", ret);
         }
-        EchoResult::Err { rc } => {
-            eprintln!("rc: {}", rc);
+        None => {
+            let ret = transport.call(GetDateTimeRequest {}).await?;
+            println!("{}", ret.datetime);
         }
     }
     Ok(())
 }
 
-pub async fn reset(transport: &mut Client, sequence: u8) -> Result<()> {
-    let ret: SmpFrame<ResetResult> = transport
-        .transceive_cbor(&os_management::reset(sequence, false))
+pub async fn params(transport: &mut Client) -> Result<()> {
+    let ret = transport.call(McumgrParamsRequest {}).await?;
+    debug!("{:?}", ret);
+
+    println!("buf_size:  {}", ret.buf_size);
+    println!("buf_count: {}", ret.buf_count);
+    Ok(())
+}
+
+pub async fn info(transport: &mut Client, format: String) -> Result<()> {
+    let ret = transport
+        .call(GetInfoRequest {
+            format: Some(format.clone()),
+        })
         .await?;
     debug!("{:?}", ret);
 
-    match ret.data {
-        ResetResult::Ok {} => {
-            println!("Rebooted");
-        }
-        ResetResult::Err { rc } => {
-            eprintln!("rc: {}", rc);
-        }
+    let info = OsInfo::parse(&format, &ret.output);
+    if info.fields.is_empty() {
+        println!("{}", ret.output);
+    }
+    for (field, value) in info.fields {
+        println!("{:<18} {}", format!("{:?}:", field), value);
+    }
+    Ok(())
+}
+
+pub async fn bootloader(transport: &mut Client, query: Option<String>) -> Result<()> {
+    let ret = transport.call(BootloaderInfoRequest { query }).await?;
+    debug!("{:?}", ret);
+
+    if let Some(bootloader) = ret.bootloader {
+        println!("bootloader:   {}", bootloader);
+    }
+    if let Some(mode) = ret.mode {
+        println!("mode:         {}", mode);
+    }
+    if let Some(no_downgrade) = ret.no_downgrade {
+        println!("no-downgrade: {}", no_downgrade);
     }
     Ok(())
 }
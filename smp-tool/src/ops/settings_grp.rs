@@ -0,0 +1,129 @@
+// smp-tool/src/ops/settings_grp.rs
+
+use crate::error::Error;
+use crate::error::Result;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_bytes::ByteBuf;
+use tracing::debug;
+
+use mcumgr_smp::settings_management::{
+    CommitSettingsRequest, DeleteSettingRequest, ReadSettingRequest, SaveSettingsRequest,
+    WriteSettingRequest,
+};
+
+use crate::client::Client;
+
+/// Setting names mapped to their values as hex.
+/// Values are opaque bytes for SMP, hex keeps binary values intact in JSON and TOML.
+pub type SettingsBackup = BTreeMap<String, String>;
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "toml")
+}
+
+async fn read(client: &mut Client, name: &str) -> Result<Vec<u8>> {
+    let ret = client
+        .call(ReadSettingRequest {
+            name: name.to_string(),
+            max_size: None,
+        })
+        .await?;
+    debug!("{:?}", ret);
+
+    if let Some(max_size) = ret.max_size {
+        return Err(Error::Settings(format!(
+            "value of {} is larger than {} bytes",
+            name, max_size
+        )));
+    }
+    Ok(ret.val.into_vec())
+}
+
+async fn write(client: &mut Client, name: &str, val: Vec<u8>) -> Result<()> {
+    client
+        .call(WriteSettingRequest {
+            name: name.to_string(),
+            val: ByteBuf::from(val),
+        })
+        .await?;
+    Ok(())
+}
+
+pub async fn get(client: &mut Client, name: &str) -> Result<()> {
+    let val = read(client, name).await?;
+
+    println!("hex:    {}", hex::encode(&val));
+    if let Ok(s) = std::str::from_utf8(&val) {
+        println!("string: {}", s);
+    }
+    Ok(())
+}
+
+pub async fn set(client: &mut Client, name: &str, val: Vec<u8>) -> Result<()> {
+    write(client, name, val).await
+}
+
+pub async fn delete(client: &mut Client, name: &str) -> Result<()> {
+    client
+        .call(DeleteSettingRequest {
+            name: name.to_string(),
+        })
+        .await?;
+    Ok(())
+}
+
+pub async fn commit(client: &mut Client) -> Result<()> {
+    client.call(CommitSettingsRequest {}).await?;
+    Ok(())
+}
+
+/// Read the given settings and write them to `file`, as TOML for `.toml` files and JSON otherwise
+pub async fn export(client: &mut Client, names: &[String], file: &Path) -> Result<()> {
+    let mut backup = SettingsBackup::new();
+    for name in names {
+        backup.insert(name.clone(), hex::encode(read(client, name).await?));
+    }
+
+    let content = if is_toml(file) {
+        toml::to_string(&backup).map_err(|e| Error::Settings(e.to_string()))?
+    } else {
+        serde_json::to_string_pretty(&backup).map_err(|e| Error::Settings(e.to_string()))?
+    };
+    std::fs::write(file, content)?;
+
+    println!("exported {} settings", backup.len());
+    Ok(())
+}
+
+/// Write all settings of an export to the device, then commit and optionally save them
+pub async fn import(client: &mut Client, file: &Path, save: bool) -> Result<()> {
+    let content = std::fs::read_to_string(file)?;
+    let backup: SettingsBackup = if is_toml(file) {
+        toml::from_str(&content).map_err(|e| Error::Settings(e.to_string()))?
+    } else {
+        serde_json::from_str(&content).map_err(|e| Error::Settings(e.to_string()))?
+    };
+
+    // decode everything first, a typo should not leave the device half configured
+    let values = backup
+        .into_iter()
+        .map(|(name, val)| {
+            hex::decode(&val)
+                .map(|val| (name.clone(), val))
+                .map_err(|e| Error::Settings(format!("invalid value of {}: {}", name, e)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for (name, val) in &values {
+        write(client, name, val.clone()).await?;
+    }
+    commit(client).await?;
+    if save {
+        client.call(SaveSettingsRequest {}).await?;
+    }
+
+    println!("imported {} settings", values.len());
+    Ok(())
+}
@@ -8,50 +8,36 @@ use reedline::{
 };
 use tracing::debug;
 
-use mcumgr_smp::{
-    shell_management::{self, ShellResult},
-    smp::SmpFrame,
-};
+use mcumgr_smp::shell_management::{ShellCommand, ShellResponse};
 
 use crate::client::Client;
 
 /// This function sends a shell command to the smp server and expects a response within the timeout
-pub async fn transceive(transport: &mut Client, cmd: Vec<String>, sequence: u8) -> Result<String> {
-    let ret: SmpFrame<ShellResult> = transport
-        .transceive_cbor(&shell_management::shell_command(sequence, cmd))
-        .await?;
+pub async fn transceive(transport: &mut Client, cmd: Vec<String>) -> Result<String> {
+    let ret = transport.call(ShellCommand { argv: cmd }).await?;
     debug!("{:?}", ret);
 
-    match ret.data {
-        ShellResult::Ok { o, ret: 0 } => Ok(o),
-        ShellResult::Ok { o, ret } => Err(Error::TransceiveReturnErrorCode {
+    match ret {
+        ShellResponse { o, ret: 0 } => Ok(o),
+        ShellResponse { o, ret } => Err(Error::TransceiveReturnErrorCode {
             err_code: ret,
             output: o,
         }),
-        ShellResult::Err { rc } => Err(Error::ShellResultError(rc)),
     }
 }
 
 /// One-shot "exec" command: `smp-tool shell exec <cmd ...>`
-pub async fn exec(transport: &mut Client, cmd: Vec<String>, sequence: u8) -> Result<()> {
-    let ret: SmpFrame<ShellResult> = transport
-        .transceive_cbor(&shell_management::shell_command(sequence, cmd))
-        .await?;
+pub async fn exec(transport: &mut Client, cmd: Vec<String>) -> Result<()> {
+    let ret = transport.call(ShellCommand { argv: cmd }).await?;
     debug!("{:?}", ret);
 
-    match ret.data {
-        ShellResult::Ok { o, ret } => {
-            println!("ret: {}, o: {}", ret, o);
-        }
-        ShellResult::Err { rc } => {
-            eprintln!("rc: {}", rc);
-        }
-    }
+    let ShellResponse { o, ret } = ret;
+    println!("ret: {}, o: {}", ret, o);
     Ok(())
 }
 
 /// Interactive shell
-pub async fn interactive(transport: &mut Client, sequence: u8) -> Result<()> {
+pub async fn interactive(transport: &mut Client) -> Result<()> {
     let keybindings = default_emacs_keybindings();
     let edit_mode = Box::new(Emacs::new(keybindings));
 
@@ -66,28 +52,21 @@ pub async fn interactive(transport: &mut Client, sequence: u8) -> Result<()> {
         let sig = line_editor.read_line(&prompt)?;
 
         match sig {
-            Signal::Success(buffer) => 'succ: {
+            Signal::Success(buffer) => {
                 let argv: Vec<_> = buffer.split_whitespace().map(|s| s.to_owned()).collect();
 
-                let ret: Result<SmpFrame<ShellResult>, _> = transport
-                    .transceive_cbor(&shell_management::shell_command(sequence, argv))
-                    .await;
+                let ret = transport.call(ShellCommand { argv }).await;
                 debug!("{:?}", ret);
 
-                let data = match ret {
-                    Ok(smp_frame) => smp_frame.data,
-                    Err(err) => {
-                        println!("transport error: {}", err);
-                        break 'succ;
-                    }
-                };
-
-                match data {
-                    ShellResult::Ok { o, ret: _ } => {
+                match ret {
+                    Ok(ShellResponse { o, ret: _ }) => {
                         println!("{}", o);
                     }
-                    ShellResult::Err { rc } => {
-                        eprintln!("SMP Error: rc: {}", rc);
+                    Err(Error::Device(err)) => {
+                        eprintln!("SMP Error: {}", err);
+                    }
+                    Err(err) => {
+                        println!("transport error: {}", err);
                     }
                 }
             }
@@ -0,0 +1,72 @@
+// smp-tool/src/ops/stat_grp.rs
+
+use crate::error::Result;
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use mcumgr_smp::stat_management::{StatGroupPayload, StatGroupRequest, StatListRequest};
+
+use crate::client::Client;
+
+async fn read(client: &mut Client, group: &str) -> Result<StatGroupPayload> {
+    let ret = client
+        .call(StatGroupRequest {
+            name: group.to_string(),
+        })
+        .await?;
+    debug!("{:?}", ret);
+    Ok(ret)
+}
+
+pub async fn list(client: &mut Client) -> Result<()> {
+    let ret = client.call(StatListRequest {}).await?;
+    debug!("{:?}", ret);
+
+    for name in ret.stat_list {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+pub async fn show(client: &mut Client, group: &str) -> Result<()> {
+    let ret = read(client, group).await?;
+
+    println!("{}:", ret.name);
+    for (name, value) in ret.fields {
+        println!("  {:<24} {}", name, value);
+    }
+    Ok(())
+}
+
+/// Poll a group every `interval` and print the counters that changed.
+/// A lost poll is skipped, the next one prints the changes since the last answer.
+pub async fn watch(client: &mut Client, group: &str, interval: Duration) -> Result<()> {
+    let mut previous = read(client, group).await?;
+    for (name, value) in &previous.fields {
+        println!("{:<24} {}", name, value);
+    }
+
+    let mut ticker = tokio::time::interval(interval);
+    // the first tick completes immediately
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let current = match read(client, group).await {
+            Ok(current) => current,
+            Err(err) if err.is_transient() => {
+                warn!("reading {} failed: {}", group, err);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        for (name, delta) in current.delta(&previous) {
+            if delta != 0 {
+                println!("{:<24} {:+} ({})", name, delta, current.fields[&name]);
+            }
+        }
+        previous = current;
+    }
+}
@@ -9,7 +9,7 @@ use crate::error::Result;
 use mcumgr_smp::{
     application_management,
     shell_management::{self, ShellCommand},
-    smp::SmpFrame,
+    smp::{SmpFrame, SmpVersion},
     transport::{smp::CborSmpTransportAsync, udp::UdpTransportAsync},
     Group,
 };
@@ -20,6 +20,7 @@ pub struct Server {
     target_grp: Group,
     pub local_addr: SocketAddr,
     seq: u8,
+    version: SmpVersion,
 }
 
 impl Server {
@@ -33,6 +34,7 @@ impl Server {
             target_grp: Group::Default,
             local_addr,
             seq: 0,
+            version: SmpVersion::V1,
         })
     }
 
@@ -51,6 +53,8 @@ impl Server {
 
         self.target_grp = frame_any.group;
         self.seq = frame_any.sequence;
+        // answer in the version of the request, like a v2 capable device
+        self.version = frame_any.version;
 
         // 2) Dispatch by group
         match self.target_grp {
@@ -76,11 +80,17 @@ impl Server {
     pub async fn reply(&mut self, cmd: String) -> Result<()> {
         if self.target_grp == Group::ApplicationManagement {
             self.transport
-                .send_to_cbor(&application_management::get_state_response(self.seq, cmd))
+                .send_to_cbor(
+                    &application_management::get_state_response(self.seq, cmd)
+                        .with_version(self.version),
+                )
                 .await?;
         } else {
             self.transport
-                .send_to_cbor(&shell_management::shell_command_response(self.seq, cmd))
+                .send_to_cbor(
+                    &shell_management::shell_command_response(self.seq, cmd)
+                        .with_version(self.version),
+                )
                 .await?;
         }
 
@@ -0,0 +1,182 @@
+// smp-tool/src/simulator/fs.rs
+
+//! File management group on an in-memory file system that survives resets
+
+use std::collections::BTreeMap;
+
+use mcumgr_smp::device_error::FsMgmtError;
+use mcumgr_smp::fs_management::{
+    FileClosePayload, FileDownloadPayload, FileDownloadRequest, FileHashPayload, FileHashRequest,
+    FileStatusPayload, FileStatusRequest, FileUploadPayload, FsManagementCommand, HashOutput,
+    SupportedHashPayload, SupportedHashType,
+};
+use mcumgr_smp::{OpCode, ReturnCode};
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+use super::{Failure, Reply, Request, Simulator};
+
+/// Largest chunk of a download
+const DOWNLOAD_CHUNK: usize = 512;
+
+#[derive(Debug, Default)]
+pub(super) struct FileSystem {
+    files: BTreeMap<String, Vec<u8>>,
+    /// Zephyr keeps the file of the last chunk open until it is closed
+    open: Option<String>,
+}
+
+impl FileSystem {
+    pub(super) fn file(&self, name: &str) -> Option<&[u8]> {
+        self.files.get(name).map(Vec::as_slice)
+    }
+
+    pub(super) fn open_file(&self) -> Option<&str> {
+        self.open.as_deref()
+    }
+
+    fn get(&self, name: &str) -> Result<&Vec<u8>, Failure> {
+        self.files.get(name).ok_or(Failure::group(
+            FsMgmtError::FileNotFound,
+            ReturnCode::NoEntry,
+        ))
+    }
+
+    fn upload(&mut self, chunk: Chunk, stalled: bool) -> Result<FileUploadPayload, Failure> {
+        if !chunk.name.starts_with('/') {
+            return Err(Failure::group(
+                FsMgmtError::FileInvalidName,
+                ReturnCode::InvalidValue,
+            ));
+        }
+
+        if chunk.off == 0 {
+            self.files.insert(chunk.name.clone(), Vec::new());
+        }
+        let file = self.files.get_mut(&chunk.name).ok_or(Failure::group(
+            FsMgmtError::FileNotFound,
+            ReturnCode::NoEntry,
+        ))?;
+        if chunk.off > file.len() {
+            return Err(Failure::group(
+                FsMgmtError::FileOffsetLargerThanFile,
+                ReturnCode::InvalidValue,
+            ));
+        }
+        if !stalled {
+            file.truncate(chunk.off);
+            file.extend_from_slice(&chunk.data);
+        }
+        self.open = Some(chunk.name);
+        Ok(FileUploadPayload {
+            off: file.len() as u32,
+        })
+    }
+
+    fn download(&mut self, download: FileDownloadRequest) -> Result<FileDownloadPayload, Failure> {
+        let file = self.get(&download.name)?;
+        if download.off > file.len() {
+            return Err(Failure::group(
+                FsMgmtError::FileOffsetLargerThanFile,
+                ReturnCode::InvalidValue,
+            ));
+        }
+        let end = file.len().min(download.off + DOWNLOAD_CHUNK);
+        let chunk = FileDownloadPayload {
+            off: download.off as u32,
+            data: ByteBuf::from(&file[download.off..end]),
+            len: (download.off == 0).then_some(file.len() as u32),
+        };
+        self.open = Some(download.name);
+        Ok(chunk)
+    }
+
+    fn hash(&self, hash: FileHashRequest) -> Result<FileHashPayload, Failure> {
+        let file = self.get(&hash.name)?;
+        let off = (hash.off.unwrap_or(0) as usize).min(file.len());
+        let len = (hash.len.map(|len| len as usize))
+            .unwrap_or(usize::MAX)
+            .min(file.len() - off);
+        let data = &file[off..off + len];
+
+        let type_ = hash.type_.unwrap_or_else(|| "sha256".to_string());
+        let output = match type_.as_str() {
+            "sha256" => HashOutput::Hash(ByteBuf::from(Sha256::digest(data).to_vec())),
+            "crc32" => HashOutput::Checksum(crc32(data) as u64),
+            _ => {
+                return Err(Failure::group(
+                    FsMgmtError::ChecksumHashNotFound,
+                    ReturnCode::InvalidValue,
+                ))
+            }
+        };
+        Ok(FileHashPayload {
+            type_,
+            off: off as u32,
+            len: len as u32,
+            output,
+        })
+    }
+}
+
+/// Owned [FileUploadChunk](mcumgr_smp::fs_management::FileUploadChunk)
+#[derive(Deserialize)]
+struct Chunk {
+    name: String,
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+    off: usize,
+}
+
+/// IEEE CRC32 as used by the Zephyr fs group
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+pub(super) fn handle(simulator: &mut Simulator, request: &Request) -> Reply {
+    use FsManagementCommand::*;
+    let fs = &mut simulator.fs;
+
+    if request.is(OpCode::WriteRequest, File) {
+        let written = fs.upload(request.payload()?, simulator.faults.fs_stalled)?;
+        Ok(request.respond(written))
+    } else if request.is(OpCode::ReadRequest, File) {
+        let chunk = fs.download(request.payload()?)?;
+        Ok(request.respond(chunk))
+    } else if request.is(OpCode::ReadRequest, Status) {
+        let status: FileStatusRequest = request.payload()?;
+        let len = fs.get(&status.name)?.len() as u32;
+        Ok(request.respond(FileStatusPayload { len }))
+    } else if request.is(OpCode::ReadRequest, HashChecksum) {
+        let hash = fs.hash(request.payload()?)?;
+        Ok(request.respond(hash))
+    } else if request.is(OpCode::ReadRequest, SupportedHashChecksum) {
+        let types = BTreeMap::from([
+            (
+                "crc32".to_string(),
+                SupportedHashType { format: 0, size: 4 },
+            ),
+            (
+                "sha256".to_string(),
+                SupportedHashType {
+                    format: 1,
+                    size: 32,
+                },
+            ),
+        ]);
+        Ok(request.respond(SupportedHashPayload { types }))
+    } else if request.is(OpCode::WriteRequest, Close) {
+        fs.open = None;
+        Ok(request.respond(FileClosePayload {}))
+    } else {
+        Err(ReturnCode::NotSupported.into())
+    }
+}
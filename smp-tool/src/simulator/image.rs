@@ -0,0 +1,400 @@
+// smp-tool/src/simulator/image.rs
+
+//! Image group and MCUboot in swap mode with a primary and a secondary slot
+
+use mcumgr_smp::application_management::{
+    ApplicationManagementCommand, GetImageStatePayload, ImageSlots, ImageState, SlotInfo,
+    SlotInfoResponse, WriteImageChunkPayload,
+};
+use mcumgr_smp::device_error::ImgMgmtError;
+use mcumgr_smp::mcuboot_image::{ImageHeader, ImageVersion, McubootImage, IMAGE_MAGIC};
+use mcumgr_smp::{OpCode, ReturnCode};
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+use super::{Failure, Reply, Request, Simulator, SimulatorOptions};
+
+/// Size of the MCUboot trailer at the end of a slot
+const TRAILER_SIZE: u32 = 0x1000;
+
+/// Build an MCUboot image with a correct SHA256 hash TLV, e.g. to upload to the simulator
+pub fn build_image(version: ImageVersion, payload: &[u8]) -> Vec<u8> {
+    const HEADER_SIZE: u16 = 0x200;
+
+    let mut image = IMAGE_MAGIC.to_le_bytes().to_vec();
+    image.extend(0u32.to_le_bytes()); // load address
+    image.extend(HEADER_SIZE.to_le_bytes());
+    image.extend(0u16.to_le_bytes()); // protected TLVs
+    image.extend((payload.len() as u32).to_le_bytes());
+    image.extend(0u32.to_le_bytes()); // flags
+    image.extend([version.major, version.minor]);
+    image.extend(version.revision.to_le_bytes());
+    image.extend(version.build_num.to_le_bytes());
+    image.resize(HEADER_SIZE as usize, 0);
+    image.extend_from_slice(payload);
+
+    let hash = Sha256::digest(&image);
+    // TLV info, then the SHA256 TLV
+    image.extend(0x6907u16.to_le_bytes());
+    image.extend((4u16 + 4 + 32).to_le_bytes());
+    image.extend(0x10u16.to_le_bytes());
+    image.extend(32u16.to_le_bytes());
+    image.extend_from_slice(&hash);
+    image
+}
+
+/// Image in a slot
+#[derive(Debug, Clone)]
+struct Slot {
+    hash: Vec<u8>,
+    version: ImageVersion,
+    /// MCUboot accepts the image, its hash matches
+    valid: bool,
+}
+
+impl Slot {
+    fn new(data: &[u8]) -> Self {
+        let Ok(image) = McubootImage::parse(data) else {
+            return Self {
+                hash: Sha256::digest(data).to_vec(),
+                version: ImageVersion::default(),
+                valid: false,
+            };
+        };
+        Self {
+            hash: image
+                .hash()
+                .map(<[u8]>::to_vec)
+                .unwrap_or_else(|_| Sha256::digest(data).to_vec()),
+            version: image.header.version,
+            valid: image.verify_hash().unwrap_or(false),
+        }
+    }
+}
+
+/// The version as Zephyr reports it, the build number only if it is set
+fn format_version(version: ImageVersion) -> String {
+    let ImageVersion {
+        major,
+        minor,
+        revision,
+        build_num,
+    } = version;
+    match build_num {
+        0 => format!("{}.{}.{}", major, minor, revision),
+        _ => format!("{}.{}.{}.{}", major, minor, revision, build_num),
+    }
+}
+
+struct Upload {
+    data: Vec<u8>,
+    len: usize,
+    sha: Option<Vec<u8>>,
+}
+
+pub(super) struct Images {
+    primary: Slot,
+    secondary: Option<Slot>,
+    /// the primary slot runs an image that is reverted on the next reset unless confirmed
+    testing: bool,
+    /// the secondary slot is swapped in on the next reset, permanently if `Some(true)`
+    pending: Option<bool>,
+    upload: Option<Upload>,
+    slot_size: u32,
+}
+
+impl Images {
+    pub(super) fn new(options: &SimulatorOptions) -> Self {
+        let version = ImageVersion {
+            major: 1,
+            ..Default::default()
+        };
+        let data = options
+            .image
+            .clone()
+            .unwrap_or_else(|| build_image(version, b"simulated firmware"));
+        Self {
+            primary: Slot::new(&data),
+            secondary: None,
+            testing: false,
+            pending: None,
+            upload: None,
+            slot_size: options.slot_size,
+        }
+    }
+
+    pub(super) fn running(&self) -> (Vec<u8>, String) {
+        (
+            self.primary.hash.clone(),
+            format_version(self.primary.version),
+        )
+    }
+
+    /// MCUboot on reset, a new image that `crashes` is swapped back right away
+    pub(super) fn boot(&mut self, crashes: bool) {
+        self.upload = None;
+
+        match self.pending.take() {
+            Some(permanent) => match self.secondary.take() {
+                // the new image never gets to confirm itself
+                Some(secondary) if crashes => self.secondary = Some(secondary),
+                // swap
+                Some(secondary) if secondary.valid => {
+                    self.secondary = Some(std::mem::replace(&mut self.primary, secondary));
+                    self.testing = !permanent;
+                }
+                // an image that fails validation is erased
+                _ => {}
+            },
+            // an unconfirmed image is swapped back
+            None if self.testing => {
+                if let Some(secondary) = self.secondary.take() {
+                    self.secondary = Some(std::mem::replace(&mut self.primary, secondary));
+                }
+                self.testing = false;
+            }
+            None => {}
+        }
+    }
+
+    fn state(&self) -> GetImageStatePayload {
+        let slot = |number: i32, slot: &Slot| ImageState {
+            image: Some(0),
+            slot: number,
+            version: format_version(slot.version),
+            hash: Some(ByteBuf::from(slot.hash.clone())),
+            bootable: slot.valid,
+            pending: number == 1 && self.pending.is_some(),
+            confirmed: number == 0 && !self.testing,
+            active: number == 0,
+            permanent: number == 1 && self.pending == Some(true),
+        };
+
+        let mut images = vec![slot(0, &self.primary)];
+        images.extend(self.secondary.iter().map(|secondary| slot(1, secondary)));
+        GetImageStatePayload {
+            images,
+            split_status: None,
+        }
+    }
+
+    /// The secondary slot holds the image to revert to and must not be overwritten
+    fn secondary_in_use(&self) -> bool {
+        self.testing || self.pending.is_some()
+    }
+
+    fn write_state(&mut self, write: StateWrite) -> Result<(), Failure> {
+        let secondary = self.secondary.as_ref().map(|slot| slot.hash.as_slice());
+        match write.hash.as_ref().map(|hash| hash.as_slice()) {
+            // confirm the running image
+            None if write.confirm => self.testing = false,
+            None => {
+                return Err(Failure::group(
+                    ImgMgmtError::InvalidHash,
+                    ReturnCode::InvalidValue,
+                ))
+            }
+            Some(hash) if hash == self.primary.hash.as_slice() => {
+                if !write.confirm {
+                    return Err(Failure::group(
+                        ImgMgmtError::ImageSettingTestToActiveDenied,
+                        ReturnCode::BadState,
+                    ));
+                }
+                self.testing = false;
+            }
+            Some(hash) if Some(hash) == secondary => {
+                if self.testing {
+                    // the running image must be confirmed before the next one can be tested
+                    return Err(Failure::group(
+                        ImgMgmtError::ImageConfirmationDenied,
+                        ReturnCode::BadState,
+                    ));
+                }
+                self.pending = Some(write.confirm);
+            }
+            Some(_) => {
+                return Err(Failure::group(
+                    ImgMgmtError::HashNotFound,
+                    ReturnCode::InvalidValue,
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&mut self, chunk: Chunk) -> Result<WriteImageChunkPayload, Failure> {
+        if chunk.image.is_some_and(|image| image != 0) {
+            return Err(Failure::group(
+                ImgMgmtError::InvalidSlot,
+                ReturnCode::InvalidValue,
+            ));
+        }
+
+        if chunk.off == 0 {
+            // the probe of a resume carries no data, the header is checked once it arrives
+            if !chunk.data.is_empty() {
+                self.check_header(&chunk.data, chunk.upgrade)?;
+            }
+            let len = chunk.len.ok_or(ReturnCode::InvalidValue)?;
+            let sha = chunk.sha.map(ByteBuf::into_vec);
+
+            // the same image again continues the upload
+            let resume = self
+                .upload
+                .as_ref()
+                .is_some_and(|upload| upload.len == len && sha.is_some() && upload.sha == sha);
+            if !resume {
+                self.start_upload(len, sha)?;
+            }
+        }
+
+        let upload = self.upload.as_mut().ok_or(Failure::group(
+            ImgMgmtError::InvalidOffset,
+            ReturnCode::InvalidValue,
+        ))?;
+        // chunks are only written in order, the response tells the client where to continue
+        if chunk.off == upload.data.len() {
+            if upload.data.len() + chunk.data.len() > upload.len {
+                return Err(Failure::group(
+                    ImgMgmtError::InvalidImageDataOverrun,
+                    ReturnCode::InvalidValue,
+                ));
+            }
+            upload.data.extend_from_slice(&chunk.data);
+        }
+
+        let off = upload.data.len();
+        if off == upload.len && self.secondary.is_none() {
+            self.secondary = Some(Slot::new(&upload.data));
+        }
+        Ok(WriteImageChunkPayload {
+            off: off as u32,
+            match_: None,
+        })
+    }
+
+    fn check_header(&self, data: &[u8], upgrade: bool) -> Result<(), Failure> {
+        let header = ImageHeader::parse(data).map_err(|_| {
+            Failure::group(
+                ImgMgmtError::InvalidImageHeaderMagic,
+                ReturnCode::InvalidValue,
+            )
+        })?;
+        if upgrade && header.version <= self.primary.version {
+            return Err(Failure::group(
+                ImgMgmtError::CurrentVersionIsNewer,
+                ReturnCode::InvalidValue,
+            ));
+        }
+        Ok(())
+    }
+
+    fn start_upload(&mut self, len: usize, sha: Option<Vec<u8>>) -> Result<(), Failure> {
+        if self.secondary_in_use() {
+            return Err(Failure::group(
+                ImgMgmtError::NoFreeSlot,
+                ReturnCode::BadState,
+            ));
+        }
+        if len as u32 > self.slot_size - TRAILER_SIZE {
+            return Err(Failure::group(
+                ImgMgmtError::InvalidImageTooLarge,
+                ReturnCode::InvalidValue,
+            ));
+        }
+        // starting an upload erases the slot
+        self.secondary = None;
+        self.upload = Some(Upload {
+            data: Vec::new(),
+            len,
+            sha,
+        });
+        Ok(())
+    }
+
+    fn erase(&mut self, slot: Option<u32>) -> Result<(), Failure> {
+        match slot.unwrap_or(1) {
+            1 if self.secondary_in_use() => Err(Failure::group(
+                ImgMgmtError::ImageSettingTestToActiveDenied,
+                ReturnCode::BadState,
+            )),
+            1 => {
+                self.secondary = None;
+                self.upload = None;
+                Ok(())
+            }
+            _ => Err(Failure::group(
+                ImgMgmtError::InvalidSlot,
+                ReturnCode::InvalidValue,
+            )),
+        }
+    }
+
+    fn slot_info(&self) -> SlotInfoResponse {
+        let slot = |slot| SlotInfo {
+            slot,
+            size: self.slot_size as u64,
+            upload_image_id: None,
+        };
+        SlotInfoResponse {
+            images: vec![ImageSlots {
+                image: 0,
+                slots: vec![slot(0), slot(1)],
+                max_image_size: Some(self.slot_size - TRAILER_SIZE),
+            }],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct StateWrite {
+    #[serde(default)]
+    hash: Option<ByteBuf>,
+    #[serde(default)]
+    confirm: bool,
+}
+
+#[derive(Deserialize)]
+struct Chunk {
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+    off: usize,
+    #[serde(default)]
+    image: Option<u8>,
+    #[serde(default)]
+    len: Option<usize>,
+    #[serde(default)]
+    sha: Option<ByteBuf>,
+    #[serde(default)]
+    upgrade: bool,
+}
+
+#[derive(Deserialize)]
+struct Erase {
+    #[serde(default)]
+    slot: Option<u32>,
+}
+
+pub(super) fn handle(simulator: &mut Simulator, request: &Request) -> Reply {
+    let images = &mut simulator.images;
+
+    if request.is(OpCode::ReadRequest, ApplicationManagementCommand::State) {
+        Ok(request.respond(images.state()))
+    } else if request.is(OpCode::WriteRequest, ApplicationManagementCommand::State) {
+        images.write_state(request.payload()?)?;
+        Ok(request.respond(images.state()))
+    } else if request.is(OpCode::WriteRequest, ApplicationManagementCommand::Upload) {
+        let written = images.write_chunk(request.payload()?)?;
+        Ok(request.respond(written))
+    } else if request.is(OpCode::WriteRequest, ApplicationManagementCommand::Erase) {
+        let erase: Erase = request.payload()?;
+        images.erase(erase.slot)?;
+        Ok(request.respond(serde_json::json!({})))
+    } else if request.is(OpCode::ReadRequest, ApplicationManagementCommand::SlotInfo) {
+        Ok(request.respond(images.slot_info()))
+    } else {
+        Err(ReturnCode::NotSupported.into())
+    }
+}
@@ -0,0 +1,411 @@
+// smp-tool/src/simulator/mod.rs
+
+//! Simulated Zephyr device with MCUboot, for development and tests without hardware.
+//!
+//! [Simulator] answers SMP requests from in-memory state: two image slots that MCUboot swaps
+//! on reset, the os group, statistics, settings, a file system and a pluggable [Shell].
+//! It works on encoded frames and not on a connection, so it runs on any transport:
+//! [serve] answers the requests arriving on a UDP socket or a pseudo terminal,
+//! [SimulatorTransport] connects a [Client](crate::client::Client) in the same process.
+
+mod fs;
+mod image;
+mod os;
+mod settings;
+mod shell;
+mod stat;
+
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use mcumgr_smp::transport::error::Error as TransportError;
+use mcumgr_smp::transport::smp::SmpTransportAsync;
+use mcumgr_smp::{
+    DeviceErrorCode, Group, OpCode, ReturnCode, SmpFrame, SmpGroupError, SmpResponse, SmpVersion,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::Result;
+
+pub use image::build_image;
+pub use shell::{BasicShell, Shell};
+
+#[derive(Debug, Clone)]
+pub struct SimulatorOptions {
+    /// MCUboot image in the primary slot, a generated 1.0.0 image if not set
+    pub image: Option<Vec<u8>>,
+    /// Size of each of the two image slots
+    pub slot_size: u32,
+    /// Size of an SMP buffer, the largest request the device accepts
+    pub buf_size: u32,
+    pub buf_count: u32,
+    /// The device doesn't answer for this long after a reset
+    pub reboot_time: Duration,
+    /// Node name in the os info
+    pub name: String,
+}
+
+impl Default for SimulatorOptions {
+    fn default() -> Self {
+        Self {
+            image: None,
+            slot_size: 0x40000,
+            buf_size: 2048,
+            buf_count: 4,
+            reboot_time: Duration::from_millis(200),
+            name: "smp-simulator".to_string(),
+        }
+    }
+}
+
+/// Misbehaviour of the device, for tests of how clients cope with it
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    /// the device resets instead of answering the request with this index, counted from 0
+    pub reset_at: Option<usize>,
+    /// new images crash on their first boot and MCUboot goes back to the previous image
+    pub boot_failure: bool,
+    /// file uploads are answered without writing anything, like a full file system
+    pub fs_stalled: bool,
+}
+
+impl SimulatorOptions {
+    /// Read the primary slot image from a file
+    pub fn with_image_file(mut self, path: &Path) -> Result<Self> {
+        self.image = Some(std::fs::read(path)?);
+        Ok(self)
+    }
+}
+
+pub struct Simulator {
+    options: SimulatorOptions,
+    images: image::Images,
+    fs: fs::FileSystem,
+    settings: settings::Settings,
+    stats: stat::Stats,
+    shell: Box<dyn Shell>,
+    faults: Faults,
+    /// requests that reached the device
+    requests: usize,
+    /// added to the host clock, changed by setting the date and time
+    clock_offset_ms: i64,
+    booted: Instant,
+    /// the device is rebooting until then and doesn't answer
+    offline_until: Option<Instant>,
+    /// reset after the current response was sent
+    reset: bool,
+}
+
+impl Simulator {
+    pub fn new(options: SimulatorOptions) -> Self {
+        let images = image::Images::new(&options);
+        Self {
+            options,
+            images,
+            fs: fs::FileSystem::default(),
+            settings: settings::Settings::default(),
+            stats: stat::Stats::default(),
+            shell: Box::new(BasicShell),
+            faults: Faults::default(),
+            requests: 0,
+            clock_offset_ms: 0,
+            booted: Instant::now(),
+            offline_until: None,
+            reset: false,
+        }
+    }
+
+    /// Run shell commands with `shell` instead of [BasicShell]
+    pub fn with_shell(mut self, shell: impl Shell + 'static) -> Self {
+        self.shell = Box::new(shell);
+        self
+    }
+
+    /// Run into `faults`
+    pub fn with_faults(mut self, faults: Faults) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    pub fn options(&self) -> &SimulatorOptions {
+        &self.options
+    }
+
+    /// Hash and version of the running image
+    pub fn running_image(&self) -> (Vec<u8>, String) {
+        self.images.running()
+    }
+
+    /// Content of a file, e.g. to check an upload
+    pub fn file(&self, name: &str) -> Option<&[u8]> {
+        self.fs.file(name)
+    }
+
+    /// The file kept open since the last upload or download chunk, until it is closed
+    pub fn open_file(&self) -> Option<&str> {
+        self.fs.open_file()
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.offline_until
+            .is_none_or(|until| Instant::now() >= until)
+    }
+
+    /// Answer an encoded request frame.
+    /// Returns nothing for broken frames, responses and while the device reboots.
+    pub fn handle(&mut self, frame: &[u8]) -> Option<Vec<u8>> {
+        let index = self.requests;
+        self.requests += 1;
+        if self.faults.reset_at == Some(index) {
+            self.reboot();
+        }
+        if !self.is_online() {
+            return None;
+        }
+        let request = Request::parse(frame)?;
+
+        let reply = match request.header.group {
+            Group::Default => os::handle(self, &request),
+            Group::ApplicationManagement => image::handle(self, &request),
+            Group::Statistics => stat::handle(self, &request),
+            Group::Settings => settings::handle(self, &request),
+            Group::FileManagement => fs::handle(self, &request),
+            Group::ShellManagement => shell::handle(self, &request),
+            _ => Err(ReturnCode::NotSupported.into()),
+        };
+        self.stats.count(reply.is_ok());
+        let response = reply.unwrap_or_else(|failure| request.fail(failure));
+
+        if std::mem::take(&mut self.reset) {
+            self.reboot();
+        }
+        Some(response)
+    }
+
+    /// What happens on a reset: MCUboot swaps images and everything in RAM is lost
+    fn reboot(&mut self) {
+        self.images.boot(self.faults.boot_failure);
+        self.settings.load();
+        self.stats = stat::Stats::default();
+        self.booted = Instant::now();
+        self.offline_until = Some(Instant::now() + self.options.reboot_time);
+    }
+}
+
+/// A request frame and its decoded header
+struct Request<'a> {
+    header: SmpFrame<()>,
+    frame: &'a [u8],
+}
+
+/// Encoded response or the error to answer with
+type Reply = Result<Vec<u8>, Failure>;
+
+/// Error answered instead of a response payload
+#[derive(Debug, Clone, Copy)]
+struct Failure {
+    /// returned to SMP version 2 requests
+    code: DeviceErrorCode,
+    /// returned to SMP version 1 requests, which only know the generic codes
+    legacy: ReturnCode,
+}
+
+impl Failure {
+    fn group(code: impl Into<DeviceErrorCode>, legacy: ReturnCode) -> Self {
+        Self {
+            code: code.into(),
+            legacy,
+        }
+    }
+}
+
+impl From<ReturnCode> for Failure {
+    fn from(rc: ReturnCode) -> Self {
+        Self::group(rc, rc)
+    }
+}
+
+impl<'a> Request<'a> {
+    fn parse(frame: &'a [u8]) -> Option<Self> {
+        let header = SmpFrame::decode(frame, |_| Ok(())).ok()?;
+        matches!(header.operation, OpCode::ReadRequest | OpCode::WriteRequest)
+            .then_some(Self { header, frame })
+    }
+
+    fn is(&self, operation: OpCode, command: impl Into<u8>) -> bool {
+        self.header.operation == operation && self.header.command == command.into()
+    }
+
+    fn payload<T: DeserializeOwned>(&self) -> Result<T, Failure> {
+        SmpFrame::<T>::decode_with_cbor(self.frame)
+            .map(|frame| frame.data)
+            .map_err(|_| ReturnCode::InvalidValue.into())
+    }
+
+    fn respond<T: Serialize>(&self, payload: T) -> Vec<u8> {
+        let operation = match self.header.operation {
+            OpCode::ReadRequest => OpCode::ReadResponse,
+            _ => OpCode::WriteResponse,
+        };
+        SmpFrame::new(
+            operation,
+            self.header.sequence,
+            self.header.group,
+            self.header.command,
+            payload,
+        )
+        .with_version(self.header.version)
+        .encode_with_cbor()
+    }
+
+    fn fail(&self, failure: Failure) -> Vec<u8> {
+        let group_error = self.header.version == SmpVersion::V2
+            && !matches!(failure.code, DeviceErrorCode::Mgmt(_));
+        let response: SmpResponse<()> = if group_error {
+            SmpResponse::GroupErr(SmpGroupError {
+                group: self.header.group.into(),
+                rc: failure.code.rc(),
+            })
+        } else {
+            SmpResponse::Err {
+                rc: failure.legacy.into(),
+                rsn: None,
+            }
+        };
+        self.respond(response)
+    }
+}
+
+/// Answer the requests arriving on `transport` until it fails
+pub async fn serve(
+    simulator: &Mutex<Simulator>,
+    mut transport: impl SmpTransportAsync,
+) -> Result<()> {
+    loop {
+        let frame = match transport.receive().await {
+            Ok(frame) => frame,
+            Err(TransportError::Io(err)) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let response = simulator.lock().unwrap().handle(&frame);
+        if let Some(response) = response {
+            transport.send_to(response).await?;
+        }
+    }
+}
+
+/// How long a receive waits when the simulator didn't answer, like a lost response
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Faults of the link between a client and the simulator, requests are counted from 0
+#[derive(Debug, Clone, Default)]
+pub struct LinkFaults {
+    /// requests that are lost on the way to the device
+    pub lost: Vec<usize>,
+    /// requests whose response only arrives after the next request was sent
+    pub delayed: Vec<usize>,
+    /// sending fails after this many requests, like a pulled cable
+    pub disconnect_after: Option<usize>,
+}
+
+/// What went over a link
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    pub requests: usize,
+    /// length of the longest request
+    pub max_request: usize,
+    /// most responses waiting to be received at once
+    pub max_pending: usize,
+}
+
+/// Connection to a simulator in the same process
+pub struct SimulatorTransport {
+    simulator: Arc<Mutex<Simulator>>,
+    responses: VecDeque<Vec<u8>>,
+    /// responses held back by [LinkFaults::delayed]
+    delayed: Vec<Vec<u8>>,
+    faults: LinkFaults,
+    stats: Arc<Mutex<LinkStats>>,
+    mtu: Option<usize>,
+}
+
+impl SimulatorTransport {
+    pub fn new(simulator: Arc<Mutex<Simulator>>) -> Self {
+        Self {
+            simulator,
+            responses: VecDeque::new(),
+            delayed: Vec::new(),
+            faults: LinkFaults::default(),
+            stats: Arc::default(),
+            mtu: None,
+        }
+    }
+
+    pub fn with_faults(mut self, faults: LinkFaults) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Limit frames to `mtu` bytes instead of the SMP buffer size of the simulator
+    pub fn with_mtu(mut self, mtu: usize) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// Statistics that stay readable after the transport is handed over, e.g. to a client
+    pub fn stats_handle(&self) -> Arc<Mutex<LinkStats>> {
+        self.stats.clone()
+    }
+}
+
+#[async_trait]
+impl SmpTransportAsync for SimulatorTransport {
+    async fn send(&mut self, frame: Vec<u8>) -> Result<(), TransportError> {
+        let mut stats = self.stats.lock().unwrap();
+        if self
+            .faults
+            .disconnect_after
+            .is_some_and(|requests| stats.requests >= requests)
+        {
+            return Err(io::Error::from(io::ErrorKind::NotConnected).into());
+        }
+        let index = stats.requests;
+        stats.requests += 1;
+        stats.max_request = stats.max_request.max(frame.len());
+        if self.faults.lost.contains(&index) {
+            return Ok(());
+        }
+
+        let response = self.simulator.lock().unwrap().handle(&frame);
+        self.responses.extend(self.delayed.drain(..));
+        if self.faults.delayed.contains(&index) {
+            self.delayed.extend(response);
+        } else {
+            self.responses.extend(response);
+        }
+        stats.max_pending = stats.max_pending.max(self.responses.len());
+        Ok(())
+    }
+
+    async fn send_to(&mut self, frame: Vec<u8>) -> Result<(), TransportError> {
+        self.send(frame).await
+    }
+
+    async fn receive(&mut self) -> Result<Vec<u8>, TransportError> {
+        if let Some(response) = self.responses.pop_front() {
+            return Ok(response);
+        }
+        tokio::time::sleep(RECEIVE_TIMEOUT).await;
+        Err(io::Error::from(io::ErrorKind::TimedOut).into())
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+            .unwrap_or_else(|| self.simulator.lock().unwrap().options.buf_size as usize)
+    }
+}
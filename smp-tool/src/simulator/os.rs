@@ -0,0 +1,237 @@
+// smp-tool/src/simulator/os.rs
+
+//! OS group: echo, reset, parameters, date and time, info
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use mcumgr_smp::device_error::OsMgmtError;
+use mcumgr_smp::os_management::{
+    BootloaderInfoRequest, BootloaderInfoResponse, DateTimeResponse, EchoRequest, EchoResponse,
+    GetInfoRequest, GetInfoResponse, McumgrParamsResponse, MemPoolStat, MemPoolStatResponse,
+    OsInfoField, OsManagementCommand, ResetResponse, SetDateTimeRequest, SetDateTimeResponse,
+    TaskStat, TaskStatResponse,
+};
+use mcumgr_smp::{OpCode, ReturnCode};
+
+use super::{Failure, Reply, Request, Simulator};
+
+pub(super) fn handle(simulator: &mut Simulator, request: &Request) -> Reply {
+    use OsManagementCommand::*;
+
+    if request.is(OpCode::WriteRequest, Echo) {
+        let echo: EchoRequest = request.payload()?;
+        Ok(request.respond(EchoResponse { r: echo.d }))
+    } else if request.is(OpCode::WriteRequest, Reset) {
+        simulator.reset = true;
+        Ok(request.respond(ResetResponse {}))
+    } else if request.is(OpCode::ReadRequest, McumgrParameters) {
+        Ok(request.respond(McumgrParamsResponse {
+            buf_size: simulator.options.buf_size,
+            buf_count: simulator.options.buf_count,
+        }))
+    } else if request.is(OpCode::ReadRequest, DateTime) {
+        let now = now_ms() + simulator.clock_offset_ms;
+        Ok(request.respond(DateTimeResponse {
+            datetime: format_datetime(now),
+        }))
+    } else if request.is(OpCode::WriteRequest, DateTime) {
+        let set: SetDateTimeRequest = request.payload()?;
+        let time = parse_datetime(&set.datetime).ok_or(Failure::group(
+            OsMgmtError::InvalidFormat,
+            ReturnCode::InvalidValue,
+        ))?;
+        simulator.clock_offset_ms = time - now_ms();
+        Ok(request.respond(SetDateTimeResponse {}))
+    } else if request.is(OpCode::ReadRequest, TaskStatistics) {
+        Ok(request.respond(tasks(simulator)))
+    } else if request.is(OpCode::ReadRequest, MemoryPoolStatistics) {
+        let pool = MemPoolStat {
+            blksiz: simulator.options.buf_size,
+            nblks: simulator.options.buf_count,
+            nfree: simulator.options.buf_count - 1,
+            min: Some(simulator.options.buf_count - 1),
+        };
+        Ok(request.respond(MemPoolStatResponse {
+            mpools: BTreeMap::from([("smp_buf".to_string(), pool)]),
+        }))
+    } else if request.is(OpCode::ReadRequest, Info) {
+        let info: GetInfoRequest = request.payload()?;
+        let output = info_output(simulator, info.format.as_deref().unwrap_or("s"))?;
+        Ok(request.respond(GetInfoResponse { output }))
+    } else if request.is(OpCode::ReadRequest, BootloaderInfo) {
+        let info: BootloaderInfoRequest = request.payload()?;
+        let response = match info.query.as_deref() {
+            None => BootloaderInfoResponse {
+                bootloader: Some("MCUboot".to_string()),
+                mode: None,
+                no_downgrade: None,
+            },
+            // swap using scratch
+            Some("mode") => BootloaderInfoResponse {
+                bootloader: None,
+                mode: Some(1),
+                no_downgrade: Some(false),
+            },
+            Some(_) => {
+                return Err(Failure::group(
+                    OsMgmtError::QueryYieldsNoAnswer,
+                    ReturnCode::InvalidValue,
+                ))
+            }
+        };
+        Ok(request.respond(response))
+    } else {
+        Err(ReturnCode::NotSupported.into())
+    }
+}
+
+fn tasks(simulator: &Simulator) -> TaskStatResponse {
+    let uptime = simulator.booted.elapsed().as_millis() as u64;
+    let task = |prio, tid, stkuse| TaskStat {
+        prio,
+        tid,
+        state: 1,
+        stkuse: Some(stkuse),
+        stksiz: Some(512),
+        cswcnt: Some((uptime / 10) as u32),
+        runtime: Some(uptime),
+    };
+    TaskStatResponse {
+        tasks: BTreeMap::from([
+            ("idle".to_string(), task(15, 0, 64)),
+            ("main".to_string(), task(0, 1, 220)),
+            ("smp_work".to_string(), task(-1, 2, 310)),
+        ]),
+    }
+}
+
+/// Output of the info command like `uname` prints it, in the order of the fields
+fn info_output(simulator: &Simulator, format: &str) -> Result<String, Failure> {
+    let mut fields = Vec::new();
+    for c in format.chars() {
+        match OsInfoField::from_format_char(c) {
+            Some(field) => fields.push(field),
+            None if c == 'a' => fields.extend(OsInfoField::ALL),
+            None => {
+                return Err(Failure::group(
+                    OsMgmtError::InvalidFormat,
+                    ReturnCode::InvalidValue,
+                ))
+            }
+        }
+    }
+    fields.sort();
+    fields.dedup();
+
+    let (_, version) = simulator.images.running();
+    let output: Vec<String> = fields
+        .into_iter()
+        .map(|field| match field {
+            OsInfoField::KernelName => "Zephyr".to_string(),
+            OsInfoField::NodeName => simulator.options.name.clone(),
+            OsInfoField::KernelRelease => version.clone(),
+            OsInfoField::KernelVersion => format!("v{}", version),
+            OsInfoField::BuildDateTime => "Thu Jan 1 00:00:00 1970".to_string(),
+            OsInfoField::Machine => "arm".to_string(),
+            OsInfoField::Processor => "cortex-m33".to_string(),
+            OsInfoField::HardwarePlatform => "smp_simulator".to_string(),
+            OsInfoField::OperatingSystem => "Zephyr".to_string(),
+        })
+        .collect();
+    Ok(output.join(" "))
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+const MS_PER_DAY: i64 = 86_400_000;
+
+/// Milliseconds since the epoch as `yyyy-MM-ddTHH:mm:ss.SSS`
+fn format_datetime(ms: i64) -> String {
+    let (year, month, day) = civil_from_days(ms.div_euclid(MS_PER_DAY));
+    let ms = ms.rem_euclid(MS_PER_DAY);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Parse `yyyy-MM-ddTHH:mm:ss` with optional fraction and UTC offset, like Zephyr's RTC API
+fn parse_datetime(datetime: &str) -> Option<i64> {
+    let (date, time) = datetime.split_once('T')?;
+
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // split off the time zone
+    let (time, offset_ms) = if let Some(time) = time.strip_suffix('Z') {
+        (time, 0)
+    } else if let Some(at) = time.rfind(['+', '-']) {
+        let (time, zone) = time.split_at(at);
+        let sign = if zone.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = zone[1..].split_once(':')?;
+        let minutes = hours.parse::<i64>().ok()? * 60 + minutes.parse::<i64>().ok()?;
+        (time, sign * minutes * 60_000)
+    } else {
+        (time, 0)
+    };
+
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let millis = match fraction {
+        "" => 0,
+        fraction if fraction.bytes().all(|b| b.is_ascii_digit()) => {
+            format!("{:0<3}", &fraction[..fraction.len().min(3)])
+                .parse()
+                .ok()?
+        }
+        _ => return None,
+    };
+
+    let days = days_from_civil(year, month, day);
+    Some(
+        days * MS_PER_DAY + hour * 3_600_000 + minute * 60_000 + second * 1000 + millis - offset_ms,
+    )
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,68 @@
+// smp-tool/src/simulator/settings.rs
+
+//! Settings group, values are kept over a reset only after a save
+
+use std::collections::BTreeMap;
+
+use mcumgr_smp::device_error::SettingsMgmtError;
+use mcumgr_smp::settings_management::{
+    DeleteSettingRequest, ReadSettingPayload, ReadSettingRequest, SettingsManagementCommand,
+    SettingsPayload, WriteSettingRequest,
+};
+use mcumgr_smp::{OpCode, ReturnCode};
+use serde_bytes::ByteBuf;
+
+use super::{Failure, Reply, Request, Simulator};
+
+#[derive(Debug, Default)]
+pub(super) struct Settings {
+    values: BTreeMap<String, Vec<u8>>,
+    /// the values in persistent storage
+    saved: BTreeMap<String, Vec<u8>>,
+}
+
+impl Settings {
+    /// Load the saved values, like on boot
+    pub(super) fn load(&mut self) {
+        self.values = self.saved.clone();
+    }
+}
+
+fn not_found() -> Failure {
+    Failure::group(SettingsMgmtError::KeyNotFound, ReturnCode::NoEntry)
+}
+
+pub(super) fn handle(simulator: &mut Simulator, request: &Request) -> Reply {
+    use SettingsManagementCommand::*;
+    let settings = &mut simulator.settings;
+
+    if request.is(OpCode::ReadRequest, ReadWrite) {
+        let read: ReadSettingRequest = request.payload()?;
+        let value = settings.values.get(&read.name).ok_or_else(not_found)?;
+        let max_size = read.max_size.map(|max| max as usize).unwrap_or(usize::MAX);
+        let truncated = value.len() > max_size;
+        Ok(request.respond(ReadSettingPayload {
+            val: ByteBuf::from(&value[..value.len().min(max_size)]),
+            max_size: truncated.then_some(max_size as u32),
+        }))
+    } else if request.is(OpCode::WriteRequest, ReadWrite) {
+        let write: WriteSettingRequest = request.payload()?;
+        settings.values.insert(write.name, write.val.into_vec());
+        Ok(request.respond(SettingsPayload {}))
+    } else if request.is(OpCode::WriteRequest, Delete) {
+        let delete: DeleteSettingRequest = request.payload()?;
+        settings.values.remove(&delete.name).ok_or_else(not_found)?;
+        settings.saved.remove(&delete.name);
+        Ok(request.respond(SettingsPayload {}))
+    } else if request.is(OpCode::WriteRequest, Commit) {
+        Ok(request.respond(SettingsPayload {}))
+    } else if request.is(OpCode::ReadRequest, LoadSave) {
+        settings.load();
+        Ok(request.respond(SettingsPayload {}))
+    } else if request.is(OpCode::WriteRequest, LoadSave) {
+        settings.saved = settings.values.clone();
+        Ok(request.respond(SettingsPayload {}))
+    } else {
+        Err(ReturnCode::NotSupported.into())
+    }
+}
@@ -0,0 +1,54 @@
+// smp-tool/src/simulator/shell.rs
+
+//! Shell group with pluggable commands
+
+use mcumgr_smp::device_error::ShellMgmtError;
+use mcumgr_smp::shell_management::{ShellCommand, ShellResponse};
+use mcumgr_smp::{OpCode, ReturnCode};
+
+use super::{Failure, Reply, Request, Simulator};
+
+/// Runs the shell commands sent to the simulator
+pub trait Shell: Send {
+    /// Run a command, returns the exit code and the output
+    fn exec(&mut self, argv: &[String]) -> (i32, String);
+}
+
+impl<F> Shell for F
+where
+    F: FnMut(&[String]) -> (i32, String) + Send,
+{
+    fn exec(&mut self, argv: &[String]) -> (i32, String) {
+        self(argv)
+    }
+}
+
+/// Shell with the `echo` and `help` commands
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BasicShell;
+
+impl Shell for BasicShell {
+    fn exec(&mut self, argv: &[String]) -> (i32, String) {
+        match argv[0].as_str() {
+            "echo" => (0, argv[1..].join(" ")),
+            "help" => (0, "Available commands:\n  echo\n  help".to_string()),
+            command => (-8, format!("{}: command not found", command)),
+        }
+    }
+}
+
+pub(super) fn handle(simulator: &mut Simulator, request: &Request) -> Reply {
+    if !request.is(OpCode::WriteRequest, 0) {
+        return Err(ReturnCode::NotSupported.into());
+    }
+
+    let command: ShellCommand = request.payload()?;
+    if command.argv.first().is_none_or(String::is_empty) {
+        return Err(Failure::group(
+            ShellMgmtError::EmptyCommand,
+            ReturnCode::InvalidValue,
+        ));
+    }
+    let (ret, o) = simulator.shell.exec(&command.argv);
+    Ok(request.respond(ShellResponse { o, ret }))
+}
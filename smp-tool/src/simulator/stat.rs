@@ -0,0 +1,60 @@
+// smp-tool/src/simulator/stat.rs
+
+//! Statistics group with the counters of the SMP server
+
+use std::collections::BTreeMap;
+
+use mcumgr_smp::device_error::StatMgmtError;
+use mcumgr_smp::stat_management::{
+    StatGroupPayload, StatGroupRequest, StatListPayload, StatManagementCommand,
+};
+use mcumgr_smp::{OpCode, ReturnCode};
+
+use super::{Failure, Reply, Request, Simulator};
+
+const GROUP: &str = "smp";
+
+/// Counters since the last reset
+#[derive(Debug, Default)]
+pub(super) struct Stats {
+    requests: u64,
+    errors: u64,
+}
+
+impl Stats {
+    pub(super) fn count(&mut self, ok: bool) {
+        self.requests += 1;
+        if !ok {
+            self.errors += 1;
+        }
+    }
+}
+
+pub(super) fn handle(simulator: &mut Simulator, request: &Request) -> Reply {
+    let stats = &simulator.stats;
+
+    if request.is(OpCode::ReadRequest, StatManagementCommand::ListGroups) {
+        Ok(request.respond(StatListPayload {
+            stat_list: vec![GROUP.to_string()],
+        }))
+    } else if request.is(OpCode::ReadRequest, StatManagementCommand::GroupData) {
+        let read: StatGroupRequest = request.payload()?;
+        if read.name != GROUP {
+            return Err(Failure::group(
+                StatMgmtError::InvalidGroup,
+                ReturnCode::NoEntry,
+            ));
+        }
+        let fields = BTreeMap::from([
+            // this request is counted after it was answered
+            ("requests".to_string(), stats.requests + 1),
+            ("errors".to_string(), stats.errors),
+        ]);
+        Ok(request.respond(StatGroupPayload {
+            name: read.name,
+            fields,
+        }))
+    } else {
+        Err(ReturnCode::NotSupported.into())
+    }
+}
@@ -1,6 +1,7 @@
 use core::time;
 use mcumgr_smp::application_management::{self, GetImageStateResult};
 use mcumgr_smp::smp::SmpFrame;
+use mcumgr_smp::transport::retry::RetryPolicy;
 use smp_tool::client::Client;
 use std::net::SocketAddr;
 
@@ -9,24 +10,36 @@ use std::time::{Duration, Instant};
 
 pub async fn wait_until_online(host: SocketAddr) -> Result<()> {
     println!("Trying to connect...");
-    let deadline = Instant::now() + Duration::from_secs(20);
 
-    let mut client = Client::new(host, Some(time::Duration::from_millis(1000))).await?;
+    // the policy resends lost frames, while the device reboots the port can also be unreachable
+    let deadline = Instant::now() + Duration::from_secs(20);
+    let retry = RetryPolicy::default()
+        .with_attempts(5)
+        .with_timeout(Some(time::Duration::from_millis(1000)))
+        .with_backoff(Duration::ZERO, Duration::ZERO);
 
     loop {
-        if Instant::now() >= deadline {
-            return Err(anyhow!("target is not available!"));
-        }
-
-        let res: std::result::Result<SmpFrame<GetImageStateResult>, _> = client
-            .transceive_cbor(&application_management::get_state(42))
-            .await;
-
-        if res.is_ok() {
-            println!("Connected!");
-            return Ok(());
+        let result = match Client::new(host, None).await {
+            Ok(client) => client
+                .with_retry_policy(retry.clone())
+                .get_img_state()
+                .await
+                .map(|_| ()),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(()) => break,
+            Err(err) if Instant::now() >= deadline => {
+                return Err(anyhow!("target is not available! {err}"))
+            }
+            Err(err) => {
+                println!("not yet: {err}");
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
         }
     }
+    println!("Connected!");
+    Ok(())
 }
 
 pub async fn get_hash(addr: SocketAddr, slot: i32) -> anyhow::Result<String> {
@@ -38,31 +51,24 @@ pub async fn get_hash(addr: SocketAddr, slot: i32) -> anyhow::Result<String> {
         .transceive_cbor(&application_management::get_state(42))
         .await?;
 
-    let hash = match frame.data {
-        GetImageStateResult::Ok(payload) => {
-            let mut slot_hash: Option<String> = None;
+    let payload = frame
+        .into_result()
+        .map_err(|err| anyhow!("GetImageStateResult error: {err}"))?;
+
+    let mut slot_hash: Option<String> = None;
 
-            for img in payload.images {
-                if img.slot == slot {
-                    if let Some(h) = img.hash {
-                        if h.len() == 32 {
-                            let s: String = h.iter().map(|b| format!("{:02x}", b)).collect();
-                            slot_hash = Some(s);
-                            break;
-                        }
-                    }
+    for img in payload.images {
+        if img.slot == slot {
+            if let Some(h) = img.hash {
+                if h.len() == 32 {
+                    let s: String = h.iter().map(|b| format!("{:02x}", b)).collect();
+                    slot_hash = Some(s);
+                    break;
                 }
             }
-
-            slot_hash.ok_or_else(|| anyhow!("slot {slot} hash not found"))?
         }
-        GetImageStateResult::Err(err) => {
-            return Err(anyhow!(
-                "GetImageStateResult error rc={}, rsn={:?}",
-                err.rc,
-                err.rsn
-            ));
-        }
-    };
+    }
+
+    let hash = slot_hash.ok_or_else(|| anyhow!("slot {slot} hash not found"))?;
     Ok(hash)
 }
@@ -1,14 +1,7 @@
 use core::time;
 use serde::Deserialize;
-use smp_tool::client::Client;
-use std::{
-    fs,
-    net::SocketAddr,
-    path::PathBuf,
-    str::FromStr,
-    thread,
-    time::{Duration, Instant},
-};
+use smp_tool::client::{Client, FlashOptions};
+use std::{fs, net::SocketAddr, path::PathBuf, str::FromStr, thread, time::Duration};
 
 mod common;
 
@@ -51,35 +44,26 @@ async fn deploy(addr: SocketAddr) -> anyhow::Result<()> {
 
     println!("Uploading the image into slot1");
 
-    let deadline = Instant::now() + Duration::from_secs(20);
     let mut client = Client::new(addr, Some(time::Duration::from_millis(5000))).await?;
-    // Upload with retry mechanism
-    loop {
-        let res: std::result::Result<(), String> = client
-            .flash(None, &bin_path, 256, false, fw_hash_hex)
-            .await
-            .map_err(|e| format!("flash error: {e}"));
-
-        match res {
-            Ok(()) => {
-                println!("Uploading done!");
-                break;
-            }
-            Err(e) => println!("{e}"),
-        }
-
-        if Instant::now() >= deadline {
-            panic!("Upload failed");
-            // or: return Err(anyhow::anyhow!("Upload failed"));
-        }
-    }
+    // lost chunks are resent by flash itself
+    client
+        .flash(
+            &bin_path,
+            &FlashOptions {
+                hash: Some(fw_hash_hex.to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| anyhow::anyhow!("flash error: {e}"))?;
+    println!("Uploading done!");
 
     thread::sleep(Duration::from_secs(1)); // wait after image upload
     println!("Labeling for testing..");
 
     // label for test + reset via ops
     let res: Result<(), String> = client
-        .test_next_boot(&fw_hash_hex)
+        .test_next_boot(fw_hash_hex)
         .await
         .map_err(|e| format!("test_next_boot error: {e}"));
     println!("Rebooting");
@@ -88,7 +72,7 @@ async fn deploy(addr: SocketAddr) -> anyhow::Result<()> {
         panic!("image test next boot step failed: {e}");
     }
     let res: Result<(), String> = client
-        .reset()
+        .reset(false, None)
         .await
         .map_err(|e| format!("reset error: {e}"));
 
@@ -104,7 +88,7 @@ async fn deploy(addr: SocketAddr) -> anyhow::Result<()> {
     println!("Confirming...");
 
     let res: Result<(), String> = client
-        .confirm(&fw_hash_hex)
+        .confirm(fw_hash_hex)
         .await
         .map_err(|e| format!("confirm error: {e}"));
     if let Err(e) = res {
@@ -0,0 +1,152 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mcumgr_smp::mcuboot_image::{ImageVersion, McubootImage};
+use smp_tool::client::Client;
+use smp_tool::dfu::{Dfu, DfuOptions, DfuOutcome, DfuStep};
+use smp_tool::simulator::{build_image, Faults, Simulator, SimulatorOptions, SimulatorTransport};
+
+const REBOOT_TIME: Duration = Duration::from_millis(20);
+
+fn simulator(faults: Faults) -> Arc<Mutex<Simulator>> {
+    let options = SimulatorOptions {
+        reboot_time: REBOOT_TIME,
+        ..Default::default()
+    };
+    Arc::new(Mutex::new(Simulator::new(options).with_faults(faults)))
+}
+
+fn client(simulator: &Arc<Mutex<Simulator>>) -> Client {
+    Client::from_transport(SimulatorTransport::new(simulator.clone()))
+}
+
+/// Write a 1.1.0 image, returns its path and hash
+fn firmware(name: &str) -> (PathBuf, Vec<u8>) {
+    let version = ImageVersion {
+        major: 1,
+        minor: 1,
+        ..Default::default()
+    };
+    let image = build_image(version, &[0x5a; 3000]);
+    let path = std::env::temp_dir().join(format!("smp-tool-dfu-{}.bin", name));
+    std::fs::write(&path, &image).unwrap();
+    let hash = McubootImage::parse(&image)
+        .unwrap()
+        .hash()
+        .unwrap()
+        .to_vec();
+    (path, hash)
+}
+
+fn options() -> DfuOptions {
+    DfuOptions {
+        online_timeout: Duration::from_secs(5),
+        reboot_delay: Duration::ZERO,
+        confirm: true,
+        ..Default::default()
+    }
+}
+
+fn steps(report: &smp_tool::dfu::DfuReport) -> Vec<(DfuStep, bool)> {
+    report
+        .steps
+        .iter()
+        .map(|step| (step.step, step.result.is_ok()))
+        .collect()
+}
+
+#[tokio::test]
+async fn update_confirms_new_image() {
+    let simulator = simulator(Faults::default());
+    let mut client = client(&simulator);
+    let (file, hash) = firmware("update");
+
+    let report = Dfu::new(&mut client, file, options()).run().await;
+
+    assert_eq!(report.outcome, Some(DfuOutcome::Updated));
+    assert_eq!(
+        steps(&report),
+        [
+            (DfuStep::WaitOnline, true),
+            (DfuStep::CheckRunning, true),
+            (DfuStep::Upload, true),
+            (DfuStep::MarkTest, true),
+            (DfuStep::Reset, true),
+            (DfuStep::WaitReboot, true),
+            (DfuStep::Verify, true),
+            (DfuStep::Confirm, true),
+        ]
+    );
+    assert_eq!(simulator.lock().unwrap().running_image().0, hash);
+    let state = client.get_img_state().await.unwrap();
+    assert!(state.images[0].confirmed);
+}
+
+#[tokio::test]
+async fn running_image_is_not_uploaded() {
+    let (file, _) = firmware("running");
+    let device = SimulatorOptions {
+        image: Some(std::fs::read(&file).unwrap()),
+        ..Default::default()
+    };
+    let simulator = Arc::new(Mutex::new(Simulator::new(device)));
+    let mut client = client(&simulator);
+
+    let report = Dfu::new(&mut client, file, options()).run().await;
+
+    assert_eq!(report.outcome, Some(DfuOutcome::AlreadyRunning));
+    assert_eq!(report.steps.len(), 2);
+    // nothing was written to the secondary slot
+    let state = client.get_img_state().await.unwrap();
+    assert_eq!(state.images.len(), 1);
+}
+
+#[tokio::test]
+async fn image_that_does_not_boot_is_reported() {
+    let simulator = simulator(Faults {
+        boot_failure: true,
+        ..Default::default()
+    });
+    let mut client = client(&simulator);
+    let (old, _) = simulator.lock().unwrap().running_image();
+    let (file, _) = firmware("no-boot");
+
+    let report = Dfu::new(&mut client, file, options()).run().await;
+
+    assert_eq!(report.outcome, Some(DfuOutcome::RolledBack));
+    assert_eq!(
+        report.steps.last().map(|step| step.step),
+        Some(DfuStep::Verify)
+    );
+    assert!(report.steps.last().unwrap().result.is_err());
+    assert_eq!(simulator.lock().unwrap().running_image().0, old);
+}
+
+#[tokio::test]
+async fn unconfirmed_image_reverts_on_reset() {
+    let simulator = simulator(Faults::default());
+    let mut client = client(&simulator);
+    let (old, _) = simulator.lock().unwrap().running_image();
+    let (file, hash) = firmware("no-confirm");
+    let options = DfuOptions {
+        confirm: false,
+        ..options()
+    };
+
+    let report = Dfu::new(&mut client, file, options).run().await;
+
+    assert_eq!(report.outcome, Some(DfuOutcome::Testing));
+    let state = client.get_img_state().await.unwrap();
+    assert!(!state.images[0].confirmed);
+
+    // without the confirm the next reset brings back the previous image
+    client.reset(false, None).await.unwrap();
+    tokio::time::sleep(REBOOT_TIME * 2).await;
+    assert_eq!(simulator.lock().unwrap().running_image().0, old);
+    let state = client.get_img_state().await.unwrap();
+    assert_eq!(
+        state.images[1].hash.as_ref().map(|hash| hash.to_vec()),
+        Some(hash)
+    );
+}
@@ -0,0 +1,180 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mcumgr_smp::mcuboot_image::{ImageVersion, McubootImage};
+use smp_tool::client::Client;
+use smp_tool::dfu::{DfuOptions, DfuOutcome, DfuStep};
+use smp_tool::error::Error;
+use smp_tool::fleet::{self, DeviceStatus, FleetDevice, FleetOptions, FleetReport};
+use smp_tool::simulator::{build_image, Faults, Simulator, SimulatorOptions, SimulatorTransport};
+
+/// Inventory and devices, the `broken` ones do not boot the new image
+fn fleet(count: usize, broken: &[usize]) -> (Vec<FleetDevice>, Vec<Arc<Mutex<Simulator>>>) {
+    let inventory = (0..count)
+        .map(|i| FleetDevice {
+            socket_addr: format!("[2001:db8::{}]:1337", i + 1),
+            name: None,
+        })
+        .collect();
+    let devices = (0..count)
+        .map(|i| {
+            let options = SimulatorOptions {
+                reboot_time: Duration::from_millis(20),
+                ..Default::default()
+            };
+            let faults = Faults {
+                boot_failure: broken.contains(&i),
+                ..Default::default()
+            };
+            Arc::new(Mutex::new(Simulator::new(options).with_faults(faults)))
+        })
+        .collect();
+    (inventory, devices)
+}
+
+fn options(concurrency: usize) -> FleetOptions {
+    FleetOptions {
+        dfu: DfuOptions {
+            online_timeout: Duration::from_secs(5),
+            reboot_delay: Duration::ZERO,
+            confirm: true,
+            ..Default::default()
+        },
+        concurrency,
+        ..Default::default()
+    }
+}
+
+fn client(simulator: &Arc<Mutex<Simulator>>) -> Client {
+    Client::from_transport(SimulatorTransport::new(simulator.clone()))
+}
+
+/// Write the 1.1.0 image of the test `name`, returns its path and hash
+fn firmware(name: &str) -> (PathBuf, Vec<u8>) {
+    let version = ImageVersion {
+        major: 1,
+        minor: 1,
+        ..Default::default()
+    };
+    let image = build_image(version, &[0x5a; 3000]);
+    let path = std::env::temp_dir().join(format!("smp-tool-fleet-{}.bin", name));
+    std::fs::write(&path, &image).unwrap();
+    let hash = McubootImage::parse(&image)
+        .unwrap()
+        .hash()
+        .unwrap()
+        .to_vec();
+    (path, hash)
+}
+
+async fn update(
+    image: &Path,
+    inventory: &[FleetDevice],
+    devices: &[Arc<Mutex<Simulator>>],
+    options: &FleetOptions,
+) -> FleetReport {
+    fleet::update(inventory, image, options, |device| {
+        let index = inventory
+            .iter()
+            .position(|d| d.socket_addr == device.socket_addr);
+        let device = index.map(|i| devices[i].clone());
+        async move {
+            device
+                .map(|device| client(&device))
+                .ok_or_else(|| Error::Fleet("unknown device".to_string()))
+        }
+    })
+    .await
+}
+
+fn statuses(report: &FleetReport) -> Vec<DeviceStatus> {
+    report.devices.iter().map(|d| d.status).collect()
+}
+
+#[tokio::test]
+async fn all_devices_are_updated() {
+    let (inventory, devices) = fleet(5, &[]);
+    let (image, hash) = firmware("all");
+
+    let report = update(&image, &inventory, &devices, &options(2)).await;
+
+    assert!(report.is_success());
+    assert_eq!(report.succeeded, 5);
+    assert!(report
+        .devices
+        .iter()
+        .all(|d| d.outcome == Some(DfuOutcome::Updated)));
+    for device in devices {
+        assert_eq!(device.lock().unwrap().running_image().0, hash);
+    }
+}
+
+#[tokio::test]
+async fn failed_canary_stops_rollout() {
+    let (inventory, devices) = fleet(4, &[0]);
+    let (image, _) = firmware("canary");
+    let options = FleetOptions {
+        canary: 1,
+        ..options(4)
+    };
+
+    let report = update(&image, &inventory, &devices, &options).await;
+
+    assert!(!report.is_success());
+    assert!(report
+        .stopped
+        .as_ref()
+        .unwrap()
+        .starts_with("canary stage failed"));
+    assert_eq!(
+        statuses(&report)[..],
+        [
+            DeviceStatus::Failed,
+            DeviceStatus::Skipped,
+            DeviceStatus::Skipped,
+            DeviceStatus::Skipped,
+        ]
+    );
+    let canary = &report.devices[0];
+    assert_eq!(canary.outcome, Some(DfuOutcome::RolledBack));
+    assert_eq!(canary.failed_step, Some(DfuStep::Verify));
+    for device in &devices[1..] {
+        // nothing was uploaded to the secondary slot
+        let state = client(device).get_img_state().await.unwrap();
+        assert_eq!(state.images.len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn failure_rate_stops_rollout() {
+    let (inventory, devices) = fleet(6, &[1, 2]);
+    let (image, _) = firmware("failure-rate");
+    let options = FleetOptions {
+        max_failure_rate: 0.5,
+        ..options(1)
+    };
+
+    let report = update(&image, &inventory, &devices, &options).await;
+
+    // one failed device of two is still within the limit, two of three are not
+    assert_eq!(report.stopped.as_deref(), Some("2 of 3 devices failed"));
+    assert_eq!(
+        statuses(&report)[..],
+        [
+            DeviceStatus::Succeeded,
+            DeviceStatus::Failed,
+            DeviceStatus::Failed,
+            DeviceStatus::Skipped,
+            DeviceStatus::Skipped,
+            DeviceStatus::Skipped,
+        ]
+    );
+
+    let summary = serde_json::to_value(&report).unwrap();
+    assert_eq!(summary["failed"], 2);
+    assert_eq!(summary["devices"][1]["socket_addr"], "[2001:db8::2]:1337");
+    assert_eq!(summary["devices"][1]["outcome"], "rolled_back");
+    assert_eq!(summary["devices"][1]["failed_step"], "verify");
+    assert_eq!(summary["devices"][3]["status"], "skipped");
+}
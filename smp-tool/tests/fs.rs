@@ -0,0 +1,62 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use smp_tool::client::Client;
+use smp_tool::error::Error;
+use smp_tool::simulator::{Faults, Simulator, SimulatorOptions, SimulatorTransport};
+
+fn simulator(faults: Faults) -> Arc<Mutex<Simulator>> {
+    let simulator = Simulator::new(SimulatorOptions::default()).with_faults(faults);
+    Arc::new(Mutex::new(simulator))
+}
+
+fn client(simulator: &Arc<Mutex<Simulator>>) -> Client {
+    Client::from_transport(SimulatorTransport::new(simulator.clone()))
+}
+
+fn local(name: &str, content: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("smp-tool-fs-{}", name));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn put_fails_when_the_device_stops_writing() {
+    let simulator = simulator(Faults {
+        fs_stalled: true,
+        ..Default::default()
+    });
+    let file = local("full.bin", &[0x55; 1000]);
+
+    let err = client(&simulator)
+        .fs_put(&file, "/lfs/full.bin", None)
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, Error::Fs(_)), "{}", err);
+    // the file is closed after a failed upload as well
+    assert_eq!(simulator.lock().unwrap().open_file(), None);
+}
+
+#[tokio::test]
+async fn put_and_get_close_the_file() {
+    let simulator = simulator(Faults::default());
+    let mut client = client(&simulator);
+    let content: Vec<u8> = (0..1000).map(|i| (i * 3) as u8).collect();
+    let file = local("put.bin", &content);
+    let downloaded = std::env::temp_dir().join("smp-tool-fs-get.bin");
+
+    client.fs_put(&file, "/lfs/data.bin", None).await.unwrap();
+    assert_eq!(simulator.lock().unwrap().open_file(), None);
+    assert_eq!(
+        simulator.lock().unwrap().file("/lfs/data.bin"),
+        Some(&content[..])
+    );
+
+    client
+        .fs_get("/lfs/data.bin", &downloaded, false)
+        .await
+        .unwrap();
+    assert_eq!(simulator.lock().unwrap().open_file(), None);
+    assert_eq!(std::fs::read(&downloaded).unwrap(), content);
+}
@@ -0,0 +1,116 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mcumgr_smp::mcuboot_image::{ImageVersion, McubootImage};
+use mcumgr_smp::transport::faulty::{FaultStats, Faults, FaultyTransportAsync};
+use mcumgr_smp::transport::retry::RetryPolicy;
+use mcumgr_smp::Group;
+use smp_tool::client::{Client, FlashOptions};
+use smp_tool::simulator::{build_image, Simulator, SimulatorOptions, SimulatorTransport};
+
+const SEEDS: std::ops::Range<u64> = 0..16;
+
+/// Client on a link that loses, duplicates, delays and reorders frames.
+/// Truncated requests are dropped by the device like broken frames on a real link,
+/// bit flips are left out because only the transport checksums would catch them.
+fn lossy_client(simulator: &Arc<Mutex<Simulator>>, seed: u64) -> (Client, Arc<Mutex<FaultStats>>) {
+    let faults = Faults::default()
+        .with_drop(0.1)
+        .with_duplicate(0.1)
+        .with_delay(0.1, Duration::from_millis(2))
+        .with_reorder(0.1);
+    let transport = FaultyTransportAsync::new(SimulatorTransport::new(simulator.clone()), seed)
+        .with_send_faults(faults.clone().with_truncate(0.05))
+        .with_receive_faults(faults);
+    let stats = transport.stats_handle();
+
+    let policy = RetryPolicy::default()
+        .with_attempts(10)
+        .with_timeout(Some(Duration::from_millis(50)))
+        .with_backoff(Duration::ZERO, Duration::ZERO)
+        .with_idempotent_write(Group::ShellManagement, 0);
+    (
+        Client::from_transport(transport).with_retry_policy(policy),
+        stats,
+    )
+}
+
+fn simulator() -> Arc<Mutex<Simulator>> {
+    Arc::new(Mutex::new(Simulator::new(SimulatorOptions::default())))
+}
+
+/// Write a 6000 byte image, returns its path and hash
+fn firmware() -> (std::path::PathBuf, String) {
+    let version = ImageVersion {
+        major: 1,
+        minor: 1,
+        ..Default::default()
+    };
+    let payload: Vec<u8> = (0..5448).map(|i| i as u8).collect();
+    let image = build_image(version, &payload);
+    let path = std::env::temp_dir().join("smp-tool-lossy-link.bin");
+    std::fs::write(&path, &image).unwrap();
+    let hash = McubootImage::parse(&image)
+        .unwrap()
+        .hash()
+        .unwrap()
+        .to_vec();
+    (path, hex::encode(hash))
+}
+
+#[tokio::test]
+async fn flash_and_confirm_survive_lossy_link() {
+    let (image, hash) = firmware();
+    let options = FlashOptions {
+        chunk_size: Some(256),
+        ..Default::default()
+    };
+
+    for seed in SEEDS {
+        let simulator = simulator();
+        let (mut client, stats) = lossy_client(&simulator, seed);
+
+        client
+            .flash(&image, &options)
+            .await
+            .unwrap_or_else(|err| panic!("seed {}: flash failed: {}", seed, err));
+        client
+            .confirm(&hash)
+            .await
+            .unwrap_or_else(|err| panic!("seed {}: confirm failed: {}", seed, err));
+
+        let state = Client::from_transport(SimulatorTransport::new(simulator))
+            .get_img_state()
+            .await
+            .unwrap();
+        let secondary = &state.images[1];
+        assert_eq!(
+            secondary.hash.as_ref().map(hex::encode),
+            Some(hash.clone()),
+            "seed {}",
+            seed
+        );
+        // MCUboot only accepts the image if every byte arrived
+        assert!(secondary.bootable, "seed {}", seed);
+        assert!(secondary.pending && secondary.permanent, "seed {}", seed);
+
+        let stats = *stats.lock().unwrap();
+        assert!(stats.sent.faults() > 0, "seed {}: {:?}", seed, stats);
+        assert!(stats.received.faults() > 0, "seed {}: {:?}", seed, stats);
+    }
+}
+
+#[tokio::test]
+async fn shell_survives_lossy_link() {
+    for seed in SEEDS {
+        let (mut client, _) = lossy_client(&simulator(), seed);
+
+        for i in 0..20 {
+            let output = client
+                .transceive(vec!["echo".to_string(), format!("line {}", i)])
+                .await
+                .unwrap_or_else(|err| panic!("seed {}: shell failed: {}", seed, err));
+            assert_eq!(output, format!("line {}", i), "seed {}", seed);
+        }
+    }
+}
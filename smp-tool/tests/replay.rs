@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mcumgr_smp::mcuboot_image::{ImageVersion, McubootImage};
+use mcumgr_smp::transport::faulty::{Faults, FaultyTransportAsync};
+use mcumgr_smp::transport::record::{RecordingTransportAsync, ReplayTransport};
+use mcumgr_smp::transport::retry::RetryPolicy;
+use mcumgr_smp::Group;
+use smp_tool::client::{Client, FlashOptions};
+use smp_tool::simulator::{build_image, Simulator, SimulatorOptions, SimulatorTransport};
+
+fn path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("smp-tool-replay-{}", name))
+}
+
+/// Write an image of `len` bytes, returns its hash
+fn firmware(path: &Path, len: usize) -> String {
+    let version = ImageVersion {
+        minor: 1,
+        ..Default::default()
+    };
+    let firmware = build_image(version, &vec![0x42; len]);
+    std::fs::write(path, &firmware).unwrap();
+    hex::encode(McubootImage::parse(&firmware).unwrap().hash().unwrap())
+}
+
+/// Flash and mark an image for test, then run a shell command
+async fn session(client: &mut Client, image: &Path, hash: &str) -> String {
+    let options = FlashOptions {
+        chunk_size: Some(256),
+        ..Default::default()
+    };
+    client.flash(image, &options).await.unwrap();
+    client.test_next_boot(hash).await.unwrap();
+    client
+        .transceive(vec!["echo".to_string(), "done".to_string()])
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn replayed_session_gets_recorded_responses() {
+    let log = path("simulator.jsonl");
+    let image = path("simulator.bin");
+    let hash = firmware(&image, 3000);
+
+    let simulator = Arc::new(Mutex::new(Simulator::new(SimulatorOptions::default())));
+    let recording =
+        RecordingTransportAsync::create(SimulatorTransport::new(simulator), &log).unwrap();
+    let recorded = session(&mut Client::from_transport(recording), &image, &hash).await;
+
+    let replay = ReplayTransport::open(&log).unwrap();
+    let replayed = session(&mut Client::from_transport(replay), &image, &hash).await;
+
+    assert_eq!(recorded, "done");
+    assert_eq!(replayed, recorded);
+}
+
+#[tokio::test]
+async fn replay_reproduces_lossy_link() {
+    let log = path("lossy.jsonl");
+    let image = path("lossy.bin");
+    let hash = firmware(&image, 4000);
+    let policy = RetryPolicy::default()
+        .with_attempts(10)
+        .with_timeout(Some(Duration::from_millis(50)))
+        .with_backoff(Duration::ZERO, Duration::ZERO)
+        .with_idempotent_write(Group::ShellManagement, 0);
+
+    // the field: frames are lost and duplicated on the way
+    let simulator = Arc::new(Mutex::new(Simulator::new(SimulatorOptions::default())));
+    let faults = Faults::default().with_drop(0.2).with_duplicate(0.1);
+    let lossy =
+        FaultyTransportAsync::new(SimulatorTransport::new(simulator), 3).with_faults(faults);
+    let stats = lossy.stats_handle();
+    let recording = RecordingTransportAsync::create(lossy, &log).unwrap();
+    let mut client = Client::from_transport(recording).with_retry_policy(policy.clone());
+    session(&mut client, &image, &hash).await;
+
+    let stats = *stats.lock().unwrap();
+    assert!(
+        stats.sent.dropped + stats.received.dropped > 0,
+        "{:?}",
+        stats
+    );
+
+    // the test: the same requests, resends and stale responses, without the device
+    let replay = ReplayTransport::open(&log).unwrap();
+    let mut client = Client::from_transport(replay).with_retry_policy(policy);
+    assert_eq!(session(&mut client, &image, &hash).await, "done");
+}
+
+#[tokio::test]
+async fn diverging_client_fails() {
+    let log = path("diverging.jsonl");
+    let simulator = Arc::new(Mutex::new(Simulator::new(SimulatorOptions::default())));
+    let recording =
+        RecordingTransportAsync::create(SimulatorTransport::new(simulator), &log).unwrap();
+    Client::from_transport(recording)
+        .transceive(vec!["echo".to_string(), "a".to_string()])
+        .await
+        .unwrap();
+
+    let mut client = Client::from_transport(ReplayTransport::open(&log).unwrap());
+    let err = client
+        .transceive(vec!["echo".to_string(), "b".to_string()])
+        .await
+        .unwrap_err();
+
+    assert!(err.to_string().contains("diverged"), "{}", err);
+}
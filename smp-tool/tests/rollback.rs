@@ -49,7 +49,7 @@ async fn rollback(addr: SocketAddr) -> anyhow::Result<()> {
 
         println!("Rebooting");
         client
-            .reset()
+            .reset(false, None)
             .await
             .map_err(|e| format!("reset error: {e}"))?;
 
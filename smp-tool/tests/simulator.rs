@@ -0,0 +1,334 @@
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mcumgr_smp::fs_management::{FileHashRequest, HashOutput};
+use mcumgr_smp::mcuboot_image::{ImageVersion, McubootImage};
+use mcumgr_smp::os_management::{
+    EchoRequest, GetDateTimeRequest, McumgrParamsRequest, SetDateTimeRequest,
+};
+use mcumgr_smp::transport::record::ReplayTransport;
+use smp_tool::client::{Client, FlashOptions};
+use smp_tool::dfu::{Dfu, DfuOptions, DfuOutcome};
+use smp_tool::error::Error;
+use smp_tool::simulator::{build_image, Simulator, SimulatorOptions, SimulatorTransport};
+
+const REBOOT_TIME: Duration = Duration::from_millis(50);
+
+fn simulator() -> Arc<Mutex<Simulator>> {
+    let options = SimulatorOptions {
+        reboot_time: REBOOT_TIME,
+        ..Default::default()
+    };
+    Arc::new(Mutex::new(Simulator::new(options)))
+}
+
+fn client(simulator: &Arc<Mutex<Simulator>>) -> Client {
+    Client::from_transport(SimulatorTransport::new(simulator.clone()))
+}
+
+fn version(minor: u8) -> ImageVersion {
+    ImageVersion {
+        major: 1,
+        minor,
+        ..Default::default()
+    }
+}
+
+/// Write an image file, returns its path and hash
+fn firmware(name: &str, image: &[u8]) -> (PathBuf, Vec<u8>) {
+    let path = std::env::temp_dir().join(format!("smp-tool-simulator-{}.bin", name));
+    std::fs::write(&path, image).unwrap();
+    let hash = McubootImage::parse(image).unwrap().hash().unwrap().to_vec();
+    (path, hash)
+}
+
+fn dfu_options(confirm: bool) -> DfuOptions {
+    DfuOptions {
+        online_timeout: Duration::from_secs(5),
+        reboot_delay: Duration::ZERO,
+        confirm,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn update_swaps_and_confirms() {
+    let simulator = simulator();
+    let mut client = client(&simulator);
+    let (file, hash) = firmware("update", &build_image(version(1), &[0x5a; 5000]));
+
+    let report = Dfu::new(&mut client, file, dfu_options(true)).run().await;
+
+    assert_eq!(report.outcome, Some(DfuOutcome::Updated));
+    assert_eq!(
+        simulator.lock().unwrap().running_image(),
+        (hash, "1.1.0".to_string())
+    );
+    let state = client.get_img_state().await.unwrap();
+    assert!(state.images[0].confirmed);
+}
+
+#[tokio::test]
+async fn unconfirmed_image_reverts_on_reset() {
+    let simulator = simulator();
+    let mut client = client(&simulator);
+    let (old, _) = simulator.lock().unwrap().running_image();
+    let (file, hash) = firmware("revert", &build_image(version(2), &[0x3c; 3000]));
+
+    let report = Dfu::new(&mut client, file, dfu_options(false)).run().await;
+    assert_eq!(report.outcome, Some(DfuOutcome::Testing));
+    assert_eq!(simulator.lock().unwrap().running_image().0, hash);
+
+    client.reset(false, None).await.unwrap();
+    tokio::time::sleep(REBOOT_TIME * 2).await;
+
+    assert_eq!(simulator.lock().unwrap().running_image().0, old);
+    let state = client.get_img_state().await.unwrap();
+    assert!(state.images[0].confirmed);
+    assert_eq!(
+        state.images[1].hash.as_ref().map(|h| h.to_vec()),
+        Some(hash)
+    );
+}
+
+#[tokio::test]
+async fn corrupted_image_does_not_boot() {
+    let simulator = simulator();
+    let mut client = client(&simulator);
+    let (old, _) = simulator.lock().unwrap().running_image();
+    let mut image = build_image(version(3), &[0x77; 2000]);
+    image[0x300] ^= 0xff;
+    let (file, _) = firmware("corrupted", &image);
+
+    let report = Dfu::new(&mut client, file, dfu_options(true)).run().await;
+
+    assert_eq!(report.outcome, Some(DfuOutcome::RolledBack));
+    assert_eq!(simulator.lock().unwrap().running_image().0, old);
+    // MCUboot erases an image that fails validation
+    let state = client.get_img_state().await.unwrap();
+    assert_eq!(state.images.len(), 1);
+}
+
+#[tokio::test]
+async fn upgrade_rejects_older_image() {
+    let simulator = simulator();
+    let mut client = client(&simulator);
+    let (file, _) = firmware(
+        "downgrade",
+        &build_image(ImageVersion::default(), &[1; 100]),
+    );
+    let options = FlashOptions {
+        upgrade: true,
+        ..Default::default()
+    };
+
+    let err = client.flash(&file, &options).await.unwrap_err();
+
+    assert!(matches!(err, Error::Device(_)), "{}", err);
+}
+
+#[tokio::test]
+async fn fs_roundtrip() {
+    let simulator = simulator();
+    let mut client = client(&simulator);
+    let data: Vec<u8> = (0..3000).map(|i| (i * 7) as u8).collect();
+    let local = std::env::temp_dir().join("smp-tool-simulator-put.bin");
+    let downloaded = std::env::temp_dir().join("smp-tool-simulator-get.bin");
+    std::fs::write(&local, &data).unwrap();
+
+    client
+        .fs_put(&local, "/lfs/data.bin", Some(512))
+        .await
+        .unwrap();
+    assert_eq!(
+        simulator.lock().unwrap().file("/lfs/data.bin"),
+        Some(&data[..])
+    );
+
+    client
+        .fs_get("/lfs/data.bin", &downloaded, false)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&downloaded).unwrap(), data);
+
+    std::fs::write(&local, b"123456789").unwrap();
+    client
+        .fs_put(&local, "/lfs/check.txt", Some(512))
+        .await
+        .unwrap();
+    let hash = client
+        .call(FileHashRequest {
+            name: "/lfs/check.txt".to_string(),
+            type_: Some("crc32".to_string()),
+            off: None,
+            len: None,
+        })
+        .await
+        .unwrap();
+    assert_eq!(hash.output, HashOutput::Checksum(0xcbf4_3926));
+}
+
+#[tokio::test]
+async fn os_group() {
+    let simulator = simulator();
+    let mut client = client(&simulator);
+
+    let echo = client
+        .call(EchoRequest {
+            d: "hello".to_string(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(echo.r, "hello");
+
+    let params = client.call(McumgrParamsRequest {}).await.unwrap();
+    assert_eq!((params.buf_size, params.buf_count), (2048, 4));
+
+    client
+        .call(SetDateTimeRequest {
+            datetime: "2030-01-02T03:04:05+01:00".to_string(),
+        })
+        .await
+        .unwrap();
+    let now = client.call(GetDateTimeRequest {}).await.unwrap();
+    assert!(
+        now.datetime.starts_with("2030-01-02T02:04:0"),
+        "{}",
+        now.datetime
+    );
+
+    let err = client
+        .call(SetDateTimeRequest {
+            datetime: "yesterday".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::Device(_)), "{}", err);
+}
+
+#[tokio::test]
+async fn custom_shell() {
+    let simulator = Simulator::new(SimulatorOptions::default()).with_shell(|argv: &[String]| {
+        (0, argv.iter().rev().cloned().collect::<Vec<_>>().join(" "))
+    });
+    let mut client = client(&Arc::new(Mutex::new(simulator)));
+
+    let output = client
+        .transceive(vec!["a".to_string(), "b".to_string()])
+        .await
+        .unwrap();
+
+    assert_eq!(output, "b a");
+}
+
+/// `smp-tool simulate` in the background, killed when dropped
+struct Simulate(Child);
+
+impl Simulate {
+    /// Start the simulator, returns it and the address or path it prints
+    fn start(args: &[&str]) -> (Self, String) {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_smp-tool"))
+            .arg("simulate")
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let endpoint = line.split_whitespace().last().unwrap().to_string();
+        (Self(child), endpoint)
+    }
+}
+
+impl Drop for Simulate {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn smp_tool(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_smp-tool"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{:?}: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn simulate_over_udp() {
+    let (_simulate, addr) = Simulate::start(&["--udp", "127.0.0.1:0"]);
+    let port = addr.rsplit(':').next().unwrap();
+    let udp = [
+        "-t",
+        "udp",
+        "-d",
+        "127.0.0.1",
+        "-p",
+        port,
+        "--timeout-ms",
+        "1000",
+    ];
+    let (file, hash) = firmware("cli", &build_image(version(4), &[0x11; 4000]));
+
+    let echo = smp_tool(&[&udp[..], &["os", "echo", "ping"]].concat());
+    assert_eq!(echo.trim(), "ping");
+
+    let file = file.to_str().unwrap();
+    smp_tool(&[&udp[..], &["app", "flash", file]].concat());
+    let info = smp_tool(&[&udp[..], &["app", "info"]].concat());
+    assert!(info.contains(&hex::encode(hash)), "{}", info);
+}
+
+#[tokio::test]
+async fn recorded_cli_session_replays() {
+    let (_simulate, addr) = Simulate::start(&["--udp", "127.0.0.1:0"]);
+    let port = addr.rsplit(':').next().unwrap();
+    let log = std::env::temp_dir().join("smp-tool-simulator-record.jsonl");
+    let record = log.to_str().unwrap();
+
+    smp_tool(&[
+        "-t",
+        "udp",
+        "-d",
+        "127.0.0.1",
+        "-p",
+        port,
+        "--record",
+        record,
+        "os",
+        "echo",
+        "ping",
+    ]);
+
+    let mut client = Client::from_transport(ReplayTransport::open(&log).unwrap());
+    let echo = client
+        .call(EchoRequest {
+            d: "ping".to_string(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(echo.r, "ping");
+}
+
+#[cfg(unix)]
+#[test]
+fn simulate_over_pty() {
+    let (_simulate, path) = Simulate::start(&["--pty"]);
+    let serial = ["-t", "serial", "-s", &path, "--timeout-ms", "1000"];
+
+    let output = smp_tool(&[&serial[..], &["shell", "exec", "echo", "over", "serial"]].concat());
+
+    assert!(output.contains("o: over serial"), "{}", output);
+}
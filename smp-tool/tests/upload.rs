@@ -0,0 +1,233 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mcumgr_smp::mcuboot_image::{ImageVersion, McubootImage};
+use smp_tool::client::{Client, FlashOptions};
+use smp_tool::simulator::{
+    build_image, Faults, LinkFaults, LinkStats, Simulator, SimulatorOptions, SimulatorTransport,
+};
+
+/// Requests of an upload before the first chunk: slot info and MCUmgr parameters
+const SETUP: usize = 2;
+
+fn simulator(options: SimulatorOptions, faults: Faults) -> Arc<Mutex<Simulator>> {
+    let options = SimulatorOptions {
+        reboot_time: Duration::from_millis(10),
+        ..options
+    };
+    Arc::new(Mutex::new(Simulator::new(options).with_faults(faults)))
+}
+
+fn link(simulator: &Arc<Mutex<Simulator>>, faults: LinkFaults) -> SimulatorTransport {
+    SimulatorTransport::new(simulator.clone()).with_faults(faults)
+}
+
+/// Client on `transport` and the statistics of its link
+fn connect(transport: SimulatorTransport) -> (Client, Arc<Mutex<LinkStats>>) {
+    let stats = transport.stats_handle();
+    (Client::from_transport(transport), stats)
+}
+
+/// A 3000 byte image, returns its path and hash
+fn firmware(name: &str) -> (PathBuf, Vec<u8>) {
+    let version = ImageVersion {
+        major: 1,
+        minor: 1,
+        ..Default::default()
+    };
+    let payload: Vec<u8> = (0..2448u32).map(|i| (i * 7 % 251) as u8).collect();
+    let image = build_image(version, &payload);
+    assert_eq!(image.len(), 3000);
+
+    let path = std::env::temp_dir().join(format!("smp-tool-upload-{}.bin", name));
+    std::fs::write(&path, &image).unwrap();
+    let hash = McubootImage::parse(&image)
+        .unwrap()
+        .hash()
+        .unwrap()
+        .to_vec();
+    (path, hash)
+}
+
+fn options() -> FlashOptions {
+    FlashOptions {
+        chunk_size: Some(256),
+        ..Default::default()
+    }
+}
+
+/// Hash of the image in the secondary slot, once it is complete and its hash matches
+async fn uploaded(simulator: &Arc<Mutex<Simulator>>) -> Option<Vec<u8>> {
+    let mut client = Client::from_transport(SimulatorTransport::new(simulator.clone()));
+    let state = client.get_img_state().await.unwrap();
+    state
+        .images
+        .iter()
+        .find(|image| image.slot == 1 && image.bootable)
+        .and_then(|image| image.hash.as_ref())
+        .map(|hash| hash.to_vec())
+}
+
+#[tokio::test]
+async fn lost_responses_are_resent() {
+    let (path, hash) = firmware("lost");
+    let simulator = simulator(SimulatorOptions::default(), Faults::default());
+    let faults = LinkFaults {
+        delayed: vec![SETUP, SETUP + 3, SETUP + 7],
+        ..Default::default()
+    };
+
+    let (mut client, _) = connect(link(&simulator, faults));
+    client.flash(&path, &options()).await.unwrap();
+
+    assert_eq!(uploaded(&simulator).await, Some(hash));
+}
+
+#[tokio::test]
+async fn resume_in_new_client() {
+    let (path, hash) = firmware("resume");
+    let simulator = simulator(SimulatorOptions::default(), Faults::default());
+    let faults = LinkFaults {
+        disconnect_after: Some(SETUP + 5),
+        ..Default::default()
+    };
+
+    let (mut client, _) = connect(link(&simulator, faults));
+    assert!(client.flash(&path, &options()).await.is_err());
+    assert_eq!(uploaded(&simulator).await, None);
+
+    let (mut client, stats) = connect(link(&simulator, LinkFaults::default()));
+    let options = FlashOptions {
+        resume: true,
+        ..options()
+    };
+    client.flash(&path, &options).await.unwrap();
+
+    assert_eq!(uploaded(&simulator).await, Some(hash));
+    // one probe and the remaining chunks
+    assert_eq!(stats.lock().unwrap().requests, SETUP + 1 + 7);
+}
+
+#[tokio::test]
+async fn reboot_restarts_upload() {
+    let (path, hash) = firmware("reboot");
+    let faults = Faults {
+        reset_at: Some(SETUP + 6),
+        ..Default::default()
+    };
+    let simulator = simulator(SimulatorOptions::default(), faults);
+
+    let (mut client, stats) = connect(link(&simulator, LinkFaults::default()));
+    client.flash(&path, &options()).await.unwrap();
+
+    assert_eq!(uploaded(&simulator).await, Some(hash));
+    // the chunks before the reset are sent again
+    assert!(stats.lock().unwrap().requests > SETUP + 6 + 12);
+}
+
+#[tokio::test]
+async fn window_keeps_chunks_in_flight() {
+    let (path, hash) = firmware("window");
+    let simulator = simulator(SimulatorOptions::default(), Faults::default());
+
+    let (mut client, stats) = connect(link(&simulator, LinkFaults::default()));
+    let options = FlashOptions {
+        window: 4,
+        ..options()
+    };
+    client.flash(&path, &options).await.unwrap();
+
+    assert_eq!(uploaded(&simulator).await, Some(hash));
+    let stats = *stats.lock().unwrap();
+    assert_eq!(stats.max_pending, 4);
+    // no chunk was sent twice
+    assert_eq!(stats.requests, SETUP + 12);
+}
+
+#[tokio::test]
+async fn window_recovers_from_lost_chunks() {
+    let (path, hash) = firmware("window-lost");
+    let simulator = simulator(SimulatorOptions::default(), Faults::default());
+    let faults = LinkFaults {
+        lost: vec![SETUP + 2, SETUP + 9],
+        delayed: vec![SETUP + 5],
+        ..Default::default()
+    };
+
+    let (mut client, _) = connect(link(&simulator, faults));
+    let options = FlashOptions {
+        window: 4,
+        ..options()
+    };
+    client.flash(&path, &options).await.unwrap();
+
+    assert_eq!(uploaded(&simulator).await, Some(hash));
+}
+
+#[tokio::test]
+async fn chunks_fill_device_buffer() {
+    let (path, hash) = firmware("buffer");
+    let device = SimulatorOptions {
+        buf_size: 300,
+        ..Default::default()
+    };
+    let simulator = simulator(device, Faults::default());
+
+    let (mut client, stats) = connect(link(&simulator, LinkFaults::default()).with_mtu(1024));
+    let options = FlashOptions {
+        chunk_size: None,
+        ..options()
+    };
+    client.flash(&path, &options).await.unwrap();
+
+    assert_eq!(uploaded(&simulator).await, Some(hash));
+    let stats = *stats.lock().unwrap();
+    assert!(stats.max_request <= 300 && stats.max_request > 290);
+    // the first chunk carries the hash and length, the others about 275 bytes of data
+    assert_eq!(stats.requests, SETUP + 12);
+}
+
+#[tokio::test]
+async fn chunks_fill_transport_mtu() {
+    let (path, hash) = firmware("mtu");
+    let simulator = simulator(SimulatorOptions::default(), Faults::default());
+
+    let (mut client, stats) = connect(link(&simulator, LinkFaults::default()).with_mtu(1024));
+    let options = FlashOptions {
+        chunk_size: None,
+        ..options()
+    };
+    client.flash(&path, &options).await.unwrap();
+
+    assert_eq!(uploaded(&simulator).await, Some(hash));
+    let stats = *stats.lock().unwrap();
+    assert!(stats.max_request <= 1024 && stats.max_request > 1000);
+    assert_eq!(stats.requests, SETUP + 4);
+}
+
+#[tokio::test]
+async fn image_larger_than_slot_is_refused() {
+    let (path, hash) = firmware("slot");
+    // the MCUboot trailer takes 4 KiB of the slot
+    let small = SimulatorOptions {
+        slot_size: 0x1000 + 2048,
+        ..Default::default()
+    };
+    let too_small = simulator(small, Faults::default());
+
+    let (mut client, stats) = connect(link(&too_small, LinkFaults::default()));
+    let err = client.flash(&path, &options()).await.unwrap_err();
+    assert!(err.to_string().contains("does not fit"));
+    // only the slot info was requested
+    assert_eq!(stats.lock().unwrap().requests, 1);
+
+    let exact = SimulatorOptions {
+        slot_size: 0x1000 + 3000,
+        ..Default::default()
+    };
+    let simulator = simulator(exact, Faults::default());
+    let (mut client, _) = connect(link(&simulator, LinkFaults::default()));
+    client.flash(&path, &options()).await.unwrap();
+    assert_eq!(uploaded(&simulator).await, Some(hash));
+}